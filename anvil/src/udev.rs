@@ -286,7 +286,7 @@ pub fn run_udev() {
                     backend.drm.pause();
                 }
             }
-            SessionEvent::ActivateSession => {
+            SessionEvent::ActivateSession { .. } => {
                 info!("resuming session");
 
                 if let Err(err) = libinput_context.resume() {
@@ -36,6 +36,17 @@
 //! assert_eq!(get_depth(Fourcc::Argb8888), Some(32));
 //! assert_eq!(get_depth(Fourcc::Xrgb8888), Some(24));
 //! ```
+//!
+//! [`buffer_size`] returns the number of bytes required for a buffer of a format with a given size.
+//!
+//! ```
+//! # use smithay::backend::allocator::{Format, Fourcc, Modifier};
+//! # use smithay::backend::allocator::format::buffer_size;
+//! let format = Format { code: Fourcc::Argb8888, modifier: Modifier::Linear };
+//! assert_eq!(buffer_size(format, 10, 10, 64), Some(64 * 10));
+//! ```
+
+use super::{Format, Modifier};
 
 /// Macro to generate table lookup functions for formats.
 ///
@@ -363,9 +374,28 @@ format_tables! {
     // TODO: YUV and other formats
 }
 
+/// Returns the number of bytes required for a buffer of the given format and size,
+/// with every row aligned to `stride_align` bytes.
+///
+/// Only the layout of linear single-plane formats is known, so any other modifier
+/// or formats unknown to [`get_bpp`] will return [`None`].
+/// [`None`] is also returned, if the size does not fit into a `usize`.
+pub fn buffer_size(format: Format, width: u32, height: u32, stride_align: u32) -> Option<usize> {
+    if format.modifier != Modifier::Linear {
+        return None;
+    }
+
+    let bpp = get_bpp(format.code)?;
+    let row = (width as usize).checked_mul(bpp)?.checked_add(7)? / 8;
+    let align = (stride_align as usize).max(1);
+    let stride = row.checked_add(align - 1)? / align * align;
+    stride.checked_mul(height as usize)
+}
+
 #[cfg(test)]
 mod tests {
-    use super::{_impl_formats, get_bpp, get_depth, get_opaque, get_transparent, has_alpha};
+    use super::{_impl_formats, buffer_size, get_bpp, get_depth, get_opaque, get_transparent, has_alpha};
+    use crate::backend::allocator::{Format, Fourcc, Modifier};
 
     /// Tests that opaque alternatives are not the same as the variant with alpha.
     #[test]
@@ -478,4 +508,35 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn buffer_size_stride_alignment() {
+        let format = Format {
+            code: Fourcc::Xrgb8888,
+            modifier: Modifier::Linear,
+        };
+        assert_eq!(buffer_size(format, 64, 64, 0), Some(64 * 4 * 64));
+        assert_eq!(buffer_size(format, 10, 2, 256), Some(256 * 2));
+
+        let format = Format {
+            code: Fourcc::Rgb565,
+            modifier: Modifier::Linear,
+        };
+        assert_eq!(buffer_size(format, 3, 1, 4), Some(8));
+    }
+
+    #[test]
+    fn buffer_size_unknown_layout() {
+        let format = Format {
+            code: Fourcc::Argb8888,
+            modifier: Modifier::Invalid,
+        };
+        assert_eq!(buffer_size(format, 64, 64, 0), None);
+
+        let format = Format {
+            code: Fourcc::Nv12,
+            modifier: Modifier::Linear,
+        };
+        assert_eq!(buffer_size(format, 64, 64, 0), None);
+    }
 }
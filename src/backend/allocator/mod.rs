@@ -14,6 +14,7 @@
 //!
 //! Helpers:
 //! - [`Swapchain`] to help with buffer management for framebuffers
+//! - [`serde`] helpers to (de)serialize formats, if the `serde` feature is enabled
 
 pub mod dmabuf;
 #[cfg(feature = "backend_drm")]
@@ -21,6 +22,8 @@ pub mod dumb;
 pub mod format;
 #[cfg(feature = "backend_gbm")]
 pub mod gbm;
+#[cfg(feature = "serde")]
+pub mod serde;
 #[cfg(feature = "backend_vulkan")]
 pub mod vulkan;
 
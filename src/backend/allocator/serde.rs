@@ -0,0 +1,169 @@
+//! Serialization helpers for [`Format`], [`Fourcc`] and [`Modifier`].
+//!
+//! The format types are re-exported from the `drm-fourcc` crate, so they cannot implement
+//! the `serde` traits from within smithay. Instead this module provides functions to be used
+//! with serde's `with`-attribute, which (de)serialize the types with a stable representation:
+//!
+//! - a [`Fourcc`] is represented by its four character code (e.g. `"AR24"`),
+//! - a [`Modifier`] is represented by its `u64` value,
+//! - a [`Format`] is represented by a struct with a `code` and a `modifier` field using the above representations.
+//!
+//! ```no_run
+//! # use smithay::backend::allocator::{Format, Fourcc, Modifier};
+//! #[derive(serde::Serialize, serde::Deserialize)]
+//! struct CachedScanout {
+//!     #[serde(with = "smithay::backend::allocator::serde::format")]
+//!     format: Format,
+//!     #[serde(with = "smithay::backend::allocator::serde::fourcc")]
+//!     cursor: Fourcc,
+//! }
+//! ```
+//!
+//! This module is only available with the `serde` feature enabled.
+
+use std::{convert::TryFrom, fmt};
+
+use ::serde::{
+    de::{self, Deserializer, MapAccess, Visitor},
+    ser::{SerializeStruct, Serializer},
+    Deserialize,
+};
+
+use super::{Format, Fourcc, Modifier};
+
+/// (De)serialize a [`Fourcc`] as its four character code
+pub mod fourcc {
+    use super::*;
+
+    /// Serialize a [`Fourcc`] as its four character code
+    pub fn serialize<S: Serializer>(fourcc: &Fourcc, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&fourcc_to_code(*fourcc))
+    }
+
+    /// Deserialize a [`Fourcc`] from its four character code
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Fourcc, D::Error> {
+        deserializer.deserialize_str(FourccVisitor)
+    }
+}
+
+/// (De)serialize a [`Modifier`] as its `u64` value
+pub mod modifier {
+    use super::*;
+
+    /// Serialize a [`Modifier`] as its `u64` value
+    pub fn serialize<S: Serializer>(modifier: &Modifier, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(u64::from(*modifier))
+    }
+
+    /// Deserialize a [`Modifier`] from its `u64` value
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Modifier, D::Error> {
+        u64::deserialize(deserializer).map(Modifier::from)
+    }
+}
+
+/// (De)serialize a [`Format`] as a struct of its four character code and `u64` modifier value
+pub mod format {
+    use super::*;
+
+    /// Serialize a [`Format`] as a struct of its four character code and `u64` modifier value
+    pub fn serialize<S: Serializer>(format: &Format, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("Format", 2)?;
+        state.serialize_field("code", &fourcc_to_code(format.code))?;
+        state.serialize_field("modifier", &u64::from(format.modifier))?;
+        state.end()
+    }
+
+    /// Deserialize a [`Format`] from a struct of its four character code and `u64` modifier value
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Format, D::Error> {
+        deserializer.deserialize_struct("Format", FORMAT_FIELDS, FormatVisitor)
+    }
+}
+
+const FORMAT_FIELDS: &[&str] = &["code", "modifier"];
+
+fn fourcc_to_code(fourcc: Fourcc) -> String {
+    (fourcc as u32).to_le_bytes().iter().map(|b| *b as char).collect()
+}
+
+fn fourcc_from_code(code: &str) -> Option<Fourcc> {
+    let bytes = <[u8; 4]>::try_from(code.as_bytes()).ok()?;
+    Fourcc::try_from(u32::from_le_bytes(bytes)).ok()
+}
+
+struct FourccVisitor;
+
+impl<'de> Visitor<'de> for FourccVisitor {
+    type Value = Fourcc;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter.write_str("a known four character code")
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Fourcc, E> {
+        fourcc_from_code(value).ok_or_else(|| E::invalid_value(de::Unexpected::Str(value), &self))
+    }
+}
+
+struct FourccSeed(Fourcc);
+
+impl<'de> Deserialize<'de> for FourccSeed {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        fourcc::deserialize(deserializer).map(FourccSeed)
+    }
+}
+
+struct FormatVisitor;
+
+impl<'de> Visitor<'de> for FormatVisitor {
+    type Value = Format;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter.write_str("struct Format")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Format, A::Error> {
+        let mut code = None;
+        let mut modifier = None;
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "code" => {
+                    if code.is_some() {
+                        return Err(de::Error::duplicate_field("code"));
+                    }
+                    code = Some(map.next_value::<FourccSeed>()?.0);
+                }
+                "modifier" => {
+                    if modifier.is_some() {
+                        return Err(de::Error::duplicate_field("modifier"));
+                    }
+                    modifier = Some(Modifier::from(map.next_value::<u64>()?));
+                }
+                other => return Err(de::Error::unknown_field(other, FORMAT_FIELDS)),
+            }
+        }
+
+        Ok(Format {
+            code: code.ok_or_else(|| de::Error::missing_field("code"))?,
+            modifier: modifier.ok_or_else(|| de::Error::missing_field("modifier"))?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{fourcc_from_code, fourcc_to_code};
+    use crate::backend::allocator::Fourcc;
+
+    #[test]
+    fn fourcc_code_roundtrip() {
+        assert_eq!(fourcc_to_code(Fourcc::Argb8888), "AR24");
+        assert_eq!(fourcc_from_code("AR24"), Some(Fourcc::Argb8888));
+        assert_eq!(fourcc_from_code(&fourcc_to_code(Fourcc::R8)), Some(Fourcc::R8));
+    }
+
+    #[test]
+    fn fourcc_code_invalid() {
+        assert_eq!(fourcc_from_code("AR2"), None);
+        assert_eq!(fourcc_from_code("????"), None);
+    }
+}
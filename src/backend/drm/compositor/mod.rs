@@ -506,6 +506,9 @@ impl<B: AsRef<framebuffer::Handle>> FrameState<B> {
                         alpha: config.alpha,
                         damage_clips: config.damage_clips.as_ref().map(|d| d.blob()),
                         fb: *config.buffer.as_ref(),
+                        blend_mode: None,
+                        scaling_filter: None,
+                        skip_implicit_sync: false,
                     }),
                 }),
             allow_modeset,
@@ -534,10 +537,14 @@ impl<B: AsRef<framebuffer::Handle>> FrameState<B> {
                         transform: config.transform,
                         damage_clips: config.damage_clips.as_ref().map(|d| d.blob()),
                         fb: *config.buffer.as_ref(),
+                        blend_mode: None,
+                        scaling_filter: None,
+                        skip_implicit_sync: false,
                     }),
                 }),
             event,
-        )
+        )?;
+        Ok(())
     }
 
     fn page_flip(&self, surface: &DrmSurface, event: bool) -> Result<(), crate::backend::drm::error::Error> {
@@ -555,6 +562,9 @@ impl<B: AsRef<framebuffer::Handle>> FrameState<B> {
                         transform: config.transform,
                         damage_clips: config.damage_clips.as_ref().map(|d| d.blob()),
                         fb: *config.buffer.as_ref(),
+                        blend_mode: None,
+                        scaling_filter: None,
+                        skip_implicit_sync: false,
                     }),
                 }),
             event,
@@ -1966,10 +1976,11 @@ where
                                 )
                             }));
                             output_damage.extend(render_damage.clone());
-                            config.damage_clips = PlaneDamageClips::from_damage(
+                            config.damage_clips = PlaneDamageClips::from_crtc_damage(
                                 self.surface.device_fd(),
                                 config.src,
                                 config.dst,
+                                current_size,
                                 render_damage,
                             )
                             .ok()
@@ -2967,9 +2978,15 @@ where
         let damage_clips = if element_damage.is_empty() {
             None
         } else {
-            PlaneDamageClips::from_damage(self.surface.device_fd(), src, element_geometry, element_damage)
-                .ok()
-                .flatten()
+            PlaneDamageClips::from_crtc_damage(
+                self.surface.device_fd(),
+                src,
+                element_geometry,
+                output_geometry.size,
+                element_output_damage.iter().copied(),
+            )
+            .ok()
+            .flatten()
         };
 
         let plane_state = PlaneState {
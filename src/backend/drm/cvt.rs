@@ -0,0 +1,149 @@
+//! Generation of mode timings following the VESA Coordinated Video Timings (CVT) standard
+
+use drm::control::Mode;
+
+// common constants
+const CVT_H_GRANULARITY: u32 = 8;
+const CVT_MIN_V_PORCH: u32 = 3;
+const CVT_MIN_V_BPORCH: u32 = 6;
+const CVT_CLOCK_STEP: u32 = 250;
+
+// constants for normal blanking
+const CVT_MIN_VSYNC_BP: f64 = 550.0;
+const CVT_HSYNC_PERCENTAGE: u32 = 8;
+const CVT_M_PRIME: f64 = 300.0;
+const CVT_C_PRIME: f64 = 30.0;
+
+// constants for reduced blanking
+const CVT_RB_MIN_VBLANK: f64 = 460.0;
+const CVT_RB_H_SYNC: u32 = 32;
+const CVT_RB_H_BLANK: u32 = 160;
+const CVT_RB_VFPORCH: u32 = 3;
+
+/// Generates a [`Mode`] with timings following the VESA CVT 1.1 standard
+///
+/// This can be used to drive displays at resolutions or refresh rates not advertised in their EDID.
+/// `refresh` is the desired vertical refresh rate in Hz. `reduced_blanking` selects the reduced blanking
+/// variant intended for digital displays, which needs a lower pixel clock. The width is rounded down
+/// to a multiple of 8 as required by the standard.
+///
+/// There is no guarantee the display supports the generated mode. Use [`DrmSurface::use_mode`](super::DrmSurface::use_mode)
+/// to check if the device accepts it.
+pub fn cvt_mode(width: u16, height: u16, refresh: f64, reduced_blanking: bool) -> Mode {
+    let hdisplay = width as u32 - width as u32 % CVT_H_GRANULARITY;
+    let vdisplay = height as u32;
+
+    // the vsync width encodes the aspect ratio
+    let vsync = if vdisplay % 3 == 0 && vdisplay * 4 / 3 == hdisplay {
+        4
+    } else if vdisplay % 9 == 0 && vdisplay * 16 / 9 == hdisplay {
+        5
+    } else if vdisplay % 10 == 0 && vdisplay * 16 / 10 == hdisplay {
+        6
+    } else if (vdisplay % 4 == 0 && vdisplay * 5 / 4 == hdisplay)
+        || (vdisplay % 9 == 0 && vdisplay * 15 / 9 == hdisplay)
+    {
+        7
+    } else {
+        10
+    };
+
+    let (htotal, hsync_start, hsync_end, vtotal, vsync_start, hfperiod, flags) = if !reduced_blanking {
+        // horizontal period estimate in us
+        let hfperiod =
+            (1_000_000.0 / refresh - CVT_MIN_VSYNC_BP) / (vdisplay as f64 + CVT_MIN_V_PORCH as f64);
+        let vsync_and_back_porch = ((CVT_MIN_VSYNC_BP / hfperiod) as u32 + 1).max(vsync + CVT_MIN_V_BPORCH);
+        let vtotal = vdisplay + vsync_and_back_porch + CVT_MIN_V_PORCH;
+
+        let hblank_percentage = (CVT_C_PRIME - CVT_M_PRIME * hfperiod / 1000.0).max(20.0);
+        let mut hblank = (hdisplay as f64 * hblank_percentage / (100.0 - hblank_percentage)) as u32;
+        hblank -= hblank % (2 * CVT_H_GRANULARITY);
+        let htotal = hdisplay + hblank;
+
+        let hsync_end = hdisplay + hblank / 2;
+        let mut hsync_width = htotal * CVT_HSYNC_PERCENTAGE / 100;
+        hsync_width -= hsync_width % CVT_H_GRANULARITY;
+
+        (
+            htotal,
+            hsync_end - hsync_width,
+            hsync_end,
+            vtotal,
+            vdisplay + CVT_MIN_V_PORCH,
+            hfperiod,
+            drm_ffi::DRM_MODE_FLAG_NHSYNC | drm_ffi::DRM_MODE_FLAG_PVSYNC,
+        )
+    } else {
+        let hfperiod = (1_000_000.0 / refresh - CVT_RB_MIN_VBLANK) / vdisplay as f64;
+        let vbi_lines =
+            ((CVT_RB_MIN_VBLANK / hfperiod) as u32 + 1).max(CVT_RB_VFPORCH + vsync + CVT_MIN_V_BPORCH);
+        let hsync_end = hdisplay + CVT_RB_H_BLANK / 2;
+
+        (
+            hdisplay + CVT_RB_H_BLANK,
+            hsync_end - CVT_RB_H_SYNC,
+            hsync_end,
+            vdisplay + vbi_lines,
+            vdisplay + CVT_RB_VFPORCH,
+            hfperiod,
+            drm_ffi::DRM_MODE_FLAG_PHSYNC | drm_ffi::DRM_MODE_FLAG_NVSYNC,
+        )
+    };
+
+    // pixel clock in kHz
+    let mut clock = (htotal as f64 * 1000.0 / hfperiod) as u32;
+    clock -= clock % CVT_CLOCK_STEP;
+
+    let mut name = [0; 32];
+    for (dst, src) in name
+        .iter_mut()
+        .zip(format!("{}x{}", hdisplay, vdisplay).bytes())
+        .take(31)
+    {
+        *dst = src as _;
+    }
+
+    Mode::from(drm_ffi::drm_mode_modeinfo {
+        clock,
+        hdisplay: hdisplay as u16,
+        hsync_start: hsync_start as u16,
+        hsync_end: hsync_end as u16,
+        htotal: htotal as u16,
+        hskew: 0,
+        vdisplay: vdisplay as u16,
+        vsync_start: vsync_start as u16,
+        vsync_end: (vsync_start + vsync) as u16,
+        vtotal: vtotal as u16,
+        vscan: 0,
+        vrefresh: ((clock as u64 * 1000 + (htotal * vtotal) as u64 / 2) / (htotal * vtotal) as u64) as u32,
+        flags,
+        type_: drm_ffi::DRM_MODE_TYPE_USERDEF,
+        name,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::cvt_mode;
+
+    #[test]
+    fn cvt_1080p() {
+        // reference values generated by `cvt 1920 1080 60`
+        let mode = cvt_mode(1920, 1080, 60.0, false);
+        assert_eq!(mode.clock(), 173_000);
+        assert_eq!(mode.size(), (1920, 1080));
+        assert_eq!(mode.hsync(), (2048, 2248, 2576));
+        assert_eq!(mode.vsync(), (1083, 1088, 1120));
+        assert_eq!(mode.vrefresh(), 60);
+        assert_eq!(mode.name().to_str(), Ok("1920x1080"));
+    }
+
+    #[test]
+    fn cvt_1080p_reduced_blanking() {
+        // reference values generated by `cvt -r 1920 1080 60`
+        let mode = cvt_mode(1920, 1080, 60.0, true);
+        assert_eq!(mode.clock(), 138_500);
+        assert_eq!(mode.hsync(), (1968, 2000, 2080));
+        assert_eq!(mode.vsync(), (1083, 1088, 1111));
+    }
+}
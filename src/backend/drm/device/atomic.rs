@@ -185,6 +185,57 @@ impl AtomicDrmDevice {
     }
 }
 
+impl AtomicDrmDevice {
+    // adds the state captured on creation of the given crtc, the connectors and the planes attached to it
+    pub(crate) fn add_previous_crtc_state(&self, req: &mut AtomicModeReq, crtc: crtc::Handle) {
+        fn add_props<T: ResourceHandle>(req: &mut AtomicModeReq, handle: T, set: &PropertyValueSet) {
+            let (prop_handles, values) = set.as_props_and_values();
+            for (&prop_handle, &val) in prop_handles.iter().zip(values.iter()) {
+                req.add_raw_property(handle.into(), prop_handle, val);
+            }
+        }
+        // whether the `CRTC_ID` property of the object referenced the crtc
+        let attached = |mapping: Option<&HashMap<String, property::Handle>>, set: &PropertyValueSet| {
+            let Some(crtc_prop) = mapping.and_then(|mapping| mapping.get("CRTC_ID")) else {
+                return false;
+            };
+            let (prop_handles, values) = set.as_props_and_values();
+            prop_handles
+                .iter()
+                .zip(values.iter())
+                .any(|(prop, value)| prop == crtc_prop && *value == u32::from(crtc) as u64)
+        };
+
+        for (handle, set) in self.old_state.0.iter() {
+            if attached(self.prop_mapping.0.get(handle), set) {
+                add_props(req, *handle, set);
+            }
+        }
+        for (handle, set) in self.old_state.1.iter().filter(|(handle, _)| *handle == crtc) {
+            add_props(req, *handle, set);
+        }
+        for (handle, set) in self.old_state.3.iter() {
+            if attached(self.prop_mapping.2.get(handle), set) {
+                add_props(req, *handle, set);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+impl AtomicDrmDevice {
+    // Device without any saved state, so surfaces can build requests without hardware
+    pub(crate) fn dummy(fd: DrmDeviceFd, active: Arc<AtomicBool>) -> Self {
+        AtomicDrmDevice {
+            fd,
+            active,
+            old_state: (Vec::new(), Vec::new(), Vec::new(), Vec::new()),
+            prop_mapping: (HashMap::new(), HashMap::new(), HashMap::new()),
+            span: info_span!("drm_atomic"),
+        }
+    }
+}
+
 impl Drop for AtomicDrmDevice {
     fn drop(&mut self) {
         if self.active.load(Ordering::SeqCst) {
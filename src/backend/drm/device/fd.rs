@@ -1,16 +1,44 @@
-use drm::{control::Device as ControlDevice, Device as BasicDevice};
+use drm::{
+    control::{connector, crtc, Device as ControlDevice},
+    ClientCapability, Device as BasicDevice, DriverCapability,
+};
 use std::{
+    collections::{HashMap, HashSet},
     os::unix::io::{AsFd, AsRawFd, BorrowedFd, RawFd},
-    sync::Arc,
+    sync::{Arc, Mutex},
 };
 use tracing::{error, info, warn};
 
+use crate::backend::drm::{error::Error, DrmNode, NodeType, WeakDrmSurface};
 use crate::utils::{DevPath, DeviceFd};
 
 #[derive(Debug)]
 struct InternalDrmDeviceFd {
     fd: DeviceFd,
     privileged: bool,
+    driver_capabilities: Mutex<HashMap<u64, u64>>,
+    master_guards: Mutex<MasterGuards>,
+}
+
+// Bookkeeping of the live `DrmMaster` guards of a device
+#[derive(Debug, Default)]
+struct MasterGuards {
+    count: usize,
+    // whether a guard acquired the lock, which then needs to be released by the last guard
+    acquired: bool,
+}
+
+impl MasterGuards {
+    fn add(&mut self, acquired: bool) {
+        self.count += 1;
+        self.acquired |= acquired;
+    }
+
+    // returns whether the lock needs to be released
+    fn remove(&mut self) -> bool {
+        self.count -= 1;
+        self.count == 0 && std::mem::take(&mut self.acquired)
+    }
 }
 
 impl Drop for InternalDrmDeviceFd {
@@ -63,6 +91,8 @@ impl DrmDeviceFd {
         let mut dev = InternalDrmDeviceFd {
             fd,
             privileged: false,
+            driver_capabilities: Mutex::new(HashMap::new()),
+            master_guards: Mutex::new(MasterGuards::default()),
         };
 
         // We want to modeset, so we better be the master, if we run via a tty session.
@@ -81,6 +111,66 @@ impl DrmDeviceFd {
         self.0.privileged
     }
 
+    /// Acquires the drm master lock for this device.
+    ///
+    /// Becoming master again is required e.g. after a VT switch, before any modeset succeeds.
+    ///
+    /// The guards are reference counted: If the device was not master before, the lock is released
+    /// once the last [`DrmMaster`] guard is dropped. If the device already was master,
+    /// e.g. because it acquired the lock on creation, dropping the guards keeps it.
+    pub fn acquire_master(&self) -> Result<DrmMaster, Error> {
+        let mut guards = self.0.master_guards.lock().unwrap();
+        let acquired = !self.is_master();
+        if acquired {
+            self.acquire_master_lock().map_err(|source| Error::Access {
+                errmsg: "Failed to acquire drm master",
+                dev: self.dev_path(),
+                source,
+            })?;
+        }
+        guards.add(acquired);
+        Ok(DrmMaster { fd: self.clone() })
+    }
+
+    /// Returns if this file descriptor is currently the drm master of the device.
+    pub fn is_master(&self) -> bool {
+        // authenticating the invalid token 0 fails with `EINVAL` for masters
+        // and with `EACCES` for everyone else.
+        !matches!(
+            drm_ffi::auth::auth_magic_token(self.as_raw_fd(), 0),
+            Err(drm::SystemError::PermissionDenied)
+        )
+    }
+
+    /// Returns if the driver supports atomic modesetting for this device
+    ///
+    /// This can be used to decide on a modesetting strategy before creating a [`DrmDevice`](super::DrmDevice).
+    ///
+    /// *Note*: Probing this sets the atomic client capability on the file descriptor,
+    /// just like creating a [`DrmDevice`](super::DrmDevice) would.
+    pub fn supports_atomic(&self) -> bool {
+        self.set_client_capability(ClientCapability::Atomic, true).is_ok()
+    }
+
+    /// Returns the value of a driver capability of the device
+    ///
+    /// Driver capabilities do not change during the lifetime of a device,
+    /// so the result is cached and shared between all clones of this `DrmDeviceFd`.
+    pub fn cached_driver_capability(&self, cap: DriverCapability) -> Result<u64, Error> {
+        let mut capabilities = self.0.driver_capabilities.lock().unwrap();
+        if let Some(value) = capabilities.get(&(cap as u64)) {
+            return Ok(*value);
+        }
+
+        let value = self.get_driver_capability(cap).map_err(|source| Error::Access {
+            errmsg: "Failed to query driver capability",
+            dev: self.dev_path(),
+            source,
+        })?;
+        capabilities.insert(cap as u64, value);
+        Ok(value)
+    }
+
     /// Returns the underlying `DeviceFd`
     pub fn device_fd(&self) -> DeviceFd {
         self.0.fd.clone()
@@ -90,7 +180,300 @@ impl DrmDeviceFd {
     pub fn dev_id(&self) -> Result<nix::libc::dev_t, nix::Error> {
         Ok(nix::sys::stat::fstat(self.0.fd.as_raw_fd())?.st_rdev)
     }
+
+    /// Returns information about the bus the underlying device is connected to
+    ///
+    /// This can be used to correlate the device with devices of other apis,
+    /// e.g. via the pci address of a gpu in multi-gpu setups.
+    ///
+    /// *Note*: This is currently only supported on linux. Unlike libdrm's `drmGetDevice` this
+    /// does not query the kernel, but parses the sysfs entry of the device instead. Pci and usb
+    /// addresses are parsed fully, devices on other buses are best-effort and only reported by
+    /// their sysfs name, see [`BusInfo::Platform`] and [`BusInfo::Other`].
+    pub fn bus_info(&self) -> Result<BusInfo, Error> {
+        let dev_id = self.dev_id().map_err(Error::UnableToGetDeviceId)?;
+        bus_info(dev_id).map_err(Error::UnableToGetBusInfo)
+    }
+
+    /// Returns the type of the underlying drm node
+    ///
+    /// Only primary nodes allow modesetting, render nodes can just be used for rendering
+    /// and buffer allocation. Check this before creating a [`DrmDevice`](super::DrmDevice)
+    /// to avoid running into permission errors on a render node.
+    pub fn node_type(&self) -> Result<NodeType, Error> {
+        let dev_id = self.dev_id().map_err(Error::UnableToGetDeviceId)?;
+        DrmNode::from_dev_id(dev_id)
+            .map(|node| node.ty())
+            .map_err(Error::UnableToGetNodeType)
+    }
+
+    /// Returns the state of every connector of the device together with the crtc and surface driving it
+    ///
+    /// `surfaces` are the surfaces created for this device, see [`DrmSurface::weak`](crate::backend::drm::DrmSurface::weak).
+    /// They need to be passed in, as the [`DrmDeviceFd`] does not keep track of the surfaces created for it.
+    /// Surfaces, that were already dropped, are ignored.
+    /// The crtc reflects the state of the kernel, while the surface also takes connectors into account,
+    /// that are pending on a surface and only used after its next commit.
+    pub fn connector_map(&self, surfaces: &[WeakDrmSurface]) -> Result<Vec<ConnectorStatus>, Error> {
+        // dropped surfaces keep their slot, so the indices match `surfaces`
+        let connectors = surfaces
+            .iter()
+            .map(|surface| Some((surface.current_connectors()?, surface.pending_connectors()?)))
+            .collect::<Vec<_>>();
+
+        let res_handles = self.resource_handles().map_err(|source| Error::Access {
+            errmsg: "Error loading drm resources",
+            dev: self.dev_path(),
+            source,
+        })?;
+        res_handles
+            .connectors()
+            .iter()
+            .map(|&connector| {
+                let info = self
+                    .get_connector(connector, false)
+                    .map_err(|source| Error::Access {
+                        errmsg: "Error loading connector info",
+                        dev: self.dev_path(),
+                        source,
+                    })?;
+                let crtc = match info.current_encoder() {
+                    Some(enc) => self
+                        .get_encoder(enc)
+                        .map_err(|source| Error::Access {
+                            errmsg: "Error loading encoder info",
+                            dev: self.dev_path(),
+                            source,
+                        })?
+                        .crtc(),
+                    None => None,
+                };
+                let (surface, pending) = surface_assignment(connector, &connectors);
+                Ok(ConnectorStatus {
+                    connector,
+                    interface: info.interface(),
+                    interface_id: info.interface_id(),
+                    state: info.state(),
+                    crtc,
+                    surface: surface.map(|index| surfaces[index].clone()),
+                    pending,
+                })
+            })
+            .collect()
+    }
+}
+
+// Returns the index of the surface using `connector` after the next commit
+// and whether that differs from the surface currently using it.
+// `connectors` are the current and pending connectors of each surface, `None` for dropped surfaces.
+fn surface_assignment(
+    connector: connector::Handle,
+    connectors: &[Option<(HashSet<connector::Handle>, HashSet<connector::Handle>)>],
+) -> (Option<usize>, bool) {
+    let current = connectors.iter().position(|connectors| {
+        connectors
+            .as_ref()
+            .map_or(false, |(current, _)| current.contains(&connector))
+    });
+    let pending = connectors.iter().position(|connectors| {
+        connectors
+            .as_ref()
+            .map_or(false, |(_, pending)| pending.contains(&connector))
+    });
+    (pending, current != pending)
+}
+
+/// State of a connector, see [`DrmDeviceFd::connector_map`]
+#[derive(Debug, Clone)]
+pub struct ConnectorStatus {
+    /// Handle of the connector
+    pub connector: connector::Handle,
+    /// Interface of the connector, e.g. HDMI
+    pub interface: connector::Interface,
+    /// Index of the connector among the connectors with the same interface
+    pub interface_id: u32,
+    /// Whether a display is connected
+    pub state: connector::State,
+    /// Crtc currently driving the connector, if any
+    pub crtc: Option<crtc::Handle>,
+    /// Surface driving the connector after its next commit, if any
+    pub surface: Option<WeakDrmSurface>,
+    /// Whether the connector was added to or removed from a surface, which was not committed yet
+    pub pending: bool,
+}
+
+/// Bus information of a drm device, see [`DrmDeviceFd::bus_info`]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum BusInfo {
+    /// Pci device
+    Pci {
+        /// Pci domain
+        domain: u16,
+        /// Pci bus
+        bus: u8,
+        /// Pci device
+        device: u8,
+        /// Pci function
+        function: u8,
+    },
+    /// Usb device
+    Usb {
+        /// Usb bus number
+        bus: u8,
+        /// Usb device number
+        device: u8,
+    },
+    /// Platform device
+    Platform {
+        /// Name of the device, e.g. `fd4a0000.gpu`
+        name: String,
+    },
+    /// Device on any other bus
+    Other {
+        /// Name of the bus, e.g. `host1x`
+        subsystem: String,
+        /// Name of the device
+        name: String,
+    },
+}
+
+#[cfg(target_os = "linux")]
+fn bus_info(dev_id: nix::libc::dev_t) -> std::io::Result<BusInfo> {
+    use std::{fs, io, path::Path};
+
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Unexpected sysfs layout");
+    let file_name = |path: &Path| -> io::Result<String> {
+        path.file_name()
+            .and_then(|name| name.to_str())
+            .map(String::from)
+            .ok_or_else(invalid)
+    };
+
+    let device = fs::canonicalize(format!(
+        "/sys/dev/char/{}:{}/device",
+        nix::sys::stat::major(dev_id),
+        nix::sys::stat::minor(dev_id)
+    ))?;
+    let subsystem = file_name(&fs::canonicalize(device.join("subsystem"))?)?;
+    let name = file_name(&device)?;
+
+    match subsystem.as_str() {
+        "pci" => {
+            // `<domain>:<bus>:<device>.<function>`
+            let mut parts = name.split([':', '.']);
+            let mut next = |radix| {
+                parts
+                    .next()
+                    .and_then(|part| u16::from_str_radix(part, radix).ok())
+                    .ok_or_else(invalid)
+            };
+            Ok(BusInfo::Pci {
+                domain: next(16)?,
+                bus: next(16)? as u8,
+                device: next(16)? as u8,
+                function: next(16)? as u8,
+            })
+        }
+        "usb" => {
+            // the drm device is bound to an interface of the usb device
+            let usb_device = device.parent().ok_or_else(invalid)?;
+            let read_number = |file: &str| -> io::Result<u8> {
+                fs::read_to_string(usb_device.join(file))?
+                    .trim()
+                    .parse()
+                    .map_err(|_| invalid())
+            };
+            Ok(BusInfo::Usb {
+                bus: read_number("busnum")?,
+                device: read_number("devnum")?,
+            })
+        }
+        "platform" => Ok(BusInfo::Platform { name }),
+        _ => Ok(BusInfo::Other { subsystem, name }),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn bus_info(_dev_id: nix::libc::dev_t) -> std::io::Result<BusInfo> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "Bus info is only supported on linux",
+    ))
 }
 
 impl BasicDevice for DrmDeviceFd {}
 impl ControlDevice for DrmDeviceFd {}
+
+/// Guard holding the drm master lock of a [`DrmDeviceFd`]
+///
+/// Obtained via [`DrmDeviceFd::acquire_master`], the lock is released again once the last guard
+/// is dropped, unless the device already was master before.
+#[derive(Debug)]
+pub struct DrmMaster {
+    fd: DrmDeviceFd,
+}
+
+impl DrmMaster {
+    /// Returns the device this guard holds the master lock for
+    pub fn device_fd(&self) -> &DrmDeviceFd {
+        &self.fd
+    }
+}
+
+impl Drop for DrmMaster {
+    fn drop(&mut self) {
+        if !self.fd.0.master_guards.lock().unwrap().remove() {
+            return;
+        }
+        if let Err(err) = self.fd.release_master_lock() {
+            error!("Failed to drop drm master state. Error: {}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+    use std::num::NonZeroU32;
+
+    use drm::control::connector;
+
+    use super::{surface_assignment, MasterGuards};
+
+    #[test]
+    fn master_guards_keep_existing_master() {
+        // the device already was master, e.g. as it acquired the lock on creation
+        let mut guards = MasterGuards::default();
+        guards.add(false);
+        assert!(!guards.remove());
+
+        // only the last guard releases the lock it acquired
+        guards.add(true);
+        guards.add(false);
+        assert!(!guards.remove());
+        assert!(guards.remove());
+
+        // a later guard acquiring the lock again releases it again
+        guards.add(true);
+        assert!(guards.remove());
+    }
+
+    #[test]
+    fn connector_surface_assignment() {
+        let conn = |id| connector::Handle::from(NonZeroU32::new(id).unwrap());
+        let set = |ids: &[u32]| ids.iter().copied().map(conn).collect::<HashSet<_>>();
+        let connectors = [
+            // a dropped surface
+            None,
+            // connector 1 is committed, connector 2 is being added
+            Some((set(&[1]), set(&[1, 2]))),
+            // connector 3 is being removed
+            Some((set(&[3]), set(&[]))),
+        ];
+
+        assert_eq!(surface_assignment(conn(1), &connectors), (Some(1), false));
+        assert_eq!(surface_assignment(conn(2), &connectors), (Some(1), true));
+        assert_eq!(surface_assignment(conn(3), &connectors), (None, true));
+        assert_eq!(surface_assignment(conn(4), &connectors), (None, false));
+    }
+}
@@ -109,6 +109,32 @@ impl LegacyDrmDevice {
     }
 }
 
+impl LegacyDrmDevice {
+    // connectors driven by the crtc on creation
+    pub(crate) fn previous_connectors(&self, crtc: crtc::Handle) -> Vec<connector::Handle> {
+        self.old_state
+            .get(&crtc)
+            .map(|(_, connectors)| connectors.clone())
+            .unwrap_or_default()
+    }
+
+    // restores the state of the crtc captured on creation,
+    // returns `false` if the crtc was not in use back then.
+    pub(crate) fn restore_crtc(&self, crtc: crtc::Handle) -> Result<bool, Error> {
+        let Some((info, connectors)) = self.old_state.get(&crtc) else {
+            return Ok(false);
+        };
+        self.fd
+            .set_crtc(crtc, info.framebuffer(), info.position(), connectors, info.mode())
+            .map_err(|source| Error::Access {
+                errmsg: "Failed to restore crtc",
+                dev: self.fd.dev_path(),
+                source,
+            })?;
+        Ok(true)
+    }
+}
+
 impl Drop for LegacyDrmDevice {
     fn drop(&mut self) {
         if self.active.load(Ordering::SeqCst) {
@@ -6,17 +6,25 @@ use std::sync::{atomic::AtomicBool, Arc, Mutex, Weak};
 use std::time::{Duration, SystemTime};
 
 use calloop::{EventSource, Interest, Poll, PostAction, Readiness, Token, TokenFactory};
-use drm::control::{connector, crtc, plane, Device as ControlDevice, Event, Mode, ResourceHandles};
+use drm::control::{
+    atomic::AtomicModeReq, connector, crtc, plane, AtomicCommitFlags, Device as ControlDevice, Event, Mode,
+    ResourceHandles,
+};
 use drm::{ClientCapability, Device as BasicDevice, DriverCapability};
 use nix::libc::dev_t;
 
 pub(super) mod atomic;
 mod fd;
-pub use self::fd::DrmDeviceFd;
+pub use self::fd::{BusInfo, ConnectorStatus, DrmDeviceFd, DrmMaster};
 pub(super) mod legacy;
+mod source;
+pub use self::source::{DrmEventSource, DrmFdEvent};
 use crate::utils::{Buffer, DevPath, Size};
 
-use super::surface::{atomic::AtomicDrmSurface, legacy::LegacyDrmSurface, DrmSurface, DrmSurfaceInternal};
+use super::surface::{
+    atomic::AtomicDrmSurface, legacy::LegacyDrmSurface, BufferRelease, DrmSurface, DrmSurfaceInternal,
+    FrameCounter, GammaRamp,
+};
 use super::{error::Error, planes, Planes};
 use atomic::AtomicDrmDevice;
 use legacy::LegacyDrmDevice;
@@ -100,12 +108,78 @@ impl PlaneClaimStorage {
         }
     }
 
+    pub fn claimed(&self, crtc: drm::control::crtc::Handle) -> Vec<drm::control::plane::Handle> {
+        let claims = self
+            .claimed_planes
+            .lock()
+            .unwrap()
+            .values()
+            .filter_map(|claim| claim.upgrade())
+            .collect::<Vec<_>>();
+        // dropping the claims may release them, which requires the lock
+        claims
+            .iter()
+            .filter(|claim| claim.crtc() == crtc)
+            .map(|claim| claim.plane())
+            .collect()
+    }
+
+    pub fn claim_all(
+        &self,
+        planes: &[drm::control::plane::Handle],
+        crtc: drm::control::crtc::Handle,
+    ) -> Option<Vec<PlaneClaim>> {
+        let mut claims = Vec::with_capacity(planes.len());
+        let mut guard = self.claimed_planes.lock().unwrap();
+
+        // check all planes first, so we never have to roll back
+        for plane in planes {
+            match guard.get(plane).and_then(|claim| claim.upgrade()) {
+                Some(claim) if claim.crtc() == crtc => claims.push(Some(claim)),
+                Some(claim) => {
+                    // dropping the claims may release them, which requires the lock
+                    drop(guard);
+                    drop(claim);
+                    return None;
+                }
+                None => claims.push(None),
+            }
+        }
+
+        let claims = planes
+            .iter()
+            .zip(claims)
+            .map(|(plane, claim)| {
+                // a plane may be given multiple times
+                claim
+                    .or_else(|| guard.get(plane).and_then(|claim| claim.upgrade()))
+                    .unwrap_or_else(|| {
+                        let claim = PlaneClaim {
+                            claim: Arc::new(PlaneClaimInner {
+                                plane: *plane,
+                                crtc,
+                                storage: self.clone(),
+                            }),
+                        };
+                        guard.insert(*plane, claim.downgrade());
+                        claim
+                    })
+            })
+            .collect::<Vec<_>>();
+
+        drop(guard);
+        Some(claims)
+    }
+
     fn remove(&self, plane: drm::control::plane::Handle) {
         let mut guard = self.claimed_planes.lock().unwrap();
         guard.remove(&plane);
     }
 }
 
+type FrameCounters = Arc<Mutex<HashMap<crtc::Handle, Weak<FrameCounter>>>>;
+type BufferReleases = Arc<Mutex<HashMap<crtc::Handle, Weak<BufferRelease>>>>;
+
 /// An open drm device
 #[derive(Debug)]
 pub struct DrmDevice {
@@ -115,6 +189,8 @@ pub struct DrmDevice {
     cursor_size: Size<u32, Buffer>,
     resources: ResourceHandles,
     plane_claim_storage: PlaneClaimStorage,
+    frame_counters: FrameCounters,
+    buffer_releases: BufferReleases,
 }
 
 impl AsFd for DrmDevice {
@@ -197,14 +273,14 @@ impl DrmDevice {
             .set_client_capability(ClientCapability::UniversalPlanes, true)
             .is_ok();
         let has_monotonic_timestamps = fd
-            .get_driver_capability(DriverCapability::MonotonicTimestamp)
+            .cached_driver_capability(DriverCapability::MonotonicTimestamp)
             .unwrap_or(0)
             == 1;
         let cursor_width = fd
-            .get_driver_capability(DriverCapability::CursorWidth)
+            .cached_driver_capability(DriverCapability::CursorWidth)
             .unwrap_or(64);
         let cursor_height = fd
-            .get_driver_capability(DriverCapability::CursorHeight)
+            .cached_driver_capability(DriverCapability::CursorHeight)
             .unwrap_or(64);
         let cursor_size = Size::from((cursor_width as u32, cursor_height as u32));
         let resources = fd.resource_handles().map_err(|source| Error::Access {
@@ -214,6 +290,8 @@ impl DrmDevice {
         })?;
 
         let internal = Arc::new(DrmDevice::create_internal(fd, active, disable_connectors)?);
+        let frame_counters = FrameCounters::default();
+        let buffer_releases = BufferReleases::default();
 
         Ok((
             DrmDevice {
@@ -223,10 +301,14 @@ impl DrmDevice {
                 cursor_size,
                 resources,
                 plane_claim_storage: Default::default(),
+                frame_counters: frame_counters.clone(),
+                buffer_releases: buffer_releases.clone(),
             },
             DrmDeviceNotifier {
                 internal,
                 has_monotonic_timestamps,
+                frame_counters,
+                buffer_releases,
                 token: None,
             },
         ))
@@ -247,14 +329,12 @@ impl DrmDevice {
             info!("SMITHAY_USE_LEGACY is set. Forcing LegacyDrmDevice.");
         };
 
-        Ok(
-            if !force_legacy && fd.set_client_capability(ClientCapability::Atomic, true).is_ok() {
-                DrmDeviceInternal::Atomic(AtomicDrmDevice::new(fd, active, disable_connectors)?)
-            } else {
-                info!("Falling back to LegacyDrmDevice");
-                DrmDeviceInternal::Legacy(LegacyDrmDevice::new(fd, active, disable_connectors)?)
-            },
-        )
+        Ok(if !force_legacy && fd.supports_atomic() {
+            DrmDeviceInternal::Atomic(AtomicDrmDevice::new(fd, active, disable_connectors)?)
+        } else {
+            info!("Falling back to LegacyDrmDevice");
+            DrmDeviceInternal::Legacy(LegacyDrmDevice::new(fd, active, disable_connectors)?)
+        })
     }
 
     /// Returns if the underlying implementation uses atomic-modesetting or not.
@@ -361,6 +441,17 @@ impl DrmDevice {
             )?)
         };
 
+        let frame_counter = Arc::new(FrameCounter::default());
+        self.frame_counters
+            .lock()
+            .unwrap()
+            .insert(crtc, Arc::downgrade(&frame_counter));
+        let buffer_release = Arc::new(BufferRelease::default());
+        self.buffer_releases
+            .lock()
+            .unwrap()
+            .insert(crtc, Arc::downgrade(&buffer_release));
+
         Ok(DrmSurface {
             dev_id: self.dev_id,
             crtc,
@@ -368,9 +459,85 @@ impl DrmDevice {
             internal: Arc::new(internal),
             has_universal_planes: self.has_universal_planes,
             plane_claim_storage: self.plane_claim_storage.clone(),
+            frame_counter,
+            buffer_release,
+            cursor: Default::default(),
+            external_framebuffers: Default::default(),
+            committed_planes: Default::default(),
+            test_only: AtomicBool::new(false),
+            allow_tearing: AtomicBool::new(false),
+            #[cfg(feature = "backend_drm_failure_injection")]
+            injected_failures: Default::default(),
         })
     }
 
+    /// Sets the gamma ramps of multiple surfaces of this device at once.
+    ///
+    /// On atomic devices the `GAMMA_LUT`s of all crtcs are set in a single commit, so the new ramps
+    /// take effect on all outputs at the same vblank. Each ramp should have
+    /// [`DrmSurface::gamma_lut_size`] entries. If `event` is set, a `vblank` event
+    /// is generated for every crtc once the ramps are applied.
+    ///
+    /// Legacy devices cannot synchronize the update, the ramps are set one after another
+    /// via [`DrmSurface::set_gamma`] instead and no events are generated.
+    ///
+    /// Fails with [`Error::ForeignSurface`] if any surface was not created from this device.
+    pub fn commit_gamma(&self, ramps: &[(&DrmSurface, GammaRamp)], event: bool) -> Result<(), Error> {
+        for (surface, _) in ramps {
+            if surface.dev_id != self.dev_id {
+                return Err(Error::ForeignSurface(surface.crtc()));
+            }
+            if surface.is_test_only() {
+                return Err(Error::TestOnly(surface.crtc()));
+            }
+        }
+
+        let DrmDeviceInternal::Atomic(internal) = &*self.internal else {
+            for (surface, ramp) in ramps {
+                surface.set_gamma(&ramp.red, &ramp.green, &ramp.blue)?;
+            }
+            return Ok(());
+        };
+        if !internal.active.load(Ordering::SeqCst) {
+            return Err(Error::DeviceInactive);
+        }
+
+        let mut req = AtomicModeReq::new();
+        let mut blobs = Vec::with_capacity(ramps.len());
+        let mut res = Ok(());
+        for (surface, ramp) in ramps {
+            let DrmSurfaceInternal::Atomic(surf) = &*surface.internal else {
+                unreachable!("Atomic device with legacy surface");
+            };
+            match surf.add_gamma_lut(&mut req, &ramp.lut()) {
+                Ok(blob) => blobs.push(blob),
+                Err(err) => {
+                    res = Err(err);
+                    break;
+                }
+            }
+        }
+
+        if res.is_ok() {
+            let flags = if event {
+                AtomicCommitFlags::PAGE_FLIP_EVENT
+            } else {
+                AtomicCommitFlags::empty()
+            };
+            res = self.atomic_commit(flags, req).map_err(|source| Error::Access {
+                errmsg: "Failed to set gamma luts",
+                dev: self.dev_path(),
+                source,
+            });
+        }
+
+        // the crtcs hold their own references to the blobs
+        for blob in blobs {
+            let _ = self.destroy_property_blob(blob);
+        }
+        res.map_err(Error::map_removed)
+    }
+
     /// Returns the device_id of the underlying drm node
     pub fn device_id(&self) -> dev_t {
         self.dev_id
@@ -396,9 +563,21 @@ impl DrmDevice {
     }
 
     /// Actives a previously paused device.
+    ///
+    /// If the device was removed while being paused, it stays paused.
     pub fn activate(&self) {
         if self.device_fd().is_privileged() {
-            if let Err(err) = self.acquire_master_lock() {
+            if let Err(source) = self.acquire_master_lock() {
+                let err = Error::Access {
+                    errmsg: "Failed to acquire drm master",
+                    dev: self.dev_path(),
+                    source,
+                }
+                .map_removed();
+                if matches!(err, Error::DeviceRemoved(_)) {
+                    error!("{}, not activating it again", err);
+                    return;
+                }
                 error!("Failed to acquire drm master again. Error: {}", err);
             }
         }
@@ -453,6 +632,8 @@ pub enum Time {
 pub struct DrmDeviceNotifier {
     internal: Arc<DrmDeviceInternal>,
     has_monotonic_timestamps: bool,
+    frame_counters: FrameCounters,
+    buffer_releases: BufferReleases,
     token: Option<Token>,
 }
 
@@ -485,6 +666,24 @@ impl EventSource for DrmDeviceNotifier {
                             },
                             sequence: event.frame,
                         };
+                        if let Some(counter) = self
+                            .frame_counters
+                            .lock()
+                            .unwrap()
+                            .get(&event.crtc)
+                            .and_then(Weak::upgrade)
+                        {
+                            counter.vblank(event.frame);
+                        }
+                        if let Some(release) = self
+                            .buffer_releases
+                            .lock()
+                            .unwrap()
+                            .get(&event.crtc)
+                            .and_then(Weak::upgrade)
+                        {
+                            release.flipped();
+                        }
                         callback(DrmEvent::VBlank(event.crtc), &mut Some(metadata));
                     } else {
                         trace!(
@@ -496,11 +695,14 @@ impl EventSource for DrmDeviceNotifier {
             }
             Err(source) => {
                 callback(
-                    DrmEvent::Error(Error::Access {
-                        errmsg: "Error processing drm events",
-                        dev: self.internal.dev_path(),
-                        source,
-                    }),
+                    DrmEvent::Error(
+                        Error::Access {
+                            errmsg: "Error processing drm events",
+                            dev: self.internal.dev_path(),
+                            source,
+                        }
+                        .map_removed(),
+                    ),
                     &mut None,
                 );
             }
@@ -533,3 +735,28 @@ impl EventSource for DrmDeviceNotifier {
         poll.unregister(self.internal.as_fd().as_raw_fd())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::num::NonZeroU32;
+
+    use drm::control::{crtc, plane};
+
+    use super::PlaneClaimStorage;
+
+    #[test]
+    fn claimed_planes() {
+        let storage = PlaneClaimStorage::default();
+        let plane = |id| plane::Handle::from(NonZeroU32::new(id).unwrap());
+        let crtc = |id| crtc::Handle::from(NonZeroU32::new(id).unwrap());
+
+        let first = storage.claim(plane(1), crtc(10)).unwrap();
+        let _second = storage.claim(plane(2), crtc(20)).unwrap();
+        assert_eq!(storage.claimed(crtc(10)), vec![plane(1)]);
+
+        // releasing the last claim releases the plane
+        drop(first);
+        assert!(storage.claimed(crtc(10)).is_empty());
+        assert_eq!(storage.claimed(crtc(20)), vec![plane(2)]);
+    }
+}
@@ -0,0 +1,178 @@
+use std::io;
+use std::os::unix::io::{AsFd, AsRawFd};
+use std::time::SystemTime;
+
+use calloop::{EventSource, Interest, Poll, PostAction, Readiness, Token, TokenFactory};
+use drm::control::{crtc, Device as ControlDevice, Event};
+use drm::DriverCapability;
+
+use super::{DrmDeviceFd, Time};
+use crate::backend::drm::error::Error;
+use crate::utils::DevPath;
+
+use tracing::trace;
+
+/// Events read from a [`DrmDeviceFd`] by a [`DrmEventSource`]
+#[derive(Debug)]
+pub enum DrmFdEvent {
+    /// A page flip requested with an event has completed on the provided crtc
+    ///
+    /// *Note*: Page flips do not carry user data, as the crtc is already used as such.
+    Flip {
+        /// Crtc that flipped
+        crtc: crtc::Handle,
+        /// Sequence number of the frame
+        sequence: u32,
+        /// Time of the flip
+        time: Time,
+    },
+    /// A vblank requested via `drmWaitVBlank` has happened on the provided crtc
+    Vblank {
+        /// Crtc of the vblank
+        crtc: crtc::Handle,
+        /// Sequence number of the frame
+        sequence: u32,
+        /// Time of the vblank
+        time: Time,
+        /// User data passed on requesting the vblank event
+        user_data: usize,
+    },
+    /// An error happened while processing events
+    Error(Error),
+}
+
+/// Event source reading the events of a [`DrmDeviceFd`]
+///
+/// This is useful for file descriptors not managed by a [`DrmDevice`](super::DrmDevice),
+/// e.g. of drm leases. Unlike the [`DrmDeviceNotifier`](super::DrmDeviceNotifier),
+/// it does not track the state of any [`DrmSurface`](crate::backend::drm::DrmSurface).
+///
+/// Only one event source should be registered for a file descriptor at a time,
+/// as every event is only delivered once. Hotplug events are not sent through the drm device
+/// and need to be received via udev instead.
+#[derive(Debug)]
+pub struct DrmEventSource {
+    fd: DrmDeviceFd,
+    has_monotonic_timestamps: bool,
+    token: Option<Token>,
+}
+
+impl DrmEventSource {
+    /// Creates a new event source for the given file descriptor
+    pub fn new(fd: DrmDeviceFd) -> DrmEventSource {
+        let has_monotonic_timestamps = fd
+            .cached_driver_capability(DriverCapability::MonotonicTimestamp)
+            .unwrap_or(0)
+            == 1;
+        DrmEventSource {
+            fd,
+            has_monotonic_timestamps,
+            token: None,
+        }
+    }
+
+    /// Returns the underlying file descriptor
+    pub fn device_fd(&self) -> &DrmDeviceFd {
+        &self.fd
+    }
+
+    fn time(&self, duration: std::time::Duration) -> Time {
+        if self.has_monotonic_timestamps {
+            Time::Monotonic(duration)
+        } else {
+            Time::Realtime(SystemTime::UNIX_EPOCH + duration)
+        }
+    }
+}
+
+impl EventSource for DrmEventSource {
+    type Event = DrmFdEvent;
+    type Metadata = ();
+    type Ret = ();
+    type Error = io::Error;
+
+    fn process_events<F>(&mut self, _: Readiness, token: Token, mut callback: F) -> io::Result<PostAction>
+    where
+        F: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret,
+    {
+        if Some(token) != self.token {
+            return Ok(PostAction::Continue);
+        }
+
+        match self.fd.receive_events() {
+            Ok(events) => {
+                for event in events {
+                    match event {
+                        Event::PageFlip(event) => {
+                            trace!("Got a page-flip event for crtc ({:?})", event.crtc);
+                            let time = self.time(event.duration);
+                            callback(
+                                DrmFdEvent::Flip {
+                                    crtc: event.crtc,
+                                    sequence: event.frame,
+                                    time,
+                                },
+                                &mut (),
+                            );
+                        }
+                        Event::Vblank(event) => {
+                            trace!("Got a vblank event for crtc ({:?})", event.crtc);
+                            let time = self.time(event.time);
+                            callback(
+                                DrmFdEvent::Vblank {
+                                    crtc: event.crtc,
+                                    sequence: event.frame,
+                                    time,
+                                    user_data: event.user_data,
+                                },
+                                &mut (),
+                            );
+                        }
+                        _ => {
+                            trace!("Got an unknown event of device '{:?}'.", self.fd.dev_path());
+                        }
+                    }
+                }
+            }
+            Err(source) => {
+                callback(
+                    DrmFdEvent::Error(
+                        Error::Access {
+                            errmsg: "Error processing drm events",
+                            dev: self.fd.dev_path(),
+                            source,
+                        }
+                        .map_removed(),
+                    ),
+                    &mut (),
+                );
+            }
+        }
+        Ok(PostAction::Continue)
+    }
+
+    fn register(&mut self, poll: &mut Poll, factory: &mut TokenFactory) -> calloop::Result<()> {
+        self.token = Some(factory.token());
+        poll.register(
+            self.fd.as_fd().as_raw_fd(),
+            Interest::READ,
+            calloop::Mode::Level,
+            self.token.unwrap(),
+        )
+    }
+
+    fn reregister(&mut self, poll: &mut Poll, factory: &mut TokenFactory) -> calloop::Result<()> {
+        self.token = Some(factory.token());
+        poll.reregister(
+            self.fd.as_fd().as_raw_fd(),
+            Interest::READ,
+            calloop::Mode::Level,
+            self.token.unwrap(),
+        )
+    }
+
+    fn unregister(&mut self, poll: &mut Poll) -> calloop::Result<()> {
+        self.token = None;
+        poll.unregister(self.fd.as_fd().as_raw_fd())
+    }
+}
@@ -1,5 +1,9 @@
-use crate::backend::SwapBuffersError;
-use drm::control::{connector, crtc, plane, Mode, RawResourceHandle};
+use crate::backend::{
+    allocator::{Fourcc, Modifier},
+    drm::CreateDrmNodeError,
+    SwapBuffersError,
+};
+use drm::control::{connector, crtc, framebuffer, plane, Mode, RawResourceHandle};
 use std::path::PathBuf;
 
 /// Errors thrown by the [`DrmDevice`](crate::backend::drm::DrmDevice)
@@ -22,6 +26,12 @@ pub enum Error {
     /// Unable to determine device id of drm device
     #[error("Unable to determine device id of drm device")]
     UnableToGetDeviceId(#[source] nix::Error),
+    /// Unable to determine the bus info of the drm device
+    #[error("Unable to determine bus info of drm device")]
+    UnableToGetBusInfo(#[source] std::io::Error),
+    /// Unable to determine the node type of the drm device
+    #[error("Unable to determine node type of drm device")]
+    UnableToGetNodeType(#[source] CreateDrmNodeError),
     /// Device is currently paused
     #[error("Device is currently paused, operation rejected")]
     DeviceInactive,
@@ -43,12 +53,27 @@ pub enum Error {
     /// The given plane is not a primary plane and therefor not supported by the underlying implementation
     #[error("Non-Primary Planes (provided was `{0:?}`) are not available for use with legacy devices")]
     NonPrimaryPlane(plane::Handle),
+    /// The update would leave the primary plane of an active crtc without a framebuffer
+    #[error("Primary plane `{1:?}` of the active crtc `{0:?}` has no framebuffer")]
+    MissingPrimaryPlane(crtc::Handle, plane::Handle),
     /// The given plane does not allow to clear the framebuffer
     #[error("Clearing the framebuffer on plane `{0:?}` is not supported")]
     NoFramebuffer(plane::Handle),
     /// The configuration is not supported on the given plane
     #[error("The configuration is not supported on plane `{0:?}`")]
     UnsupportedPlaneConfiguration(plane::Handle),
+    /// The format and modifier of a framebuffer are not supported by the plane
+    #[error("Framebuffer `{fb:?}` with format `{format}` and modifier `{modifier:?}` is not supported by plane `{plane:?}`")]
+    UnsupportedFramebufferModifier {
+        /// Plane the framebuffer was validated against
+        plane: plane::Handle,
+        /// Framebuffer
+        fb: framebuffer::Handle,
+        /// Format of the framebuffer
+        format: Fourcc,
+        /// Modifier of the framebuffer
+        modifier: Modifier,
+    },
     /// No encoder was found for a given connector on the set crtc
     #[error("No encoder found for the given connector '{connector:?}' on crtc `{crtc:?}`")]
     NoSuitableEncoder {
@@ -66,14 +91,72 @@ pub enum Error {
         name: &'static str,
     },
     /// Atomic Test failed for new properties
+    ///
+    /// Carries the error returned by the kernel, if the test was rejected by the driver.
     #[error("Atomic Test failed for new properties on crtc ({0:?})")]
-    TestFailed(crtc::Handle),
+    TestFailed(crtc::Handle, #[source] Option<drm::SystemError>),
+    /// No mode matching a stored configuration is available
+    #[error("No mode `{name}@{refresh}mHz` is available on the given connectors")]
+    ModeNotFound {
+        /// Name of the mode
+        name: String,
+        /// Vertical refresh rate of the mode in millihertz
+        refresh: u32,
+    },
+    /// The device is not the drm master, which is required for the operation
+    #[error("The drm device `{0:?}` is not the drm master")]
+    NotMaster(Option<PathBuf>),
+    /// The device was paused, because the session is inactive, which is required for the operation
+    #[error("The session of drm device `{0:?}` is inactive")]
+    SessionInactive(Option<PathBuf>),
+    /// The device was removed, e.g. by unplugging it
+    #[error("The drm device `{0:?}` was removed")]
+    DeviceRemoved(Option<PathBuf>),
+    /// No page flip event was received in time
+    #[error("Timed out waiting for a page flip on crtc ({0:?})")]
+    FlipTimeout(crtc::Handle),
+    /// The commit was rejected by frame pacing, as it would be presented too early
+    #[error("Commit on crtc ({0:?}) would be presented before the next paced vblank")]
+    TooEarly(crtc::Handle),
+    /// The surface is in test-only mode and does not accept changes to the display
+    #[error("Surface of crtc ({0:?}) is in test-only mode, operation rejected")]
+    TestOnly(crtc::Handle),
+    /// Tearing was requested, but is not allowed for the surface
+    #[error("Tearing is not allowed on crtc ({0:?})")]
+    TearingNotAllowed(crtc::Handle),
+    /// The surface was not created from this device
+    #[error("Surface of crtc ({0:?}) belongs to a different device")]
+    ForeignSurface(crtc::Handle),
+}
+
+impl Error {
+    /// Maps access errors caused by a removed device (`ENODEV` or `ENXIO`) to [`Error::DeviceRemoved`]
+    pub(crate) fn map_removed(self) -> Error {
+        match self {
+            Error::Access {
+                dev,
+                source:
+                    drm::SystemError::Unknown {
+                        errno: nix::errno::Errno::ENODEV,
+                    }
+                    | drm::SystemError::Unknown {
+                        errno: nix::errno::Errno::ENXIO,
+                    },
+                ..
+            } => Error::DeviceRemoved(dev),
+            x => x,
+        }
+    }
 }
 
 impl From<Error> for SwapBuffersError {
     fn from(err: Error) -> SwapBuffersError {
         match err {
-            x @ Error::DeviceInactive => SwapBuffersError::TemporaryFailure(Box::new(x)),
+            x @ Error::DeviceInactive
+            | x @ Error::FlipTimeout(_)
+            | x @ Error::NotMaster(_)
+            | x @ Error::SessionInactive(_)
+            | x @ Error::TooEarly(_) => SwapBuffersError::TemporaryFailure(Box::new(x)),
             Error::Access {
                 errmsg, dev, source, ..
             } if matches!(
@@ -72,6 +72,7 @@
 
 #[cfg(all(feature = "wayland_frontend", feature = "backend_gbm"))]
 pub mod compositor;
+pub(self) mod cvt;
 pub(crate) mod device;
 pub(self) mod error;
 #[cfg(feature = "backend_gbm")]
@@ -79,19 +80,27 @@ pub mod gbm;
 pub mod node;
 
 pub(self) mod surface;
+#[cfg(feature = "backend_drm_failure_injection")]
+pub mod test;
 
 use crate::utils::DevPath;
+pub use cvt::cvt_mode;
 pub use device::{
-    DrmDevice, DrmDeviceFd, DrmDeviceNotifier, DrmEvent, EventMetadata as DrmEventMetadata, PlaneClaim,
-    Time as DrmEventTime,
+    BusInfo, ConnectorStatus, DrmDevice, DrmDeviceFd, DrmDeviceNotifier, DrmEvent, DrmEventSource,
+    DrmFdEvent, DrmMaster, EventMetadata as DrmEventMetadata, PlaneClaim, Time as DrmEventTime,
 };
 pub use error::Error as DrmError;
 pub use node::{CreateDrmNodeError, DrmNode, NodeType};
+pub use surface::config::OutputConfig;
 #[cfg(feature = "backend_gbm")]
 pub use surface::gbm::{Error as GbmBufferedSurfaceError, GbmBufferedSurface};
-pub use surface::{DrmSurface, PlaneConfig, PlaneDamageClips, PlaneState};
+pub use surface::{
+    CommitKind, ConnectorLimits, DpmsState, DrmSurface, FeedbackTranche, GammaCurveSegment, GammaRamp,
+    HdrLuminance, ModifierPolicy, OwnedPlaneConfig, PixelBlendMode, PlaneConfig, PlaneDamageClips,
+    PlaneState, PropertySnapshot, ScalingFilter, ScalingMode, VrrCapability, WeakDrmSurface,
+};
 
-use drm::control::{crtc, plane, Device as ControlDevice, PlaneType};
+use drm::control::{crtc, plane, Device as ControlDevice, Mode, ModeFlags, PlaneType};
 
 /// A set of planes as supported by a crtc
 #[derive(Debug, Clone)]
@@ -115,6 +124,34 @@ pub struct PlaneInfo {
     pub zpos: Option<i32>,
 }
 
+/// Calculates the exact refresh rate of a mode in millihertz from its timings
+///
+/// Unlike [`Mode::vrefresh`], this allows to distinguish e.g. 59.94 Hz from 60 Hz.
+pub fn mode_refresh_mhz(mode: &Mode) -> u32 {
+    let clock = mode.clock() as u64;
+    let htotal = mode.hsync().2 as u64;
+    let vtotal = mode.vsync().2 as u64;
+    if htotal == 0 || vtotal == 0 {
+        return 0;
+    }
+
+    let mut refresh = (clock * 1_000_000 / htotal + vtotal / 2) / vtotal;
+
+    if mode.flags().contains(ModeFlags::INTERLACE) {
+        refresh *= 2;
+    }
+
+    if mode.flags().contains(ModeFlags::DBLSCAN) {
+        refresh /= 2;
+    }
+
+    if mode.vscan() > 1 {
+        refresh /= mode.vscan() as u64;
+    }
+
+    refresh as u32
+}
+
 fn planes(
     dev: &(impl DevPath + ControlDevice),
     crtc: &crtc::Handle,
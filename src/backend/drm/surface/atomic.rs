@@ -1,20 +1,26 @@
+use drm::buffer::Buffer;
 use drm::control::atomic::AtomicModeReq;
 use drm::control::Device as ControlDevice;
 use drm::control::{
     connector, crtc, dumbbuffer::DumbBuffer, framebuffer, plane, property, AtomicCommitFlags, Mode, PlaneType,
 };
+use drm::DriverCapability;
 
 use std::collections::HashSet;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
 use std::sync::Mutex;
 use std::sync::{
-    atomic::{AtomicBool, Ordering},
+    atomic::{AtomicBool, AtomicUsize, Ordering},
     Arc, RwLock,
 };
 
 use crate::utils::{Coordinate, Point, Rectangle, Transform};
 use crate::{
     backend::{
-        allocator::format::{get_bpp, get_depth},
+        allocator::{
+            format::{get_bpp, get_depth},
+            Fourcc,
+        },
         drm::{
             device::atomic::{map_props, Mapping},
             device::DrmDeviceInternal,
@@ -27,18 +33,101 @@ use crate::{
 
 use tracing::{debug, info, info_span, instrument, trace, warn};
 
-use super::{PlaneConfig, PlaneState};
+use super::{
+    snapshot::StagedBlob, DpmsState, PixelBlendMode, PlaneConfig, PlaneState, PropertySnapshot,
+    ScalingFilter, ScalingMode,
+};
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct State {
     pub mode: Mode,
-    pub blob: property::Value<'static>,
+    pub blob: Arc<ModeBlob>,
     pub connectors: HashSet<connector::Handle>,
+    pub scaling_mode: Option<ScalingMode>,
+    pub connector_properties: Vec<PropertySnapshot>,
+}
+
+/// `MODE_ID` property blob of a [`State`]
+///
+/// The blob is destroyed once the last state referencing it is dropped,
+/// so replacing a mode never leaks the blob of the previous one.
+#[derive(Debug)]
+pub struct ModeBlob {
+    drm: DrmDeviceFd,
+    value: property::Value<'static>,
+    live_blobs: Arc<AtomicUsize>,
+}
+
+impl ModeBlob {
+    fn new(
+        drm: &DrmDeviceFd,
+        mode: Option<&Mode>,
+        live_blobs: &Arc<AtomicUsize>,
+    ) -> Result<Arc<Self>, Error> {
+        let value = match mode {
+            Some(mode) => drm.create_property_blob(mode).map_err(|source| Error::Access {
+                errmsg: "Failed to create Property Blob for mode",
+                dev: drm.dev_path(),
+                source,
+            })?,
+            None => property::Value::Unknown(0),
+        };
+
+        Ok(ModeBlob::from_value(drm, value, live_blobs))
+    }
+
+    // takes ownership of an already created blob
+    fn from_value(
+        drm: &DrmDeviceFd,
+        value: property::Value<'static>,
+        live_blobs: &Arc<AtomicUsize>,
+    ) -> Arc<Self> {
+        let blob = ModeBlob {
+            drm: drm.clone(),
+            value,
+            live_blobs: live_blobs.clone(),
+        };
+        if blob.is_allocated() {
+            live_blobs.fetch_add(1, Ordering::SeqCst);
+        }
+        Arc::new(blob)
+    }
+
+    fn value(&self) -> property::Value<'static> {
+        self.value
+    }
+
+    fn is_allocated(&self) -> bool {
+        self.value != property::Value::Unknown(0)
+    }
+}
+
+impl PartialEq for ModeBlob {
+    fn eq(&self, other: &Self) -> bool {
+        self.value == other.value
+    }
+}
+
+impl Eq for ModeBlob {}
+
+impl Drop for ModeBlob {
+    fn drop(&mut self) {
+        if !self.is_allocated() {
+            return;
+        }
+
+        self.live_blobs.fetch_sub(1, Ordering::SeqCst);
+        if let Err(err) = self.drm.destroy_property_blob(self.value.into()) {
+            warn!("Failed to destroy mode property blob: {}", err);
+        }
+    }
 }
 
 impl State {
     fn current_state<A: DevPath + ControlDevice>(
         fd: &A,
+        drm: &DrmDeviceFd,
+        live_blobs: &Arc<AtomicUsize>,
         crtc: crtc::Handle,
         prop_mapping: &mut Mapping,
     ) -> Result<Self, Error> {
@@ -53,14 +142,7 @@ impl State {
         // we need to be sure, we require a mode to always be set without relying on the compiler.
         // So we cheat, because it works and is easier to handle later.
         let current_mode = crtc_info.mode().unwrap_or_else(|| unsafe { std::mem::zeroed() });
-        let current_blob = match crtc_info.mode() {
-            Some(mode) => fd.create_property_blob(&mode).map_err(|source| Error::Access {
-                errmsg: "Failed to create Property Blob for mode",
-                dev: fd.dev_path(),
-                source,
-            })?,
-            None => property::Value::Unknown(0),
-        };
+        let current_blob = ModeBlob::new(drm, crtc_info.mode().as_ref(), live_blobs)?;
 
         let res_handles = fd.resource_handles().map_err(|source| Error::Access {
             errmsg: "Error loading drm resources",
@@ -106,6 +188,8 @@ impl State {
             mode: current_mode,
             blob: current_blob,
             connectors: current_connectors,
+            scaling_mode: None,
+            connector_properties: Vec::new(),
         })
     }
 }
@@ -120,6 +204,14 @@ pub struct AtomicDrmSurface {
     prop_mapping: RwLock<Mapping>,
     state: RwLock<State>,
     pending: RwLock<State>,
+    live_blobs: Arc<AtomicUsize>,
+    // whether the crtc is powered, see `set_active`
+    crtc_active: AtomicBool,
+    // signalled sync_file used to skip implicit sync, see `PlaneConfig::skip_implicit_sync`
+    signalled_fence: Mutex<Option<OwnedFd>>,
+    pub(super) restore_on_drop: AtomicBool,
+    // writeback connectors attached to the crtc, that need to be detached again on drop
+    writeback_connectors: Mutex<HashSet<connector::Handle>>,
     pub(super) span: tracing::Span,
 }
 
@@ -134,23 +226,22 @@ impl AtomicDrmSurface {
         mode: Mode,
         connectors: &[connector::Handle],
     ) -> Result<Self, Error> {
-        let span = info_span!("drm_atomic", crtc = ?crtc);
+        let span = info_span!("drm_atomic", crtc = ?crtc, label = tracing::field::Empty);
         let _guard = span.enter();
         info!(
             "Initializing drm surface ({:?}:{:?}) with mode {:?} and connectors {:?}",
             crtc, plane, mode, connectors
         );
 
-        let state = State::current_state(&*fd, crtc, &mut prop_mapping)?;
-        let blob = fd.create_property_blob(&mode).map_err(|source| Error::Access {
-            errmsg: "Failed to create Property Blob for mode",
-            dev: fd.dev_path(),
-            source,
-        })?;
+        let live_blobs = Arc::new(AtomicUsize::new(0));
+        let state = State::current_state(&*fd, fd.device_fd(), &live_blobs, crtc, &mut prop_mapping)?;
+        let blob = ModeBlob::new(fd.device_fd(), Some(&mode), &live_blobs)?;
         let pending = State {
             mode,
             blob,
             connectors: connectors.iter().copied().collect(),
+            scaling_mode: None,
+            connector_properties: Vec::new(),
         };
 
         drop(_guard);
@@ -163,6 +254,11 @@ impl AtomicDrmSurface {
             prop_mapping: RwLock::new(prop_mapping),
             state: RwLock::new(state),
             pending: RwLock::new(pending),
+            live_blobs,
+            crtc_active: AtomicBool::new(true),
+            signalled_fence: Mutex::new(None),
+            restore_on_drop: AtomicBool::new(false),
+            writeback_connectors: Mutex::new(HashSet::new()),
             span,
         };
 
@@ -214,6 +310,96 @@ impl AtomicDrmSurface {
         }
     }
 
+    // tests if the plane can scan out a linear buffer of the given format at the pending mode.
+    // returns `None` if the format cannot be tested, as no dumb buffer can be created for it.
+    pub fn test_scanout_format(&self, plane: plane::Handle, format: Fourcc) -> Result<Option<bool>, Error> {
+        let Some(bpp) = get_bpp(format) else {
+            return Ok(None);
+        };
+        let (w, h) = self.pending.read().unwrap().mode.size();
+        let (w, h) = (w as u32, h as u32);
+
+        let db = self
+            .fd
+            .create_dumb_buffer((w, h), format, bpp as u32)
+            .map_err(|source| Error::Access {
+                errmsg: "Failed to create dumb buffer",
+                dev: self.fd.dev_path(),
+                source,
+            })?;
+        // unlike `add_framebuffer`, this is not limited to the formats of the legacy depth/bpp combinations
+        let fb = drm_ffi::mode::add_fb2(
+            self.fd.device_fd().as_raw_fd(),
+            w,
+            h,
+            format as u32,
+            &[db.handle().into(), 0, 0, 0],
+            &[db.pitch(), 0, 0, 0],
+            &[0; 4],
+            &[0; 4],
+            0,
+        )
+        .ok()
+        .and_then(|fb| drm::control::from_u32(fb.fb_id));
+        let Some(fb) = fb else {
+            // the format is not supported for framebuffers at all
+            let _ = self.fd.destroy_dumb_buffer(db);
+            return Ok(Some(false));
+        };
+        let test_buffer = TestBuffer {
+            fd: self.fd.clone(),
+            db,
+            fb,
+        };
+
+        let res = self.test_state(
+            [PlaneState {
+                handle: plane,
+                config: Some(PlaneConfig {
+                    src: Rectangle::from_loc_and_size(Point::default(), (w as f64, h as f64)),
+                    dst: Rectangle::from_loc_and_size(Point::default(), (w as i32, h as i32)),
+                    transform: Transform::Normal,
+                    alpha: 1.0,
+                    damage_clips: None,
+                    fb: *test_buffer.as_ref(),
+                    blend_mode: None,
+                    scaling_filter: None,
+                    skip_implicit_sync: false,
+                }),
+            }],
+            true,
+        );
+
+        Ok(Some(res.is_ok()))
+    }
+
+    // tests if the primary plane and the given overlay planes can be enabled at the same time,
+    // each showing a buffer of the size of the pending mode.
+    pub fn test_active_planes(&self, overlays: &[plane::Handle]) -> Result<bool, Error> {
+        let size = self.pending.read().unwrap().mode.size();
+
+        let mut buffers = Vec::with_capacity(overlays.len() + 1);
+        for plane in std::iter::once(self.plane).chain(overlays.iter().copied()) {
+            buffers.push((plane, self.create_test_buffer(size, plane)?));
+        }
+
+        let planes = buffers.iter().map(|(plane, buffer)| PlaneState {
+            handle: *plane,
+            config: Some(PlaneConfig {
+                src: Rectangle::from_loc_and_size(Point::default(), size).to_f64(),
+                dst: Rectangle::from_loc_and_size(Point::default(), (size.0 as i32, size.1 as i32)),
+                transform: Transform::Normal,
+                alpha: 1.0,
+                damage_clips: None,
+                fb: buffer.fb,
+                blend_mode: None,
+                scaling_filter: None,
+                skip_implicit_sync: false,
+            }),
+        });
+        Ok(self.test_state(planes, true).is_ok())
+    }
+
     pub fn current_connectors(&self) -> HashSet<connector::Handle> {
         self.state.read().unwrap().connectors.clone()
     }
@@ -230,6 +416,11 @@ impl AtomicDrmSurface {
         self.pending.read().unwrap().mode
     }
 
+    pub fn pending_mode_blob(&self) -> Option<property::Value<'static>> {
+        let pending = self.pending.read().unwrap();
+        pending.blob.is_allocated().then(|| pending.blob.value())
+    }
+
     fn ensure_props_known(&self, conns: &[connector::Handle]) -> Result<(), Error> {
         let mapping_exists = {
             let prop_mapping = self.prop_mapping.read().unwrap();
@@ -290,16 +481,19 @@ impl AtomicDrmSurface {
                         alpha: 1.0,
                         damage_clips: None,
                         fb: test_buffer.fb,
+                        blend_mode: None,
+                        scaling_filter: None,
+                        skip_implicit_sync: false,
                     }),
                 }],
-                Some(pending.blob),
+                Some(pending.blob.value()),
             )?;
             self.fd
                 .atomic_commit(
                     AtomicCommitFlags::ALLOW_MODESET | AtomicCommitFlags::TEST_ONLY,
                     req,
                 )
-                .map_err(|_| Error::TestFailed(self.crtc))?;
+                .map_err(|source| Error::TestFailed(self.crtc, Some(source)))?;
 
             // seems to be, lets add the connector
             pending.connectors.insert(conn);
@@ -341,16 +535,19 @@ impl AtomicDrmSurface {
                     alpha: 1.0,
                     damage_clips: None,
                     fb: test_buffer.fb,
+                    blend_mode: None,
+                    scaling_filter: None,
+                    skip_implicit_sync: false,
                 }),
             }],
-            Some(pending.blob),
+            Some(pending.blob.value()),
         )?;
         self.fd
             .atomic_commit(
                 AtomicCommitFlags::ALLOW_MODESET | AtomicCommitFlags::TEST_ONLY,
                 req,
             )
-            .map_err(|_| Error::TestFailed(self.crtc))?;
+            .map_err(|source| Error::TestFailed(self.crtc, Some(source)))?;
 
         // seems to be, lets remove the connector
         pending.connectors.remove(&conn);
@@ -394,9 +591,12 @@ impl AtomicDrmSurface {
                     alpha: 1.0,
                     damage_clips: None,
                     fb: test_buffer.fb,
+                    blend_mode: None,
+                    scaling_filter: None,
+                    skip_implicit_sync: false,
                 }),
             }],
-            Some(pending.blob),
+            Some(pending.blob.value()),
         )?;
 
         self.fd
@@ -404,9 +604,66 @@ impl AtomicDrmSurface {
                 AtomicCommitFlags::ALLOW_MODESET | AtomicCommitFlags::TEST_ONLY,
                 req,
             )
-            .map_err(|_| Error::TestFailed(self.crtc))?;
+            .map_err(|source| Error::TestFailed(self.crtc, Some(source)))?;
+
+        pending.connectors = conns;
+
+        Ok(())
+    }
+
+    #[instrument(level = "debug", parent = &self.span, skip(self))]
+    pub fn reconfigure(&self, connectors: &[connector::Handle], mode: Mode) -> Result<(), Error> {
+        if connectors.is_empty() {
+            return Err(Error::SurfaceWithoutConnectors(self.crtc));
+        }
+
+        if !self.active.load(Ordering::SeqCst) {
+            return Err(Error::DeviceInactive);
+        }
+
+        let current = self.state.read().unwrap();
+        let mut pending = self.pending.write().unwrap();
+
+        self.ensure_props_known(connectors)?;
+        let conns = connectors.iter().cloned().collect::<HashSet<_>>();
+        let mut removed = current.connectors.difference(&conns);
+
+        let new_blob = ModeBlob::new(self.fd.device_fd(), Some(&mode), &self.live_blobs)?;
+        let test_buffer = self.create_test_buffer(mode.size(), self.plane)?;
+
+        // test the new connectors together with the new mode
+        let req = self.build_request(
+            &mut conns.iter(),
+            &mut removed,
+            [&PlaneState {
+                handle: self.plane,
+                config: Some(PlaneConfig {
+                    src: Rectangle::from_loc_and_size(Point::default(), mode.size()).to_f64(),
+                    dst: Rectangle::from_loc_and_size(
+                        Point::default(),
+                        (mode.size().0 as i32, mode.size().1 as i32),
+                    ),
+                    transform: Transform::Normal,
+                    alpha: 1.0,
+                    damage_clips: None,
+                    fb: test_buffer.fb,
+                    blend_mode: None,
+                    scaling_filter: None,
+                    skip_implicit_sync: false,
+                }),
+            }],
+            Some(new_blob.value()),
+        )?;
+        self.fd
+            .atomic_commit(
+                AtomicCommitFlags::ALLOW_MODESET | AtomicCommitFlags::TEST_ONLY,
+                req,
+            )
+            .map_err(|source| Error::TestFailed(self.crtc, Some(source)))?;
 
         pending.connectors = conns;
+        pending.mode = mode;
+        pending.blob = new_blob;
 
         Ok(())
     }
@@ -420,14 +677,7 @@ impl AtomicDrmSurface {
         let mut pending = self.pending.write().unwrap();
 
         // check if new config is supported
-        let new_blob = self
-            .fd
-            .create_property_blob(&mode)
-            .map_err(|source| Error::Access {
-                errmsg: "Failed to create Property Blob for mode",
-                dev: self.fd.dev_path(),
-                source,
-            })?;
+        let new_blob = ModeBlob::new(self.fd.device_fd(), Some(&mode), &self.live_blobs)?;
 
         let test_buffer = self.create_test_buffer(mode.size(), self.plane)?;
 
@@ -437,42 +687,222 @@ impl AtomicDrmSurface {
             [&PlaneState {
                 handle: self.plane,
                 config: Some(PlaneConfig {
-                    src: Rectangle::from_loc_and_size(Point::default(), pending.mode.size()).to_f64(),
+                    src: Rectangle::from_loc_and_size(Point::default(), mode.size()).to_f64(),
                     dst: Rectangle::from_loc_and_size(
                         Point::default(),
-                        (pending.mode.size().0 as i32, pending.mode.size().1 as i32),
+                        (mode.size().0 as i32, mode.size().1 as i32),
                     ),
                     transform: Transform::Normal,
                     alpha: 1.0,
                     damage_clips: None,
                     fb: test_buffer.fb,
+                    blend_mode: None,
+                    scaling_filter: None,
+                    skip_implicit_sync: false,
                 }),
             }],
-            Some(new_blob),
+            Some(new_blob.value()),
         )?;
-        if let Err(err) = self
-            .fd
+        self.fd
             .atomic_commit(
                 AtomicCommitFlags::ALLOW_MODESET | AtomicCommitFlags::TEST_ONLY,
                 req,
             )
-            .map_err(|_| Error::TestFailed(self.crtc))
-        {
-            let _ = self.fd.destroy_property_blob(new_blob.into());
-            return Err(err);
-        }
+            .map_err(|source| Error::TestFailed(self.crtc, Some(source)))?;
 
         // seems to be, lets change the mode
+        // (this drops the blob of the previously pending mode, unless it is the current one)
         pending.mode = mode;
         pending.blob = new_blob;
 
         Ok(())
     }
 
+    #[instrument(level = "debug", parent = &self.span, skip(self))]
+    pub fn set_scaling_mode(&self, mode: ScalingMode) -> Result<(), Error> {
+        if !self.active.load(Ordering::SeqCst) {
+            return Err(Error::DeviceInactive);
+        }
+
+        let mut pending = self.pending.write().unwrap();
+        let prop_mapping = self.prop_mapping.read().unwrap();
+        for conn in pending.connectors.iter() {
+            let prop = conn_prop_handle(&prop_mapping, *conn, "scaling mode")?;
+            if enum_prop_value(&*self.fd, prop, mode.drm_name())?.is_none() {
+                return Err(Error::UnknownProperty {
+                    handle: (*conn).into(),
+                    name: "scaling mode",
+                });
+            }
+        }
+
+        pending.scaling_mode = Some(mode);
+
+        Ok(())
+    }
+
+    #[instrument(level = "debug", parent = &self.span, skip(self, snapshot))]
+    pub fn restore_connector_properties(&self, snapshot: &PropertySnapshot) -> Result<(), Error> {
+        if !self.active.load(Ordering::SeqCst) {
+            return Err(Error::DeviceInactive);
+        }
+
+        let mut pending = self.pending.write().unwrap();
+        pending
+            .connector_properties
+            .retain(|staged| staged.connector() != snapshot.connector());
+        pending.connector_properties.push(snapshot.clone());
+
+        Ok(())
+    }
+
+    // size of the `GAMMA_LUT` of the crtc, if exposed by the driver
+    pub fn gamma_lut_size(&self) -> Result<Option<u32>, Error> {
+        let prop_mapping = self.prop_mapping.read().unwrap();
+        let (Ok(size_prop), Ok(_)) = (
+            crtc_prop_handle(&prop_mapping, self.crtc, "GAMMA_LUT_SIZE"),
+            crtc_prop_handle(&prop_mapping, self.crtc, "GAMMA_LUT"),
+        ) else {
+            return Ok(None);
+        };
+
+        let props = self
+            .fd
+            .get_properties(self.crtc)
+            .map_err(|source| Error::Access {
+                errmsg: "Failed to get properties of crtc",
+                dev: self.fd.dev_path(),
+                source,
+            })?;
+        let (handles, values) = props.as_props_and_values();
+        Ok(handles
+            .iter()
+            .zip(values.iter())
+            .find(|(handle, _)| **handle == size_prop)
+            .map(|(_, value)| *value as u32))
+    }
+
+    #[instrument(level = "debug", parent = &self.span, skip(self, lut))]
+    pub fn set_gamma_lut(&self, lut: &[[u16; 3]]) -> Result<(), Error> {
+        if !self.active.load(Ordering::SeqCst) {
+            return Err(Error::DeviceInactive);
+        }
+
+        let mut req = AtomicModeReq::new();
+        let blob = self.add_gamma_lut(&mut req, lut)?;
+        let res = self
+            .fd
+            .atomic_commit(AtomicCommitFlags::empty(), req)
+            .map_err(|source| Error::Access {
+                errmsg: "Failed to set gamma lut",
+                dev: self.fd.dev_path(),
+                source,
+            });
+
+        // the crtc holds its own reference to the blob
+        let _ = self.fd.destroy_property_blob(blob);
+        res
+    }
+
+    // Adds the `GAMMA_LUT` of the crtc to the request.
+    // Returns the id of the created blob, which needs to be destroyed after the commit.
+    pub(crate) fn add_gamma_lut(&self, req: &mut AtomicModeReq, lut: &[[u16; 3]]) -> Result<u64, Error> {
+        let mut lut = lut
+            .iter()
+            .map(|[red, green, blue]| drm_ffi::drm_color_lut {
+                red: *red,
+                green: *green,
+                blue: *blue,
+                reserved: 0,
+            })
+            .collect::<Vec<_>>();
+        let data = unsafe {
+            std::slice::from_raw_parts_mut(
+                lut.as_mut_ptr() as *mut u8,
+                std::mem::size_of::<drm_ffi::drm_color_lut>() * lut.len(),
+            )
+        };
+
+        let prop_mapping = self.prop_mapping.read().unwrap();
+        let prop = crtc_prop_handle(&prop_mapping, self.crtc, "GAMMA_LUT")?;
+        let blob =
+            drm_ffi::mode::create_property_blob(self.fd.device_fd().as_raw_fd(), data).map_err(|source| {
+                Error::Access {
+                    errmsg: "Failed to create gamma lut blob",
+                    dev: self.fd.dev_path(),
+                    source,
+                }
+            })?;
+        req.add_property(self.crtc, prop, property::Value::Blob(blob.blob_id as u64));
+        Ok(blob.blob_id as u64)
+    }
+
+    #[instrument(level = "debug", parent = &self.span, skip(self))]
+    pub fn set_dpms(&self, state: DpmsState) -> Result<(), Error> {
+        // atomic drivers have no notion of the dpms levels, the crtc is either active or not.
+        self.set_active(state == DpmsState::On)
+    }
+
+    #[instrument(level = "debug", parent = &self.span, skip(self))]
+    pub fn set_active(&self, active: bool) -> Result<(), Error> {
+        if !self.active.load(Ordering::SeqCst) {
+            return Err(Error::DeviceInactive);
+        }
+
+        let current = self.state.read().unwrap();
+        let prop_mapping = self.prop_mapping.read().unwrap();
+
+        let mut req = AtomicModeReq::new();
+        if active {
+            // make sure the current mode is used again
+            req.add_property(
+                self.crtc,
+                crtc_prop_handle(&prop_mapping, self.crtc, "MODE_ID")?,
+                current.blob.value(),
+            );
+        }
+        req.add_property(
+            self.crtc,
+            crtc_prop_handle(&prop_mapping, self.crtc, "ACTIVE")?,
+            property::Value::Boolean(active),
+        );
+
+        self.fd
+            .atomic_commit(AtomicCommitFlags::ALLOW_MODESET, req)
+            .map_err(|source| Error::Access {
+                errmsg: "Failed to set crtc active state",
+                dev: self.fd.dev_path(),
+                source,
+            })?;
+        self.crtc_active.store(active, Ordering::SeqCst);
+
+        Ok(())
+    }
+
+    pub fn is_crtc_active(&self) -> bool {
+        self.crtc_active.load(Ordering::SeqCst)
+    }
+
+    pub fn is_ready(&self) -> bool {
+        if !self.active.load(Ordering::SeqCst) {
+            return false;
+        }
+
+        // an empty test commit does not touch any state, but is still rejected without drm master
+        self.fd
+            .atomic_commit(AtomicCommitFlags::TEST_ONLY, AtomicModeReq::new())
+            .is_ok()
+    }
+
     pub fn commit_pending(&self) -> bool {
         *self.pending.read().unwrap() != *self.state.read().unwrap()
     }
 
+    pub fn discard_pending(&self) {
+        let current = self.state.read().unwrap();
+        *self.pending.write().unwrap() = current.clone();
+    }
+
     #[instrument(level = "trace", parent = &self.span, skip(self, planes))]
     pub fn test_state<'a>(
         &self,
@@ -493,7 +923,9 @@ impl AtomicDrmSurface {
         let mut removed = current_conns.difference(&pending_conns);
         let mut added = pending_conns.difference(&current_conns);
 
-        let req = self.build_request(&mut added, &mut removed, &*planes, Some(pending.blob))?;
+        let mut req = self.build_request(&mut added, &mut removed, &*planes, Some(pending.blob.value()))?;
+        self.add_scaling_mode(&mut req, &pending)?;
+        let _blobs = self.add_connector_properties(&mut req, &pending)?;
 
         let flags = if allow_modeset {
             AtomicCommitFlags::ALLOW_MODESET | AtomicCommitFlags::TEST_ONLY
@@ -512,6 +944,8 @@ impl AtomicDrmSurface {
         &self,
         planes: impl IntoIterator<Item = PlaneState<'a>>,
         event: bool,
+        nonblock: bool,
+        allow_modeset: bool,
     ) -> Result<(), Error> {
         if !self.active.load(Ordering::SeqCst) {
             return Err(Error::DeviceInactive);
@@ -520,10 +954,14 @@ impl AtomicDrmSurface {
         let planes = planes.into_iter().collect::<Vec<_>>();
         let mut current = self.state.write().unwrap();
         let mut used_planes = self.used_planes.lock().unwrap();
-        let pending = self.pending.write().unwrap();
+        let mut pending = self.pending.write().unwrap();
 
         debug!(current = ?*current, pending = ?*pending, ?planes, "Preparing Commit",);
 
+        if primary_plane_missing(self.plane, &used_planes, &planes) {
+            return Err(Error::MissingPrimaryPlane(self.crtc, self.plane));
+        }
+
         // we need the differences to know, which connectors need to change properties
         let current_conns = current.connectors.clone();
         let pending_conns = pending.connectors.clone();
@@ -552,68 +990,67 @@ impl AtomicDrmSurface {
 
         trace!("Testing screen config");
 
+        let modeset_flag = if allow_modeset {
+            AtomicCommitFlags::ALLOW_MODESET
+        } else {
+            AtomicCommitFlags::empty()
+        };
+
         // test the new config and return the request if it would be accepted by the driver.
-        let req = {
-            let req = self.build_request(&mut added, &mut removed, &*planes, Some(pending.blob))?;
+        // (blobs of restored connector properties only need to live until the commit went through)
+        let (req, staged_blobs) = {
+            let mut req =
+                self.build_request(&mut added, &mut removed, &*planes, Some(pending.blob.value()))?;
+            self.add_scaling_mode(&mut req, &pending)?;
+            let staged_blobs = self.add_connector_properties(&mut req, &pending)?;
 
             if let Err(err) = self
                 .fd
-                .atomic_commit(
-                    AtomicCommitFlags::ALLOW_MODESET | AtomicCommitFlags::TEST_ONLY,
-                    req.clone(),
-                )
-                .map_err(|_| Error::TestFailed(self.crtc))
+                .atomic_commit(modeset_flag | AtomicCommitFlags::TEST_ONLY, req.clone())
+                .map_err(|source| Error::TestFailed(self.crtc, Some(source)))
             {
                 warn!("New screen configuration invalid!:\n\t{:#?}\n\t{}\n", req, err);
 
                 return Err(err);
             } else {
-                if current.mode != pending.mode {
-                    if let Err(err) = self.fd.destroy_property_blob(current.blob.into()) {
-                        warn!("Failed to destory old mode property blob: {}", err);
-                    }
-                }
-
                 // new config
-                req
+                (req, staged_blobs)
             }
         };
 
         debug!("Setting screen: {:?}", req);
-        let result = self
-            .fd
-            .atomic_commit(
-                if event {
-                    // on the atomic api we can modeset and trigger a page_flip event on the same call!
-                    AtomicCommitFlags::PAGE_FLIP_EVENT | AtomicCommitFlags::ALLOW_MODESET
-                    // we also *should* not need to wait for completion, like with `set_crtc`,
-                    // because we have tested this exact commit already, so we do not expect any errors later down the line.
-                    //
-                    // but there is always an exception and `amdgpu` can fail in interesting ways with this flag set...
-                    // https://gitlab.freedesktop.org/drm/amd/-/issues?scope=all&utf8=%E2%9C%93&state=opened&search=drm_atomic_helper_wait_for_flip_done
-                    //
-                    // so we skip this flag:
-                    // AtomicCommitFlags::Nonblock,
-                } else {
-                    AtomicCommitFlags::ALLOW_MODESET
-                },
-                req,
-            )
-            .map_err(|source| Error::Access {
-                errmsg: "Error setting crtc",
-                dev: self.fd.dev_path(),
-                source,
-            });
+        let mut flags = if event {
+            // on the atomic api we can modeset and trigger a page_flip event on the same call!
+            AtomicCommitFlags::PAGE_FLIP_EVENT | modeset_flag
+            // we also *should* not need to wait for completion, like with `set_crtc`,
+            // because we have tested this exact commit already, so we do not expect any errors later down the line.
+            //
+            // but there is always an exception and `amdgpu` can fail in interesting ways with this flag set...
+            // https://gitlab.freedesktop.org/drm/amd/-/issues?scope=all&utf8=%E2%9C%93&state=opened&search=drm_atomic_helper_wait_for_flip_done
+            //
+            // so we skip this flag:
+            // AtomicCommitFlags::Nonblock,
+        } else {
+            modeset_flag
+        };
+        // ..unless explicitly requested by the caller
+        if nonblock {
+            flags |= AtomicCommitFlags::NONBLOCK;
+        }
+        let result = self.fd.atomic_commit(flags, req).map_err(|source| Error::Access {
+            errmsg: "Error setting crtc",
+            dev: self.fd.dev_path(),
+            source,
+        });
+
+        drop(staged_blobs);
 
         if result.is_ok() {
+            self.crtc_active.store(true, Ordering::SeqCst);
+            // restored properties are applied now and must not be set again on the next commit
+            pending.connector_properties.clear();
             *current = pending.clone();
-            for plane in planes.iter() {
-                if plane.config.is_some() {
-                    used_planes.insert(plane.handle);
-                } else {
-                    used_planes.remove(&plane.handle);
-                }
-            }
+            update_used_planes(&mut used_planes, &planes);
         }
 
         result
@@ -624,6 +1061,7 @@ impl AtomicDrmSurface {
         &self,
         planes: impl IntoIterator<Item = PlaneState<'a>>,
         event: bool,
+        asynchronous: bool,
     ) -> Result<(), Error> {
         if !self.active.load(Ordering::SeqCst) {
             return Err(Error::DeviceInactive);
@@ -632,43 +1070,113 @@ impl AtomicDrmSurface {
         let mut used_planes = self.used_planes.lock().unwrap();
         let planes = planes.into_iter().collect::<Vec<_>>();
 
+        if primary_plane_missing(self.plane, &used_planes, &planes) {
+            return Err(Error::MissingPrimaryPlane(self.crtc, self.plane));
+        }
+
+        // a powered down crtc needs a modeset to be activated again, using the current mode.
+        let reactivate = !self.crtc_active.load(Ordering::SeqCst);
+        let blob = if reactivate {
+            debug!("Re-activating crtc");
+            Some(self.state.read().unwrap().blob.value())
+        } else {
+            None
+        };
+
         // page flips work just like commits with fewer parameters..
-        let req = self.build_request(&mut [].iter(), &mut [].iter(), &*planes, None)?;
+        let req = self.build_request(&mut [].iter(), &mut [].iter(), &*planes, blob)?;
 
         // .. and without `AtomicCommitFlags::AllowModeset`.
         // If we would set anything here, that would require a modeset, this would fail,
         // indicating a problem in our assumptions.
         trace!(?planes, "Queueing page flip: {:?}", req);
-        let res = self
-            .fd
-            .atomic_commit(
-                if event {
-                    AtomicCommitFlags::PAGE_FLIP_EVENT | AtomicCommitFlags::NONBLOCK
-                } else {
-                    AtomicCommitFlags::NONBLOCK
-                },
-                req,
-            )
-            .map_err(|source| Error::Access {
-                errmsg: "Page flip commit failed",
-                dev: self.fd.dev_path(),
-                source,
-            });
+        let mut flags = if event {
+            AtomicCommitFlags::PAGE_FLIP_EVENT | AtomicCommitFlags::NONBLOCK
+        } else {
+            AtomicCommitFlags::NONBLOCK
+        };
+        if reactivate {
+            flags |= AtomicCommitFlags::ALLOW_MODESET;
+        } else if asynchronous {
+            flags |= AtomicCommitFlags::PAGE_FLIP_ASYNC;
+        }
+        let res = self.fd.atomic_commit(flags, req).map_err(|source| Error::Access {
+            errmsg: "Page flip commit failed",
+            dev: self.fd.dev_path(),
+            source,
+        });
 
         if res.is_ok() {
-            for plane in planes.iter() {
-                if plane.config.is_some() {
-                    used_planes.insert(plane.handle);
-                } else {
-                    used_planes.remove(&plane.handle);
-                }
-            }
+            self.crtc_active.store(true, Ordering::SeqCst);
+            update_used_planes(&mut used_planes, &planes);
         }
 
         res
     }
 
+    // returns a sync_file, that is already signalled, creating it on first use
+    fn signalled_fence(&self) -> Option<RawFd> {
+        let mut fence = self.signalled_fence.lock().unwrap();
+        if fence.is_none() {
+            if !matches!(
+                self.fd
+                    .device_fd()
+                    .cached_driver_capability(DriverCapability::SyncObj),
+                Ok(1)
+            ) {
+                return None;
+            }
+            let syncobj = self.fd.create_syncobj(true).ok()?;
+            let sync_file = self.fd.syncobj_to_fd(syncobj, true);
+            let _ = self.fd.destroy_syncobj(syncobj);
+            match sync_file {
+                Ok(sync_file) => *fence = Some(sync_file),
+                Err(err) => {
+                    debug!("Failed to export signalled sync_file: {}", err);
+                    return None;
+                }
+            }
+        }
+        fence.as_ref().map(|fence| fence.as_raw_fd())
+    }
+
     // If a mode is set a matching blob needs to be set (the inverse is not true)
+    // sets the scaling mode of the pending state on all pending connectors
+    fn add_scaling_mode(&self, req: &mut AtomicModeReq, pending: &State) -> Result<(), Error> {
+        let Some(mode) = pending.scaling_mode else {
+            return Ok(());
+        };
+
+        let prop_mapping = self.prop_mapping.read().unwrap();
+        for conn in pending.connectors.iter() {
+            let prop = conn_prop_handle(&prop_mapping, *conn, "scaling mode")?;
+            let value = enum_prop_value(&*self.fd, prop, mode.drm_name())?.ok_or(Error::UnknownProperty {
+                handle: (*conn).into(),
+                name: "scaling mode",
+            })?;
+            req.add_raw_property((*conn).into(), prop, value);
+        }
+
+        Ok(())
+    }
+
+    // adds the restored connector properties of the pending state
+    fn add_connector_properties(
+        &self,
+        req: &mut AtomicModeReq,
+        pending: &State,
+    ) -> Result<Vec<StagedBlob>, Error> {
+        let mut blobs = Vec::new();
+        for snapshot in pending.connector_properties.iter() {
+            let (values, staged) = snapshot.stage(self.fd.device_fd())?;
+            for (prop, value) in values {
+                req.add_raw_property(snapshot.connector().into(), prop, value);
+            }
+            blobs.extend(staged);
+        }
+        Ok(blobs)
+    }
+
     #[allow(clippy::too_many_arguments)]
     pub fn build_request<'a>(
         &self,
@@ -740,29 +1248,30 @@ impl AtomicDrmSurface {
                     property::Value::Framebuffer(Some(config.fb)),
                 );
 
+                let (src_x, src_y, src_w, src_h) = config.src_fixed();
                 req.add_property(
                     *handle,
                     plane_prop_handle(&prop_mapping, *handle, "SRC_X")?,
                     // these are 16.16. fixed point
-                    property::Value::UnsignedRange(to_fixed(config.src.loc.x) as u64),
+                    property::Value::UnsignedRange(src_x as u64),
                 );
                 req.add_property(
                     *handle,
                     plane_prop_handle(&prop_mapping, *handle, "SRC_Y")?,
                     // these are 16.16. fixed point
-                    property::Value::UnsignedRange(to_fixed(config.src.loc.y) as u64),
+                    property::Value::UnsignedRange(src_y as u64),
                 );
                 req.add_property(
                     *handle,
                     plane_prop_handle(&prop_mapping, *handle, "SRC_W")?,
                     // these are 16.16. fixed point
-                    property::Value::UnsignedRange(to_fixed(config.src.size.w) as u64),
+                    property::Value::UnsignedRange(src_w as u64),
                 );
                 req.add_property(
                     *handle,
                     plane_prop_handle(&prop_mapping, *handle, "SRC_H")?,
                     // these are 16.16. fixed point
-                    property::Value::UnsignedRange(to_fixed(config.src.size.h) as u64),
+                    property::Value::UnsignedRange(src_h as u64),
                 );
 
                 req.add_property(
@@ -814,6 +1323,39 @@ impl AtomicDrmSurface {
                         name: "alpha",
                     });
                 }
+                if let Some(blend_mode) = config.blend_mode {
+                    if let Ok(prop) = plane_prop_handle(&prop_mapping, *handle, "pixel blend mode") {
+                        let value = enum_prop_value(&*self.fd, prop, blend_mode.drm_name())?
+                            .ok_or(Error::UnsupportedPlaneConfiguration(*handle))?;
+                        req.add_raw_property((*handle).into(), prop, value);
+                    } else if blend_mode != PixelBlendMode::PreMultiplied {
+                        // without the property the kernel always blends pre-multiplied
+                        return Err(Error::UnknownProperty {
+                            handle: (*handle).into(),
+                            name: "pixel blend mode",
+                        });
+                    }
+                }
+                if let Some(filter) = config.scaling_filter {
+                    if let Ok(prop) = plane_prop_handle(&prop_mapping, *handle, "SCALING_FILTER") {
+                        let value = enum_prop_value(&*self.fd, prop, filter.drm_name())?
+                            .ok_or(Error::UnsupportedPlaneConfiguration(*handle))?;
+                        req.add_raw_property((*handle).into(), prop, value);
+                    } else if filter != ScalingFilter::Default {
+                        // without the property the driver always uses its default filter
+                        return Err(Error::UnknownProperty {
+                            handle: (*handle).into(),
+                            name: "SCALING_FILTER",
+                        });
+                    }
+                }
+                if config.skip_implicit_sync {
+                    if let Ok(prop) = plane_prop_handle(&prop_mapping, *handle, "IN_FENCE_FD") {
+                        if let Some(fence) = self.signalled_fence() {
+                            req.add_property(*handle, prop, property::Value::SignedRange(fence as i64));
+                        }
+                    }
+                }
                 if let Ok(prop) = plane_prop_handle(&prop_mapping, *handle, "FB_DAMAGE_CLIPS") {
                     if let Some(damage) = config.damage_clips.as_ref() {
                         req.add_property(*handle, prop, *damage);
@@ -901,6 +1443,57 @@ impl AtomicDrmSurface {
         Ok(req)
     }
 
+    #[instrument(level = "trace", parent = &self.span, skip(self))]
+    pub fn attach_writeback(
+        &self,
+        conn: connector::Handle,
+        fb: framebuffer::Handle,
+        out_fence: bool,
+    ) -> Result<Option<OwnedFd>, Error> {
+        if !self.active.load(Ordering::SeqCst) {
+            return Err(Error::DeviceInactive);
+        }
+
+        self.ensure_props_known(&[conn])?;
+        let prop_mapping = self.prop_mapping.read().unwrap();
+        let mut req = AtomicModeReq::new();
+        req.add_property(
+            conn,
+            conn_prop_handle(&prop_mapping, conn, "CRTC_ID")?,
+            property::Value::CRTC(Some(self.crtc)),
+        );
+        req.add_property(
+            conn,
+            conn_prop_handle(&prop_mapping, conn, "WRITEBACK_FB_ID")?,
+            property::Value::Framebuffer(Some(fb)),
+        );
+        // the kernel writes the fd of the fence into this, once the commit succeeded
+        let mut fence: RawFd = -1;
+        if out_fence {
+            req.add_raw_property(
+                conn.into(),
+                conn_prop_handle(&prop_mapping, conn, "WRITEBACK_OUT_FENCE_PTR")?,
+                &mut fence as *mut RawFd as u64,
+            );
+        }
+
+        // attaching the connector for the first time might require a modeset
+        self.fd
+            .atomic_commit(
+                AtomicCommitFlags::ALLOW_MODESET | AtomicCommitFlags::NONBLOCK,
+                req,
+            )
+            .map_err(|source| Error::Access {
+                errmsg: "Failed to attach writeback connector",
+                dev: self.fd.dev_path(),
+                source,
+            })?;
+        self.writeback_connectors.lock().unwrap().insert(conn);
+
+        // SAFETY: the kernel created a new fd for us, if any
+        Ok((fence >= 0).then(|| unsafe { OwnedFd::from_raw_fd(fence) }))
+    }
+
     // this helper function disconnects the plane.
     // this is mostly used to remove the contents quickly, e.g. on tty switch,
     // as other compositors might not make use of other planes,
@@ -1001,14 +1594,36 @@ impl AtomicDrmSurface {
         &self,
         fd: Option<&B>,
     ) -> Result<(), Error> {
+        let drm = self.fd.device_fd();
         *self.state.write().unwrap() = if let Some(fd) = fd {
-            State::current_state(fd, self.crtc, &mut self.prop_mapping.write().unwrap())?
+            State::current_state(
+                fd,
+                drm,
+                &self.live_blobs,
+                self.crtc,
+                &mut self.prop_mapping.write().unwrap(),
+            )?
         } else {
-            State::current_state(&*self.fd, self.crtc, &mut self.prop_mapping.write().unwrap())?
+            State::current_state(
+                &*self.fd,
+                drm,
+                &self.live_blobs,
+                self.crtc,
+                &mut self.prop_mapping.write().unwrap(),
+            )?
         };
         Ok(())
     }
 
+    /// Number of `MODE_ID` blobs created by this surface, which are neither referenced
+    /// by the current nor by the pending state.
+    #[cfg(feature = "backend_drm_debug")]
+    pub fn blob_leak_count(&self) -> usize {
+        let current = self.state.read().unwrap();
+        let pending = self.pending.read().unwrap();
+        blob_leak_count(&self.live_blobs, &current.blob, &pending.blob)
+    }
+
     pub(crate) fn device_fd(&self) -> &DrmDeviceFd {
         self.fd.device_fd()
     }
@@ -1057,7 +1672,8 @@ impl Drop for AtomicDrmSurface {
         let current = self.state.read().unwrap();
         let mut req = AtomicModeReq::new();
         let prop_mapping = self.prop_mapping.read().unwrap();
-        for conn in current.connectors.iter() {
+        let writeback_connectors = self.writeback_connectors.lock().unwrap();
+        for conn in current.connectors.iter().chain(writeback_connectors.iter()) {
             let prop = prop_mapping
                 .0
                 .get(conn)
@@ -1081,6 +1697,12 @@ impl Drop for AtomicDrmSurface {
 
         req.add_property(self.crtc, *active_prop, property::Value::Boolean(false));
         req.add_property(self.crtc, *mode_prop, property::Value::Unknown(0));
+        if self.restore_on_drop.load(Ordering::SeqCst) {
+            if let DrmDeviceInternal::Atomic(dev) = &*self.fd {
+                // overrides the properties set above for the previously used objects
+                dev.add_previous_crtc_state(&mut req, self.crtc);
+            }
+        }
         if let Err(err) = self.fd.atomic_commit(AtomicCommitFlags::ALLOW_MODESET, req) {
             warn!("Unable to disable connectors: {}", err);
         }
@@ -1138,14 +1760,36 @@ pub(crate) fn plane_prop_handle(
         .map(|x| *x)
 }
 
+// Looks up the raw value of the enum entry called `name` of an enum property
+pub(super) fn enum_prop_value(
+    fd: &(impl DevPath + ControlDevice),
+    prop: property::Handle,
+    name: &str,
+) -> Result<Option<property::RawValue>, Error> {
+    let info = fd.get_property(prop).map_err(|source| Error::Access {
+        errmsg: "Failed to get property info",
+        dev: fd.dev_path(),
+        source,
+    })?;
+    if let property::ValueType::Enum(values) = info.value_type() {
+        let (_, values) = values.values();
+        Ok(values
+            .iter()
+            .find(|value| value.name().to_str().map(|x| x == name).unwrap_or(false))
+            .map(|value| value.value()))
+    } else {
+        Ok(None)
+    }
+}
+
 #[inline]
-fn to_fixed<N: Coordinate>(n: N) -> u32 {
+pub(super) fn to_fixed<N: Coordinate>(n: N) -> u32 {
     f64::round(n.to_f64() * (1 << 16) as f64) as u32
 }
 
 bitflags::bitflags! {
     #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
-    struct DrmRotation: u8 {
+    pub(super) struct DrmRotation: u8 {
         const ROTATE_0      =   0b00000001;
         const ROTATE_90     =   0b00000010;
         const ROTATE_180    =   0b00000100;
@@ -1170,14 +1814,129 @@ impl From<Transform> for DrmRotation {
     }
 }
 
+impl DrmRotation {
+    /// Returns the [`Transform`]s, that can be set with the rotations and reflections in `self`
+    pub(super) fn transforms(self) -> Vec<Transform> {
+        [
+            Transform::Normal,
+            Transform::_90,
+            Transform::_180,
+            Transform::_270,
+            Transform::Flipped,
+            Transform::Flipped90,
+            Transform::Flipped180,
+            Transform::Flipped270,
+        ]
+        .into_iter()
+        .filter(|transform| self.contains(DrmRotation::from(*transform)))
+        .collect()
+    }
+
+    /// Maps a value of the `rotation` property back to a [`Transform`],
+    /// returns `None` if the value contains more than one rotation.
+    pub(super) fn to_transform(self) -> Option<Transform> {
+        let rotation = self
+            & (DrmRotation::ROTATE_0
+                | DrmRotation::ROTATE_90
+                | DrmRotation::ROTATE_180
+                | DrmRotation::ROTATE_270);
+        let mut steps = match rotation {
+            // `Transform::Flipped` is mapped without any rotation
+            x if x.is_empty() => 0,
+            DrmRotation::ROTATE_0 => 0,
+            DrmRotation::ROTATE_90 => 1,
+            DrmRotation::ROTATE_180 => 2,
+            DrmRotation::ROTATE_270 => 3,
+            _ => return None,
+        };
+
+        // reflecting along the x axis equals reflecting along the y axis rotated by 180 degrees
+        let mut flipped = false;
+        if self.contains(DrmRotation::REFLECT_Y) {
+            flipped = !flipped;
+        }
+        if self.contains(DrmRotation::REFLECT_X) {
+            flipped = !flipped;
+            steps += 2;
+        }
+
+        Some(match (flipped, steps % 4) {
+            (false, 0) => Transform::Normal,
+            (false, 1) => Transform::_90,
+            (false, 2) => Transform::_180,
+            (false, _) => Transform::_270,
+            (true, 0) => Transform::Flipped,
+            (true, 1) => Transform::Flipped90,
+            (true, 2) => Transform::Flipped180,
+            (true, _) => Transform::Flipped270,
+        })
+    }
+}
+
+// tracks the planes attached to the crtc after a successful commit.
+// planes without a config got cleared in the same request as the active ones.
+fn update_used_planes(used_planes: &mut HashSet<plane::Handle>, planes: &[PlaneState<'_>]) {
+    for plane in planes {
+        if plane.config.is_some() {
+            used_planes.insert(plane.handle);
+        } else {
+            used_planes.remove(&plane.handle);
+        }
+    }
+}
+
+#[cfg(any(test, feature = "backend_drm_debug"))]
+fn blob_leak_count(live_blobs: &AtomicUsize, current: &ModeBlob, pending: &ModeBlob) -> usize {
+    let referenced = [current, pending]
+        .into_iter()
+        .filter(|blob| blob.is_allocated())
+        .map(|blob| blob.value())
+        .collect::<HashSet<_>>()
+        .len();
+    live_blobs.load(Ordering::SeqCst).saturating_sub(referenced)
+}
+
+// Whether committing `planes` would leave the primary plane without a framebuffer,
+// planes missing from the update keep their previous configuration.
+fn primary_plane_missing(
+    primary: plane::Handle,
+    used_planes: &HashSet<plane::Handle>,
+    planes: &[PlaneState<'_>],
+) -> bool {
+    match planes.iter().find(|state| state.handle == primary) {
+        Some(state) => state.config.is_none(),
+        None => !used_planes.contains(&primary),
+    }
+}
+
 #[cfg(test)]
 mod test {
     use crate::{
         backend::drm::surface::atomic::to_fixed,
-        utils::{Physical, Rectangle},
+        utils::{Physical, Rectangle, Transform},
     };
 
-    use super::AtomicDrmSurface;
+    use std::collections::{HashMap, HashSet};
+    use std::num::NonZeroU32;
+    use std::os::unix::io::OwnedFd;
+    use std::sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex, RwLock,
+    };
+
+    use drm::control::{atomic::AtomicModeReq, crtc, property};
+
+    use super::{
+        blob_leak_count, primary_plane_missing, update_used_planes, AtomicDrmSurface, DrmRotation, ModeBlob,
+        PlaneState, State,
+    };
+    use crate::backend::drm::device::{
+        atomic::{AtomicDrmDevice, Mapping},
+        DrmDeviceInternal,
+    };
+    use crate::backend::drm::surface::test::{config, damage_clips, plane};
+    use crate::backend::drm::{cvt_mode, DrmDeviceFd};
+    use crate::utils::DeviceFd;
 
     fn is_send<S: Send>() {}
 
@@ -1199,4 +1958,265 @@ mod test {
         let fixed = to_fixed(geometry.size.w) as u64;
         assert_eq!(125835674, fixed);
     }
+
+    #[test]
+    fn rotation_to_transform() {
+        for transform in [
+            Transform::Normal,
+            Transform::_90,
+            Transform::_180,
+            Transform::_270,
+            Transform::Flipped,
+            Transform::Flipped90,
+            Transform::Flipped180,
+            Transform::Flipped270,
+        ] {
+            assert_eq!(DrmRotation::from(transform).to_transform(), Some(transform));
+        }
+
+        assert_eq!(
+            (DrmRotation::ROTATE_0 | DrmRotation::REFLECT_X).to_transform(),
+            Some(Transform::Flipped180)
+        );
+        assert_eq!(
+            (DrmRotation::ROTATE_90 | DrmRotation::REFLECT_X | DrmRotation::REFLECT_Y).to_transform(),
+            Some(Transform::_270)
+        );
+        assert_eq!(
+            (DrmRotation::ROTATE_0 | DrmRotation::ROTATE_90).to_transform(),
+            None
+        );
+    }
+
+    #[test]
+    fn supported_transforms() {
+        assert_eq!(
+            (DrmRotation::ROTATE_0 | DrmRotation::ROTATE_180).transforms(),
+            vec![Transform::Normal, Transform::_180]
+        );
+        assert_eq!(
+            DrmRotation::all().transforms(),
+            vec![
+                Transform::Normal,
+                Transform::_90,
+                Transform::_180,
+                Transform::_270,
+                Transform::Flipped,
+                Transform::Flipped90,
+                Transform::Flipped180,
+                Transform::Flipped270,
+            ]
+        );
+        // reflections are set along the y axis
+        assert_eq!(
+            (DrmRotation::ROTATE_0 | DrmRotation::ROTATE_90 | DrmRotation::REFLECT_X).transforms(),
+            vec![Transform::Normal, Transform::_90]
+        );
+    }
+
+    #[test]
+    fn cleared_planes_in_same_commit() {
+        let mut used_planes = HashSet::from([plane(1), plane(2), plane(3)]);
+
+        // update the primary plane while clearing both overlays
+        update_used_planes(
+            &mut used_planes,
+            &[
+                PlaneState {
+                    handle: plane(1),
+                    config: Some(config(10)),
+                },
+                PlaneState {
+                    handle: plane(2),
+                    config: None,
+                },
+                PlaneState {
+                    handle: plane(3),
+                    config: None,
+                },
+            ],
+        );
+        assert_eq!(used_planes, HashSet::from([plane(1)]));
+
+        // clearing a plane and attaching another one in the same commit
+        update_used_planes(
+            &mut used_planes,
+            &[
+                PlaneState {
+                    handle: plane(1),
+                    config: None,
+                },
+                PlaneState {
+                    handle: plane(4),
+                    config: Some(config(40)),
+                },
+            ],
+        );
+        assert_eq!(used_planes, HashSet::from([plane(4)]));
+    }
+
+    #[test]
+    fn missing_primary_plane() {
+        let overlay = PlaneState {
+            handle: plane(2),
+            config: Some(config(20)),
+        };
+        let primary = |config| PlaneState {
+            handle: plane(1),
+            config,
+        };
+
+        // the primary plane has not been used yet
+        let mut used_planes = HashSet::new();
+        assert!(primary_plane_missing(plane(1), &used_planes, &[overlay.clone()]));
+        assert!(!primary_plane_missing(
+            plane(1),
+            &used_planes,
+            &[primary(Some(config(10))), overlay.clone()]
+        ));
+
+        // planes missing from the update keep their framebuffer
+        used_planes.insert(plane(1));
+        assert!(!primary_plane_missing(plane(1), &used_planes, &[overlay.clone()]));
+        assert!(primary_plane_missing(
+            plane(1),
+            &used_planes,
+            &[primary(None), overlay]
+        ));
+    }
+
+    // `AtomicModeReq` has no accessors, so the properties are read back from its debug representation
+    fn request_properties(req: &AtomicModeReq) -> Vec<(u32, u64)> {
+        let debug = format!("{:?}", req);
+        let list = |field: &str| {
+            let start = debug.find(&format!("{}: [", field)).unwrap() + field.len() + 3;
+            let end = start + debug[start..].find(']').unwrap();
+            debug[start..end]
+                .split(|c: char| !c.is_ascii_digit())
+                .filter(|number| !number.is_empty())
+                .map(|number| number.parse::<u64>().unwrap())
+                .collect::<Vec<_>>()
+        };
+        list("props")
+            .into_iter()
+            .map(|prop| prop as u32)
+            .zip(list("values"))
+            .collect()
+    }
+
+    #[test]
+    fn shared_damage_blob_request() {
+        let fd = OwnedFd::from(std::fs::File::open("/dev/null").unwrap());
+        let drm = DrmDeviceFd::new(DeviceFd::from(fd));
+        // inactive, so dropping the surface does not touch the device
+        let active = Arc::new(AtomicBool::new(false));
+        let crtc = crtc::Handle::from(NonZeroU32::new(100).unwrap());
+        let prop = |id: u32| property::Handle::from(NonZeroU32::new(id).unwrap());
+
+        // `FB_DAMAGE_CLIPS` of plane `n` is property `n * 100`, all other properties follow it
+        let mut mapping: Mapping = (HashMap::new(), HashMap::new(), HashMap::new());
+        mapping
+            .1
+            .insert(crtc, HashMap::from([(String::from("ACTIVE"), prop(1))]));
+        for id in [1, 2] {
+            let props = [
+                "FB_DAMAGE_CLIPS",
+                "CRTC_ID",
+                "FB_ID",
+                "SRC_X",
+                "SRC_Y",
+                "SRC_W",
+                "SRC_H",
+                "CRTC_X",
+                "CRTC_Y",
+                "CRTC_W",
+                "CRTC_H",
+            ];
+            mapping.2.insert(
+                plane(id),
+                props
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, name)| (String::from(name), prop(id * 100 + i as u32)))
+                    .collect(),
+            );
+        }
+
+        let live_blobs = Arc::new(AtomicUsize::new(0));
+        let state = || State {
+            mode: cvt_mode(64, 64, 60.0, false),
+            blob: ModeBlob::from_value(&drm, property::Value::Unknown(0), &live_blobs),
+            connectors: HashSet::new(),
+            scaling_mode: None,
+            connector_properties: Vec::new(),
+        };
+        let surface = AtomicDrmSurface {
+            fd: Arc::new(DrmDeviceInternal::Atomic(AtomicDrmDevice::dummy(
+                drm.clone(),
+                active.clone(),
+            ))),
+            active,
+            crtc,
+            plane: plane(1),
+            used_planes: Mutex::new(HashSet::new()),
+            prop_mapping: RwLock::new(mapping),
+            state: RwLock::new(state()),
+            pending: RwLock::new(state()),
+            live_blobs: live_blobs.clone(),
+            crtc_active: AtomicBool::new(true),
+            signalled_fence: Mutex::new(None),
+            restore_on_drop: AtomicBool::new(false),
+            writeback_connectors: Mutex::new(HashSet::new()),
+            span: tracing::Span::none(),
+        };
+
+        // two planes scanning out the same region, referencing one damage blob in the same commit
+        let clips = damage_clips(7);
+        let planes = [1, 2].map(|id| {
+            let mut config = config(id * 10);
+            config.damage_clips = Some(clips.blob());
+            PlaneState {
+                handle: plane(id),
+                config: Some(config),
+            }
+        });
+        let req = surface
+            .build_request(&mut [].iter(), &mut [].iter(), &planes, None)
+            .unwrap();
+
+        let damage = request_properties(&req)
+            .into_iter()
+            .filter(|(prop, _)| prop % 100 == 0)
+            .collect::<Vec<_>>();
+        assert_eq!(damage, vec![(100, 7), (200, 7)]);
+    }
+
+    #[test]
+    fn mode_blobs_not_leaked() {
+        // destroying the blobs fails without a drm device, which is only logged
+        let fd = OwnedFd::from(std::fs::File::open("/dev/null").unwrap());
+        let drm = DrmDeviceFd::new(DeviceFd::from(fd));
+        let live_blobs = Arc::new(AtomicUsize::new(0));
+
+        let mut current = ModeBlob::from_value(&drm, property::Value::Blob(1), &live_blobs);
+        let mut pending = current.clone();
+        assert_eq!(live_blobs.load(Ordering::SeqCst), 1);
+
+        // `use_mode` replaces the pending blob
+        for id in 2..5 {
+            pending = ModeBlob::from_value(&drm, property::Value::Blob(id), &live_blobs);
+            assert_eq!(blob_leak_count(&live_blobs, &current, &pending), 0);
+        }
+        assert_eq!(live_blobs.load(Ordering::SeqCst), 2);
+
+        // a commit makes the pending blob the current one
+        current = pending.clone();
+        assert_eq!(blob_leak_count(&live_blobs, &current, &pending), 0);
+        assert_eq!(live_blobs.load(Ordering::SeqCst), 1);
+
+        // surfaces without a mode do not allocate a blob
+        pending = ModeBlob::from_value(&drm, property::Value::Unknown(0), &live_blobs);
+        assert_eq!(blob_leak_count(&live_blobs, &current, &pending), 0);
+        assert_eq!(live_blobs.load(Ordering::SeqCst), 1);
+    }
 }
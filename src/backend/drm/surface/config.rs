@@ -0,0 +1,130 @@
+//! Persistable snapshots of the configuration of a [`DrmSurface`]
+
+use std::convert::TryFrom;
+
+use drm::control::{connector, Device as ControlDevice, Mode};
+use tracing::warn;
+
+use super::DrmSurface;
+use crate::{
+    backend::drm::{error::Error, mode_refresh_mhz},
+    utils::DevPath,
+    utils::Transform,
+};
+
+/// Snapshot of the configuration of a [`DrmSurface`]
+///
+/// Connectors and modes are identified by their names instead of their handles,
+/// so the configuration can be persisted (e.g. using the `serde` feature) and
+/// restored in another session via [`OutputConfig::apply`].
+///
+/// The position of the output in the global compositor space is not known to the
+/// [`DrmSurface`] and needs to be stored alongside, if necessary.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OutputConfig {
+    /// Name of the mode, e.g. `1920x1080`
+    pub mode_name: String,
+    /// Vertical refresh rate of the mode in millihertz, see [`mode_refresh_mhz`]
+    pub refresh: u32,
+    /// Names of the connectors, e.g. `DP-1`
+    pub connectors: Vec<String>,
+    /// Transform of the output
+    ///
+    /// This is not applied to the [`DrmSurface`] and is [`Transform::Normal`] for new snapshots.
+    pub transform: Transform,
+}
+
+impl TryFrom<&DrmSurface> for OutputConfig {
+    type Error = Error;
+
+    /// Creates a snapshot of the pending state of the surface
+    fn try_from(surface: &DrmSurface) -> Result<Self, Error> {
+        let mode = surface.pending_mode();
+        let connectors = surface
+            .pending_connectors()
+            .into_iter()
+            .map(|conn| connector_name(surface, conn))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(OutputConfig {
+            mode_name: mode.name().to_string_lossy().into_owned(),
+            refresh: mode_refresh_mhz(&mode),
+            connectors,
+            transform: Transform::Normal,
+        })
+    }
+}
+
+impl OutputConfig {
+    /// Restores the stored connectors and mode as the pending state of the given surface
+    ///
+    /// Connectors not existing on the device anymore are skipped.
+    /// The changes are applied on the next [`commit`](DrmSurface::commit).
+    pub fn apply(&self, surface: &DrmSurface) -> Result<(), Error> {
+        let resources = surface.resource_handles().map_err(|source| Error::Access {
+            errmsg: "Error loading resource handles",
+            dev: surface.dev_path(),
+            source,
+        })?;
+
+        let mut connectors = Vec::new();
+        let mut modes = Vec::new();
+        for conn in resources.connectors() {
+            if !self.connectors.contains(&connector_name(surface, *conn)?) {
+                continue;
+            }
+            let info = surface
+                .get_connector(*conn, false)
+                .map_err(|source| Error::Access {
+                    errmsg: "Error loading connector info",
+                    dev: surface.dev_path(),
+                    source,
+                })?;
+            connectors.push(*conn);
+            modes.extend_from_slice(info.modes());
+        }
+
+        if connectors.len() != self.connectors.len() {
+            warn!(
+                "Not all connectors of {:?} are available, using {:?}",
+                self.connectors, connectors
+            );
+        }
+        if connectors.is_empty() {
+            return Err(Error::SurfaceWithoutConnectors(surface.crtc()));
+        }
+
+        let mode = modes
+            .into_iter()
+            .find(|mode| self.matches(mode))
+            .ok_or_else(|| Error::ModeNotFound {
+                name: self.mode_name.clone(),
+                refresh: self.refresh,
+            })?;
+
+        // the mode needs to be supported by the pending connectors and vice versa,
+        // so try both orders.
+        if surface.use_mode(mode).is_ok() {
+            surface.set_connectors(&connectors)
+        } else {
+            surface.set_connectors(&connectors)?;
+            surface.use_mode(mode)
+        }
+    }
+
+    fn matches(&self, mode: &Mode) -> bool {
+        mode_refresh_mhz(mode) == self.refresh && mode.name().to_string_lossy() == self.mode_name
+    }
+}
+
+fn connector_name(surface: &DrmSurface, conn: connector::Handle) -> Result<String, Error> {
+    let info = surface
+        .get_connector(conn, false)
+        .map_err(|source| Error::Access {
+            errmsg: "Error loading connector info",
+            dev: surface.dev_path(),
+            source,
+        })?;
+    Ok(format!("{}-{}", info.interface().as_str(), info.interface_id()))
+}
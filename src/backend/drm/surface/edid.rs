@@ -0,0 +1,298 @@
+//! Minimal parsing of the EDID blob of a connector
+
+use super::HdrLuminance;
+
+const HEADER: [u8; 8] = [0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00];
+const BASE_BLOCK_SIZE: usize = 128;
+// offsets of the four 18 byte display descriptors of the base block
+const DESCRIPTORS: [usize; 4] = [54, 72, 90, 108];
+const RANGE_LIMITS_TAG: u8 = 0xfd;
+const CTA_EXTENSION_TAG: u8 = 0x02;
+const EXTENDED_DATA_BLOCK_TAG: u8 = 0x07;
+const HDR_STATIC_METADATA_TAG: u8 = 0x06;
+const VIDEO_DATA_BLOCK_TAG: u8 = 0x02;
+const YCBCR420_VIDEO_DATA_TAG: u8 = 0x0e;
+const YCBCR420_CAPABILITY_MAP_TAG: u8 = 0x0f;
+
+const AR_16_9: u32 = drm_ffi::DRM_MODE_FLAG_PIC_AR_16_9;
+const AR_64_27: u32 = drm_ffi::DRM_MODE_FLAG_PIC_AR_64_27;
+const AR_256_135: u32 = drm_ffi::DRM_MODE_FLAG_PIC_AR_256_135;
+
+// size, refresh rate and picture aspect ratio of the CTA-861 video formats,
+// that are commonly offered with YCbCr 4:2:0
+const YCBCR420_VICS: &[(u8, (u16, u16), u32, u32)] = &[
+    (93, (3840, 2160), 24, AR_16_9),
+    (94, (3840, 2160), 25, AR_16_9),
+    (95, (3840, 2160), 30, AR_16_9),
+    (96, (3840, 2160), 50, AR_16_9),
+    (97, (3840, 2160), 60, AR_16_9),
+    (98, (4096, 2160), 24, AR_256_135),
+    (99, (4096, 2160), 25, AR_256_135),
+    (100, (4096, 2160), 30, AR_256_135),
+    (101, (4096, 2160), 50, AR_256_135),
+    (102, (4096, 2160), 60, AR_256_135),
+    (103, (3840, 2160), 24, AR_64_27),
+    (104, (3840, 2160), 25, AR_64_27),
+    (105, (3840, 2160), 30, AR_64_27),
+    (106, (3840, 2160), 50, AR_64_27),
+    (107, (3840, 2160), 60, AR_64_27),
+    (114, (3840, 2160), 48, AR_16_9),
+    (115, (4096, 2160), 48, AR_256_135),
+    (116, (3840, 2160), 48, AR_64_27),
+    (117, (3840, 2160), 100, AR_16_9),
+    (118, (3840, 2160), 120, AR_16_9),
+    (119, (3840, 2160), 100, AR_64_27),
+    (120, (3840, 2160), 120, AR_64_27),
+    (194, (7680, 4320), 24, AR_16_9),
+    (195, (7680, 4320), 25, AR_16_9),
+    (196, (7680, 4320), 30, AR_16_9),
+    (197, (7680, 4320), 48, AR_16_9),
+    (198, (7680, 4320), 50, AR_16_9),
+    (199, (7680, 4320), 60, AR_16_9),
+    (200, (7680, 4320), 100, AR_16_9),
+    (201, (7680, 4320), 120, AR_16_9),
+    (218, (4096, 2160), 100, AR_256_135),
+    (219, (4096, 2160), 120, AR_256_135),
+];
+
+/// Returns the 18 byte display descriptors of the base block
+fn descriptors(edid: &[u8]) -> impl Iterator<Item = &[u8]> {
+    let valid = edid.len() >= BASE_BLOCK_SIZE && edid[..8] == HEADER;
+    DESCRIPTORS
+        .iter()
+        .filter(move |_| valid)
+        .map(move |offset| &edid[*offset..*offset + 18])
+        // detailed timing descriptors start with a non-zero pixel clock
+        .filter(|descriptor| descriptor[0..3] == [0, 0, 0])
+}
+
+/// Parses the minimum and maximum vertical refresh rate in Hz from the display range limits descriptor
+pub(super) fn vrr_range(edid: &[u8]) -> Option<(u32, u32)> {
+    let descriptor = descriptors(edid).find(|descriptor| descriptor[3] == RANGE_LIMITS_TAG)?;
+
+    // EDID 1.4 allows rates above 255 Hz via an offset
+    let flags = descriptor[4];
+    let min = descriptor[5] as u32 + if flags & 0b01 != 0 { 255 } else { 0 };
+    let max = descriptor[6] as u32 + if flags & 0b10 != 0 { 255 } else { 0 };
+    if min == 0 || max < min {
+        return None;
+    }
+    Some((min, max))
+}
+
+/// Parses the maximum pixel clock in kHz from the display range limits descriptor
+pub(super) fn max_pixel_clock(edid: &[u8]) -> Option<u32> {
+    let descriptor = descriptors(edid).find(|descriptor| descriptor[3] == RANGE_LIMITS_TAG)?;
+
+    // given in multiples of 10 MHz
+    match descriptor[9] {
+        0 => None,
+        clock => Some(clock as u32 * 10_000),
+    }
+}
+
+/// Returns the tag and payload of the data blocks of all CTA-861 extension blocks
+fn cta_data_blocks(edid: &[u8]) -> impl Iterator<Item = (u8, &[u8])> {
+    let valid = edid.len() >= BASE_BLOCK_SIZE && edid[..8] == HEADER;
+    let extensions = if valid { edid[126] as usize } else { 0 };
+    edid.chunks_exact(BASE_BLOCK_SIZE)
+        .skip(1)
+        .take(extensions)
+        .filter(|block| block[0] == CTA_EXTENSION_TAG)
+        .flat_map(|block| {
+            // the data block collection ends, where the detailed timing descriptors start
+            let end = (block[2] as usize).clamp(4, BASE_BLOCK_SIZE - 1);
+            let mut data = &block[4..end];
+            std::iter::from_fn(move || {
+                let (&header, rest) = data.split_first()?;
+                let len = (header & 0x1f) as usize;
+                if len > rest.len() {
+                    return None;
+                }
+                let (payload, rest) = rest.split_at(len);
+                data = rest;
+                Some((header >> 5, payload))
+            })
+        })
+}
+
+/// Returns the payload of all extended data blocks, starting with the extended tag
+fn cta_extended_data_blocks(edid: &[u8]) -> impl Iterator<Item = &[u8]> {
+    cta_data_blocks(edid)
+        .filter(|(tag, _)| *tag == EXTENDED_DATA_BLOCK_TAG)
+        .map(|(_, payload)| payload)
+}
+
+/// Parses the desired content luminance from the HDR static metadata data block
+pub(super) fn hdr_luminance(edid: &[u8]) -> Option<HdrLuminance> {
+    let block =
+        cta_extended_data_blocks(edid).find(|payload| payload.first() == Some(&HDR_STATIC_METADATA_TAG))?;
+
+    // the luminance values are optional, the block ends after the last present one
+    let max_code = block.get(3).copied().filter(|code| *code != 0);
+    let max_frame_average_code = block.get(4).copied().filter(|code| *code != 0);
+    let min_code = block.get(5).copied();
+
+    let max_luminance = max_code.map(|code| 50.0 * 2f32.powf(code as f32 / 32.0));
+    let max_frame_average_luminance = max_frame_average_code.map(|code| 50.0 * 2f32.powf(code as f32 / 32.0));
+    // the minimum is defined relative to the maximum
+    let min_luminance = min_code
+        .zip(max_luminance)
+        .map(|(code, max)| max * (code as f32 / 255.0).powi(2) / 100.0);
+
+    Some(HdrLuminance {
+        max_luminance,
+        max_frame_average_luminance,
+        min_luminance,
+    })
+}
+
+/// Returns the video format codes of the short video descriptors, that can be driven using YCbCr 4:2:0
+fn ycbcr420_vics(edid: &[u8]) -> Vec<u8> {
+    // codes 129 to 192 mark the native formats with the upper bit
+    let vic = |svd: u8| {
+        if (129..=192).contains(&svd) {
+            svd & 0x7f
+        } else {
+            svd
+        }
+    };
+
+    let svds = cta_data_blocks(edid)
+        .filter(|(tag, _)| *tag == VIDEO_DATA_BLOCK_TAG)
+        .flat_map(|(_, payload)| payload.iter().copied())
+        .collect::<Vec<_>>();
+
+    let mut vics = Vec::new();
+    for block in cta_extended_data_blocks(edid) {
+        match block.split_first() {
+            // formats only supported with YCbCr 4:2:0
+            Some((&YCBCR420_VIDEO_DATA_TAG, svds)) => vics.extend(svds.iter().copied().map(vic)),
+            // formats of the video data blocks, that also support YCbCr 4:2:0
+            Some((&YCBCR420_CAPABILITY_MAP_TAG, map)) => {
+                vics.extend(
+                    svds.iter()
+                        .enumerate()
+                        // an empty map applies to all formats
+                        .filter(|(i, _)| {
+                            map.is_empty() || map.get(i / 8).map_or(false, |byte| byte & (1 << (i % 8)) != 0)
+                        })
+                        .map(|(_, svd)| vic(*svd)),
+                );
+            }
+            _ => {}
+        }
+    }
+    vics
+}
+
+/// Returns whether the display supports YCbCr 4:2:0 for a video format of the given size and refresh rate
+///
+/// `aspect_ratio` are the `DRM_MODE_FLAG_PIC_AR_*` flags of the mode, `DRM_MODE_FLAG_PIC_AR_NONE` matches
+/// formats of any picture aspect ratio.
+pub(super) fn supports_ycbcr420(edid: &[u8], size: (u16, u16), refresh: u32, aspect_ratio: u32) -> bool {
+    let vics = ycbcr420_vics(edid);
+    YCBCR420_VICS
+        .iter()
+        .any(|(vic, vic_size, vic_refresh, vic_aspect_ratio)| {
+            *vic_size == size
+                && *vic_refresh == refresh
+                && (aspect_ratio == drm_ffi::DRM_MODE_FLAG_PIC_AR_NONE || *vic_aspect_ratio == aspect_ratio)
+                && vics.contains(vic)
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        hdr_luminance, max_pixel_clock, supports_ycbcr420, vrr_range, AR_16_9, AR_256_135, AR_64_27, HEADER,
+    };
+
+    fn edid_with_range(flags: u8, min: u8, max: u8) -> Vec<u8> {
+        let mut edid = vec![0; 128];
+        edid[..8].copy_from_slice(&HEADER);
+        // detailed timing descriptor
+        edid[54] = 0x02;
+        edid[55] = 0x3a;
+        // display range limits descriptor
+        edid[72..79].copy_from_slice(&[0, 0, 0, 0xfd, flags, min, max]);
+        edid
+    }
+
+    #[test]
+    fn parse_vrr_range() {
+        assert_eq!(vrr_range(&edid_with_range(0, 48, 144)), Some((48, 144)));
+        assert_eq!(vrr_range(&edid_with_range(0b10, 48, 5)), Some((48, 260)));
+        assert_eq!(vrr_range(&edid_with_range(0, 0, 0)), None);
+        assert_eq!(vrr_range(&edid_with_range(0, 48, 144)[..64]), None);
+    }
+
+    #[test]
+    fn parse_max_pixel_clock() {
+        let mut edid = edid_with_range(0, 48, 144);
+        assert_eq!(max_pixel_clock(&edid), None);
+        edid[81] = 60;
+        assert_eq!(max_pixel_clock(&edid), Some(600_000));
+    }
+
+    fn edid_with_hdr_metadata(luminance: &[u8]) -> Vec<u8> {
+        let mut edid = vec![0; 256];
+        edid[..8].copy_from_slice(&HEADER);
+        edid[126] = 1;
+        // CTA-861 extension with a video data block and the HDR static metadata block
+        let cta = &mut edid[128..];
+        cta[0] = 0x02;
+        cta[1] = 0x03;
+        let mut blocks = vec![0x42, 0x10, 0x04];
+        blocks.extend_from_slice(&[0xe0 | (3 + luminance.len() as u8), 0x06, 0x05, 0x01]);
+        blocks.extend_from_slice(luminance);
+        cta[2] = 4 + blocks.len() as u8;
+        cta[4..4 + blocks.len()].copy_from_slice(&blocks);
+        edid
+    }
+
+    #[test]
+    fn parse_hdr_luminance() {
+        let luminance = hdr_luminance(&edid_with_hdr_metadata(&[96, 64, 26])).unwrap();
+        assert_eq!(luminance.max_luminance, Some(400.0));
+        assert_eq!(luminance.max_frame_average_luminance, Some(200.0));
+        let min = luminance.min_luminance.unwrap();
+        assert!((min - 0.0416).abs() < 0.001, "{}", min);
+
+        let luminance = hdr_luminance(&edid_with_hdr_metadata(&[])).unwrap();
+        assert_eq!(luminance.max_luminance, None);
+        assert_eq!(luminance.min_luminance, None);
+
+        assert!(hdr_luminance(&edid_with_hdr_metadata(&[96])[..128]).is_none());
+    }
+
+    #[test]
+    fn parse_ycbcr420() {
+        let mut edid = vec![0; 256];
+        edid[..8].copy_from_slice(&HEADER);
+        edid[126] = 1;
+        let cta = &mut edid[128..];
+        cta[0] = 0x02;
+        cta[1] = 0x03;
+        // video data block: 1080p60 (native), 2160p60, 2160p50
+        let mut blocks = vec![0x43, 0x90, 0x61, 0x60];
+        // capability map: only the second format also supports 4:2:0
+        blocks.extend_from_slice(&[0xe2, 0x0f, 0b010]);
+        // 4:2:0 video data block: 4096x2160p60 only with 4:2:0
+        blocks.extend_from_slice(&[0xe2, 0x0e, 0x66]);
+        cta[2] = 4 + blocks.len() as u8;
+        cta[4..4 + blocks.len()].copy_from_slice(&blocks);
+
+        let none = drm_ffi::DRM_MODE_FLAG_PIC_AR_NONE;
+        assert!(supports_ycbcr420(&edid, (3840, 2160), 60, none));
+        assert!(!supports_ycbcr420(&edid, (3840, 2160), 50, none));
+        assert!(supports_ycbcr420(&edid, (4096, 2160), 60, none));
+        assert!(!supports_ycbcr420(&edid, (1920, 1080), 60, none));
+        assert!(!supports_ycbcr420(&edid[..128], (3840, 2160), 60, none));
+
+        // only the 16:9 variant of 2160p60 (vic 97) is listed, not the 64:27 one (vic 107)
+        assert!(supports_ycbcr420(&edid, (3840, 2160), 60, AR_16_9));
+        assert!(!supports_ycbcr420(&edid, (3840, 2160), 60, AR_64_27));
+        assert!(supports_ycbcr420(&edid, (4096, 2160), 60, AR_256_135));
+    }
+}
@@ -0,0 +1,128 @@
+//! Helpers to describe gamma curves of a [`DrmSurface`](super::DrmSurface)
+
+/// Linear segment of a gamma curve, see [`DrmSurface::set_gamma_curve`](super::DrmSurface::set_gamma_curve)
+///
+/// Inputs and outputs are normalized to `0.0..=1.0`. Inputs inside `start..=end` are
+/// linearly interpolated between `start_value` and `end_value`, inputs not covered by any
+/// segment are passed through unchanged. If segments overlap, the first one wins.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GammaCurveSegment {
+    /// Start of the input range
+    pub start: f64,
+    /// End of the input range
+    pub end: f64,
+    /// Red, green and blue output at the start of the range
+    pub start_value: [f64; 3],
+    /// Red, green and blue output at the end of the range
+    pub end_value: [f64; 3],
+}
+
+impl GammaCurveSegment {
+    fn value(&self, x: f64) -> Option<[f64; 3]> {
+        if x < self.start || x > self.end {
+            return None;
+        }
+
+        let t = if self.end > self.start {
+            (x - self.start) / (self.end - self.start)
+        } else {
+            0.0
+        };
+        let mut value = [0.0; 3];
+        for (channel, value) in value.iter_mut().enumerate() {
+            *value = self.start_value[channel] + (self.end_value[channel] - self.start_value[channel]) * t;
+        }
+        Some(value)
+    }
+}
+
+/// Gamma ramp of a crtc with one entry per channel and step
+///
+/// All channels need to have the same length.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct GammaRamp {
+    /// Red channel
+    pub red: Vec<u16>,
+    /// Green channel
+    pub green: Vec<u16>,
+    /// Blue channel
+    pub blue: Vec<u16>,
+}
+
+impl GammaRamp {
+    pub(in crate::backend::drm) fn lut(&self) -> Vec<[u16; 3]> {
+        self.red
+            .iter()
+            .zip(self.green.iter())
+            .zip(self.blue.iter())
+            .map(|((red, green), blue)| [*red, *green, *blue])
+            .collect()
+    }
+
+    // Parses the data of a `GAMMA_LUT` blob, an array of `drm_color_lut`s
+    pub(super) fn from_lut_blob(data: &[u8]) -> GammaRamp {
+        let mut ramp = GammaRamp::default();
+        for entry in data.chunks_exact(std::mem::size_of::<drm_ffi::drm_color_lut>()) {
+            let channel = |i: usize| u16::from_ne_bytes([entry[i * 2], entry[i * 2 + 1]]);
+            ramp.red.push(channel(0));
+            ramp.green.push(channel(1));
+            ramp.blue.push(channel(2));
+        }
+        ramp
+    }
+}
+
+/// Evaluates the segments into a flat lut of `size` red, green and blue entries
+pub(super) fn sample_segments(segments: &[GammaCurveSegment], size: usize) -> Vec<[u16; 3]> {
+    (0..size)
+        .map(|i| {
+            let x = if size > 1 {
+                i as f64 / (size - 1) as f64
+            } else {
+                0.0
+            };
+            let value = segments
+                .iter()
+                .find_map(|segment| segment.value(x))
+                .unwrap_or([x; 3]);
+            value.map(|v| (v.clamp(0.0, 1.0) * u16::MAX as f64).round() as u16)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{sample_segments, GammaCurveSegment, GammaRamp};
+
+    #[test]
+    fn sample_gamma_curve() {
+        let segments = [GammaCurveSegment {
+            start: 0.0,
+            end: 0.5,
+            start_value: [0.0; 3],
+            end_value: [1.0, 0.5, 0.0],
+        }];
+
+        let lut = sample_segments(&segments, 3);
+        assert_eq!(lut[0], [0, 0, 0]);
+        assert_eq!(lut[1], [u16::MAX, 32768, 0]);
+        // not covered by any segment
+        assert_eq!(lut[2], [u16::MAX; 3]);
+    }
+
+    #[test]
+    fn lut_blob_roundtrip() {
+        let ramp = GammaRamp {
+            red: vec![0, 100, u16::MAX],
+            green: vec![1, 200, 30000],
+            blue: vec![2, 300, 40000],
+        };
+        let data = ramp
+            .lut()
+            .into_iter()
+            .flat_map(|[red, green, blue]| [red, green, blue, 0])
+            .flat_map(u16::to_ne_bytes)
+            .collect::<Vec<_>>();
+        assert_eq!(GammaRamp::from_lut_blob(&data), ramp);
+    }
+}
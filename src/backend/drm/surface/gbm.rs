@@ -207,6 +207,9 @@ where
                 transform: Transform::Normal,
                 damage_clips: None,
                 fb: *handle.as_ref(),
+                blend_mode: None,
+                scaling_filter: None,
+                skip_implicit_sync: false,
             }),
         };
 
@@ -310,7 +313,7 @@ where
             Rectangle::from_loc_and_size(Point::default(), (mode.size().0 as i32, mode.size().1 as i32));
 
         let damage_clips = damage.and_then(|damage| {
-            PlaneDamageClips::from_damage(self.drm.device_fd(), src, dst, damage)
+            PlaneDamageClips::from_crtc_damage(self.drm.device_fd(), src, dst, dst.size, damage)
                 .ok()
                 .flatten()
         });
@@ -324,11 +327,14 @@ where
                 alpha: 1.0,
                 damage_clips: damage_clips.as_ref().map(|d| d.blob()),
                 fb: *handle.as_ref(),
+                blend_mode: None,
+                scaling_filter: None,
+                skip_implicit_sync: false,
             }),
         };
 
         let flip = if self.drm.commit_pending() {
-            self.drm.commit([plane_state], true)
+            self.drm.commit([plane_state], true).map(|_| ())
         } else {
             self.drm.page_flip([plane_state], true)
         };
@@ -1,4 +1,7 @@
-use drm::control::{connector, crtc, encoder, framebuffer, Device as ControlDevice, Mode, PageFlipFlags};
+use drm::buffer;
+use drm::control::{
+    connector, crtc, encoder, framebuffer, property, Device as ControlDevice, Mode, PageFlipFlags,
+};
 
 use std::collections::HashSet;
 use std::sync::{
@@ -13,12 +16,16 @@ use crate::{
     utils::DevPath,
 };
 
-use tracing::{debug, info, info_span, instrument, trace};
+use tracing::{debug, info, info_span, instrument, trace, warn};
+
+use super::{atomic::enum_prop_value, DpmsState, PropertySnapshot, ScalingMode};
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct State {
     pub mode: Mode,
     pub connectors: HashSet<connector::Handle>,
+    pub scaling_mode: Option<ScalingMode>,
+    pub connector_properties: Vec<PropertySnapshot>,
 }
 
 impl State {
@@ -66,6 +73,8 @@ impl State {
         Ok(State {
             mode: current_mode.unwrap_or_else(|| unsafe { std::mem::zeroed() }),
             connectors: current_connectors,
+            scaling_mode: None,
+            connector_properties: Vec::new(),
         })
     }
 }
@@ -77,6 +86,9 @@ pub struct LegacyDrmSurface {
     crtc: crtc::Handle,
     state: RwLock<State>,
     pending: RwLock<State>,
+    // whether the connectors are powered, see `set_dpms`
+    crtc_active: AtomicBool,
+    pub(super) restore_on_drop: AtomicBool,
     pub(super) span: tracing::Span,
 }
 
@@ -88,7 +100,7 @@ impl LegacyDrmSurface {
         mode: Mode,
         connectors: &[connector::Handle],
     ) -> Result<Self, Error> {
-        let span = info_span!("drm_legacy", crtc = ?crtc);
+        let span = info_span!("drm_legacy", crtc = ?crtc, label = tracing::field::Empty);
         let _guard = span.enter();
         info!(?mode, ?connectors, ?crtc, "Initializing drm surface",);
 
@@ -96,6 +108,8 @@ impl LegacyDrmSurface {
         let pending = State {
             mode,
             connectors: connectors.iter().copied().collect(),
+            scaling_mode: None,
+            connector_properties: Vec::new(),
         };
 
         drop(_guard);
@@ -105,6 +119,8 @@ impl LegacyDrmSurface {
             crtc,
             state: RwLock::new(state),
             pending: RwLock::new(pending),
+            crtc_active: AtomicBool::new(true),
+            restore_on_drop: AtomicBool::new(false),
             span,
         };
 
@@ -179,6 +195,30 @@ impl LegacyDrmSurface {
         Ok(())
     }
 
+    #[instrument(level = "debug", parent = &self.span, skip(self))]
+    pub fn reconfigure(&self, connectors: &[connector::Handle], mode: Mode) -> Result<(), Error> {
+        if connectors.is_empty() {
+            return Err(Error::SurfaceWithoutConnectors(self.crtc));
+        }
+
+        if !self.active.load(Ordering::SeqCst) {
+            return Err(Error::DeviceInactive);
+        }
+
+        let mut pending = self.pending.write().unwrap();
+
+        for conn in connectors {
+            if !self.check_connector(*conn, &mode)? {
+                return Err(Error::ModeNotSuitable(mode));
+            }
+        }
+
+        pending.connectors = connectors.iter().cloned().collect();
+        pending.mode = mode;
+
+        Ok(())
+    }
+
     #[instrument(level = "debug", parent = &self.span, skip(self))]
     pub fn use_mode(&self, mode: Mode) -> Result<(), Error> {
         if !self.active.load(Ordering::SeqCst) {
@@ -209,10 +249,122 @@ impl LegacyDrmSurface {
         Ok(())
     }
 
+    #[instrument(level = "debug", parent = &self.span, skip(self))]
+    pub fn set_scaling_mode(&self, mode: ScalingMode) -> Result<(), Error> {
+        if !self.active.load(Ordering::SeqCst) {
+            return Err(Error::DeviceInactive);
+        }
+
+        let mut pending = self.pending.write().unwrap();
+        for conn in pending.connectors.iter() {
+            self.scaling_mode_value(*conn, mode)?;
+        }
+
+        pending.scaling_mode = Some(mode);
+
+        Ok(())
+    }
+
+    #[instrument(level = "debug", parent = &self.span, skip(self, snapshot))]
+    pub fn restore_connector_properties(&self, snapshot: &PropertySnapshot) -> Result<(), Error> {
+        if !self.active.load(Ordering::SeqCst) {
+            return Err(Error::DeviceInactive);
+        }
+
+        let mut pending = self.pending.write().unwrap();
+        pending
+            .connector_properties
+            .retain(|staged| staged.connector() != snapshot.connector());
+        pending.connector_properties.push(snapshot.clone());
+
+        Ok(())
+    }
+
+    // looks up the "scaling mode" property of a connector and the value for the given mode
+    fn scaling_mode_value(
+        &self,
+        conn: connector::Handle,
+        mode: ScalingMode,
+    ) -> Result<(property::Handle, property::RawValue), Error> {
+        let unsupported = || Error::UnknownProperty {
+            handle: conn.into(),
+            name: "scaling mode",
+        };
+
+        let prop = self
+            .connector_property(conn, "scaling mode")?
+            .ok_or_else(unsupported)?;
+        let value = enum_prop_value(&*self.fd, prop, mode.drm_name())?.ok_or_else(unsupported)?;
+        Ok((prop, value))
+    }
+
+    #[instrument(level = "debug", parent = &self.span, skip(self))]
+    pub fn set_dpms(&self, state: DpmsState) -> Result<(), Error> {
+        if !self.active.load(Ordering::SeqCst) {
+            return Err(Error::DeviceInactive);
+        }
+
+        let current = self.state.read().unwrap();
+        for conn in current.connectors.iter() {
+            let prop = self
+                .connector_property(*conn, "DPMS")?
+                .ok_or(Error::UnknownProperty {
+                    handle: (*conn).into(),
+                    name: "DPMS",
+                })?;
+            trace!(connector = ?conn, "Setting DPMS {:?}", state);
+            self.fd
+                .set_property(*conn, prop, state.drm_value())
+                .map_err(|source| Error::Access {
+                    errmsg: "Failed to set DPMS state",
+                    dev: self.fd.dev_path(),
+                    source,
+                })?;
+        }
+        self.crtc_active.store(state == DpmsState::On, Ordering::SeqCst);
+
+        Ok(())
+    }
+
+    pub fn is_crtc_active(&self) -> bool {
+        self.crtc_active.load(Ordering::SeqCst)
+    }
+
+    // looks up a property of a connector by its name
+    fn connector_property(
+        &self,
+        conn: connector::Handle,
+        name: &str,
+    ) -> Result<Option<property::Handle>, Error> {
+        let props = self.fd.get_properties(conn).map_err(|source| Error::Access {
+            errmsg: "Failed to get properties for connector",
+            dev: self.fd.dev_path(),
+            source,
+        })?;
+        let (handles, _) = props.as_props_and_values();
+        for handle in handles {
+            let info = self.fd.get_property(*handle).map_err(|source| Error::Access {
+                errmsg: "Failed to get property of connector",
+                dev: self.fd.dev_path(),
+                source,
+            })?;
+            if info.name().to_str().map(|x| x == name).unwrap_or(false) {
+                return Ok(Some(*handle));
+            }
+        }
+
+        Ok(None)
+    }
+
     pub fn commit_pending(&self) -> bool {
         *self.pending.read().unwrap() != *self.state.read().unwrap()
     }
 
+    pub fn discard_pending(&self) {
+        let current = self.state.read().unwrap();
+        *self.pending.write().unwrap() = current.clone();
+    }
+
     #[instrument(level = "trace", parent = &self.span, skip(self))]
     pub fn commit(&self, framebuffer: framebuffer::Handle, event: bool) -> Result<(), Error> {
         if !self.active.load(Ordering::SeqCst) {
@@ -220,7 +372,7 @@ impl LegacyDrmSurface {
         }
 
         let mut current = self.state.write().unwrap();
-        let pending = self.pending.read().unwrap();
+        let mut pending = self.pending.write().unwrap();
 
         {
             let removed = current.connectors.difference(&pending.connectors);
@@ -262,6 +414,35 @@ impl LegacyDrmSurface {
             if current.mode != pending.mode {
                 info!("Setting new mode: {:?}", pending.mode.name());
             }
+
+            if let Some(mode) = pending.scaling_mode {
+                for conn in pending.connectors.iter() {
+                    let (prop, value) = self.scaling_mode_value(*conn, mode)?;
+                    trace!(connector = ?conn, "Setting scaling mode {:?}", mode);
+                    self.fd
+                        .set_property(*conn, prop, value)
+                        .map_err(|source| Error::Access {
+                            errmsg: "Failed to set scaling mode",
+                            dev: self.fd.dev_path(),
+                            source,
+                        })?;
+                }
+            }
+
+            for snapshot in pending.connector_properties.iter() {
+                // the kernel keeps its own reference to any blob set, so the staged ones can be dropped right away
+                let (values, _blobs) = snapshot.stage(self.fd.device_fd())?;
+                trace!(connector = ?snapshot.connector(), "Restoring {} properties", values.len());
+                for (prop, value) in values {
+                    self.fd
+                        .set_property(snapshot.connector(), prop, value)
+                        .map_err(|source| Error::Access {
+                            errmsg: "Failed to restore connector property",
+                            dev: self.fd.dev_path(),
+                            source,
+                        })?;
+                }
+            }
         }
 
         debug!("Setting screen");
@@ -284,7 +465,11 @@ impl LegacyDrmSurface {
                 source,
             })?;
 
+        // restored properties are applied now and must not be set again on the next commit
+        pending.connector_properties.clear();
         *current = pending.clone();
+        // `set_crtc` powers the connectors up again
+        self.crtc_active.store(true, Ordering::SeqCst);
 
         if event {
             // set crtc does not trigger page_flip events, so we immediately queue a flip
@@ -305,28 +490,93 @@ impl LegacyDrmSurface {
     }
 
     #[instrument(level = "trace", parent = &self.span, skip(self))]
-    pub fn page_flip(&self, framebuffer: framebuffer::Handle, event: bool) -> Result<(), Error> {
+    pub fn set_cursor(&self, fb: Option<framebuffer::Handle>, hotspot: (i32, i32)) -> Result<(), Error> {
+        if !self.active.load(Ordering::SeqCst) {
+            return Err(Error::DeviceInactive);
+        }
+
+        let buffer = match fb {
+            Some(fb) => {
+                let info = self.fd.get_framebuffer(fb).map_err(|source| Error::Access {
+                    errmsg: "Failed to get framebuffer info",
+                    dev: self.fd.dev_path(),
+                    source,
+                })?;
+                // the buffer handle is only exposed to the drm master
+                let handle = info
+                    .buffer()
+                    .ok_or_else(|| Error::NotMaster(self.fd.dev_path()))?;
+                Some(CursorBuffer {
+                    handle,
+                    size: info.size(),
+                    pitch: info.pitch(),
+                })
+            }
+            None => None,
+        };
+
+        #[allow(deprecated)]
+        let result = self
+            .fd
+            .set_cursor2(self.crtc, buffer.as_ref(), hotspot)
+            .map_err(|source| Error::Access {
+                errmsg: "Failed to set cursor",
+                dev: self.fd.dev_path(),
+                source,
+            });
+        // the kernel keeps its own reference to the buffer, the handle created by `GETFB` is not needed anymore
+        super::close_gem_handles(&*self.fd, buffer.map(|buffer| buffer.handle.into()));
+        result
+    }
+
+    #[instrument(level = "trace", parent = &self.span, skip(self))]
+    pub fn move_cursor(&self, position: (i32, i32)) -> Result<(), Error> {
+        if !self.active.load(Ordering::SeqCst) {
+            return Err(Error::DeviceInactive);
+        }
+
+        #[allow(deprecated)]
+        self.fd
+            .move_cursor(self.crtc, position)
+            .map_err(|source| Error::Access {
+                errmsg: "Failed to move cursor",
+                dev: self.fd.dev_path(),
+                source,
+            })
+    }
+
+    #[instrument(level = "trace", parent = &self.span, skip(self))]
+    pub fn page_flip(
+        &self,
+        framebuffer: framebuffer::Handle,
+        event: bool,
+        asynchronous: bool,
+    ) -> Result<(), Error> {
         trace!("Queueing Page flip");
 
         if !self.active.load(Ordering::SeqCst) {
             return Err(Error::DeviceInactive);
         }
 
-        ControlDevice::page_flip(
-            &*self.fd,
-            self.crtc,
-            framebuffer,
-            if event {
-                PageFlipFlags::EVENT
-            } else {
-                PageFlipFlags::empty()
-            },
-            None,
-        )
-        .map_err(|source| Error::Access {
-            errmsg: "Failed to page flip",
-            dev: self.fd.dev_path(),
-            source,
+        if !self.crtc_active.load(Ordering::SeqCst) {
+            debug!("Powering up connectors");
+            self.set_dpms(DpmsState::On)?;
+        }
+
+        let mut flags = if event {
+            PageFlipFlags::EVENT
+        } else {
+            PageFlipFlags::empty()
+        };
+        if asynchronous {
+            flags |= PageFlipFlags::ASYNC;
+        }
+        ControlDevice::page_flip(&*self.fd, self.crtc, framebuffer, flags, None).map_err(|source| {
+            Error::Access {
+                errmsg: "Failed to page flip",
+                dev: self.fd.dev_path(),
+                source,
+            }
         })
     }
 
@@ -427,6 +677,33 @@ impl LegacyDrmSurface {
     }
 }
 
+// buffer of an existing framebuffer for the legacy cursor ioctls
+#[derive(Debug)]
+struct CursorBuffer {
+    handle: buffer::Handle,
+    size: (u32, u32),
+    pitch: u32,
+}
+
+impl buffer::Buffer for CursorBuffer {
+    fn size(&self) -> (u32, u32) {
+        self.size
+    }
+
+    fn format(&self) -> buffer::DrmFourcc {
+        // not used by the cursor ioctls
+        buffer::DrmFourcc::Argb8888
+    }
+
+    fn pitch(&self) -> u32 {
+        self.pitch
+    }
+
+    fn handle(&self) -> buffer::Handle {
+        self.handle
+    }
+}
+
 impl Drop for LegacyDrmSurface {
     fn drop(&mut self) {
         let _guard = self.span.enter();
@@ -440,8 +717,29 @@ impl Drop for LegacyDrmSurface {
             return;
         }
 
-        // disable connectors again
         let current = self.state.read().unwrap();
+        if self.restore_on_drop.load(Ordering::SeqCst) {
+            if let DrmDeviceInternal::Legacy(dev) = &*self.fd {
+                // connectors not driven by the crtc before need to be disabled first
+                let previous = dev.previous_connectors(self.crtc);
+                let _ = set_connector_state(
+                    &*self.fd,
+                    current
+                        .connectors
+                        .iter()
+                        .copied()
+                        .filter(|conn| !previous.contains(conn)),
+                    false,
+                );
+                match dev.restore_crtc(self.crtc) {
+                    Ok(true) => return,
+                    Ok(false) => {}
+                    Err(err) => warn!("Failed to restore previous state of crtc: {}", err),
+                }
+            }
+        }
+
+        // disable connectors again
         if set_connector_state(&*self.fd, current.connectors.iter().copied(), false).is_ok() {
             // null commit
             let _ = self.fd.set_crtc(self.crtc, None, (0, 0), &[], None);
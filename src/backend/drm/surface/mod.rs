@@ -1,41 +1,320 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::convert::TryFrom;
-use std::os::unix::io::{AsFd, AsRawFd, BorrowedFd};
-use std::sync::Arc;
+use std::fmt;
+use std::ops::{Bound, RangeBounds};
+use std::os::unix::io::{AsFd, AsRawFd, BorrowedFd, OwnedFd};
+use std::sync::{
+    atomic::{AtomicBool, AtomicU64, Ordering},
+    Arc, Mutex, Weak,
+};
+use std::time::{Duration, Instant};
 
-use drm::control::{connector, crtc, framebuffer, plane, property, Device as ControlDevice, Mode};
+use drm::control::{
+    connector, crtc, encoder, framebuffer, plane, property, ClipRect, Device as ControlDevice, Event, Mode,
+    ResourceHandle,
+};
 use drm::{Device as BasicDevice, DriverCapability};
 
 use nix::libc::dev_t;
 
 pub(super) mod atomic;
+pub(super) mod config;
+pub(super) mod edid;
+pub(super) mod gamma;
 #[cfg(feature = "backend_gbm")]
 pub(super) mod gbm;
 pub(super) mod legacy;
+pub(super) mod snapshot;
 use super::{
-    device::PlaneClaimStorage, error::Error, plane_type, planes, DrmDeviceFd, PlaneClaim, PlaneType, Planes,
+    device::PlaneClaimStorage, error::Error, mode_refresh_mhz, plane_type, planes, DrmDeviceFd, PlaneClaim,
+    PlaneType, Planes,
 };
-use crate::utils::{Buffer, Physical, Point, Rectangle, Transform};
+use crate::utils::{Buffer, Logical, Physical, Point, Rectangle, Size, Transform};
 use crate::{
-    backend::allocator::{Format, Fourcc, Modifier},
+    backend::allocator::{format::get_bpp, Format, Fourcc, Modifier},
     utils::DevPath,
 };
 use atomic::AtomicDrmSurface;
+pub use gamma::{GammaCurveSegment, GammaRamp};
 use legacy::LegacyDrmSurface;
+pub use snapshot::PropertySnapshot;
 
-use tracing::trace;
+use tracing::{debug, trace, warn};
 
 /// An open crtc + plane combination that can be used for scan-out
+///
+/// A `DrmSurface` is `Send` and `Sync` and all its methods take `&self`, so it can be shared between
+/// threads by wrapping it into an [`Arc`]. The pending state (mode, connectors, ...) is protected by locks,
+/// so concurrent changes are serialized, but a commit uses the pending state at the time it is issued.
+/// Pending state should therefore only be modified from a single thread.
+/// Threads only interested in reading the state, e.g. for timing, can use a [`WeakDrmSurface`] instead,
+/// which does not keep the surface alive.
 #[derive(Debug)]
 pub struct DrmSurface {
-    // This field is only read when 'backend_session' is enabled
-    #[allow(dead_code)]
     pub(super) dev_id: dev_t,
     pub(super) crtc: crtc::Handle,
     pub(super) primary: plane::Handle,
     pub(super) internal: Arc<DrmSurfaceInternal>,
     pub(super) has_universal_planes: bool,
     pub(super) plane_claim_storage: PlaneClaimStorage,
+    pub(super) frame_counter: Arc<FrameCounter>,
+    pub(super) buffer_release: Arc<BufferRelease>,
+    pub(super) cursor: Mutex<CursorState>,
+    pub(super) external_framebuffers: Mutex<HashMap<framebuffer::Handle, ExternalFramebuffer>>,
+    // last committed state of every plane, see `flip_needed`
+    pub(super) committed_planes: Mutex<Vec<PlaneState<'static>>>,
+    pub(super) test_only: AtomicBool,
+    pub(super) allow_tearing: AtomicBool,
+    #[cfg(feature = "backend_drm_failure_injection")]
+    pub(super) injected_failures: super::test::InjectedFailures,
+}
+
+/// Read-only handle to a [`DrmSurface`], see [`DrmSurface::weak`]
+///
+/// All methods return `None` once the surface was dropped.
+#[derive(Debug, Clone)]
+pub struct WeakDrmSurface {
+    crtc: crtc::Handle,
+    internal: Weak<DrmSurfaceInternal>,
+    frame_counter: Weak<FrameCounter>,
+}
+
+impl WeakDrmSurface {
+    /// Returns the underlying [`crtc`](drm::control::crtc) of the surface
+    pub fn crtc(&self) -> crtc::Handle {
+        self.crtc
+    }
+
+    /// Returns whether the surface is still alive
+    pub fn is_alive(&self) -> bool {
+        self.internal.strong_count() > 0
+    }
+
+    /// Returns the currently active [`Mode`](drm::control::Mode) of the surface
+    pub fn current_mode(&self) -> Option<Mode> {
+        self.internal.upgrade().map(|internal| match &*internal {
+            DrmSurfaceInternal::Atomic(surf) => surf.current_mode(),
+            DrmSurfaceInternal::Legacy(surf) => surf.current_mode(),
+        })
+    }
+
+    /// Returns the currently pending [`Mode`](drm::control::Mode) of the surface
+    pub fn pending_mode(&self) -> Option<Mode> {
+        self.internal.upgrade().map(|internal| match &*internal {
+            DrmSurfaceInternal::Atomic(surf) => surf.pending_mode(),
+            DrmSurfaceInternal::Legacy(surf) => surf.pending_mode(),
+        })
+    }
+
+    /// Returns the currently used [`connector`](drm::control::connector)s of the surface
+    pub fn current_connectors(&self) -> Option<HashSet<connector::Handle>> {
+        self.internal.upgrade().map(|internal| match &*internal {
+            DrmSurfaceInternal::Atomic(surf) => surf.current_connectors(),
+            DrmSurfaceInternal::Legacy(surf) => surf.current_connectors(),
+        })
+    }
+
+    /// Returns the pending [`connector`](drm::control::connector)s of the surface
+    pub fn pending_connectors(&self) -> Option<HashSet<connector::Handle>> {
+        self.internal.upgrade().map(|internal| match &*internal {
+            DrmSurfaceInternal::Atomic(surf) => surf.pending_connectors(),
+            DrmSurfaceInternal::Legacy(surf) => surf.pending_connectors(),
+        })
+    }
+
+    /// Returns the number of frames committed, see [`DrmSurface::frames_committed`]
+    pub fn frames_committed(&self) -> Option<u64> {
+        self.frame_counter
+            .upgrade()
+            .map(|counter| counter.committed.load(Ordering::SeqCst))
+    }
+
+    /// Returns the number of missed frames, see [`DrmSurface::frames_missed`]
+    pub fn frames_missed(&self) -> Option<u64> {
+        self.frame_counter
+            .upgrade()
+            .map(|counter| counter.missed.load(Ordering::SeqCst))
+    }
+}
+
+/// Metadata of a framebuffer created outside of smithay
+#[derive(Debug, Clone, Copy)]
+pub(super) struct ExternalFramebuffer {
+    format: Format,
+    size: Size<i32, Buffer>,
+}
+
+/// State of the cursor set via [`DrmSurface::set_cursor`]
+#[derive(Debug, Default, Clone, Copy)]
+pub(super) struct CursorState {
+    // cursor plane of atomic devices, looked up on first use
+    plane: Option<plane::Handle>,
+    fb: Option<framebuffer::Handle>,
+    size: Size<i32, Buffer>,
+    hotspot: Point<i32, Buffer>,
+    position: Point<i32, Physical>,
+}
+
+impl CursorState {
+    // top-left corner of the cursor image on the crtc
+    fn origin(&self) -> Point<i32, Physical> {
+        self.position - Point::from((self.hotspot.x, self.hotspot.y))
+    }
+}
+
+/// Opt-in statistics and pacing of the frames of a [`DrmSurface`],
+/// updated by the [`DrmDeviceNotifier`](super::DrmDeviceNotifier).
+#[derive(Debug, Default)]
+pub(super) struct FrameCounter {
+    enabled: AtomicBool,
+    committed: AtomicU64,
+    missed: AtomicU64,
+    last_sequence: Mutex<Option<u32>>,
+    pacing: Mutex<FramePacing>,
+    watchdog: Mutex<FlipWatchdog>,
+}
+
+#[derive(Debug, Default)]
+struct FlipWatchdog {
+    timeout: Option<Duration>,
+    // submission time of the last update requesting an event, that was not followed by a flip yet
+    pending_since: Option<Instant>,
+}
+
+impl FlipWatchdog {
+    fn timed_out(&self, now: Instant) -> bool {
+        match (self.timeout, self.pending_since) {
+            (Some(timeout), Some(since)) => now.saturating_duration_since(since) >= timeout,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct FramePacing {
+    target: Option<Duration>,
+    last_flip: Option<Instant>,
+    // vblank the last paced commit is expected to be presented at
+    last_vblank: Option<Instant>,
+}
+
+impl FramePacing {
+    // Returns the vblank a commit submitted at `now` will be presented at,
+    // or `None` if it would be presented too early.
+    fn next_vblank(&self, now: Instant, frame_duration: Duration) -> Option<Instant> {
+        let (Some(target), Some(last_flip)) = (self.target, self.last_flip) else {
+            return Some(now);
+        };
+        if frame_duration.is_zero() {
+            return Some(now);
+        }
+
+        let frames = now.saturating_duration_since(last_flip).as_nanos() / frame_duration.as_nanos() + 1;
+        let vblank = last_flip + frame_duration * frames as u32;
+        match self.last_vblank {
+            // allow for half a frame of jitter between the flip events
+            Some(last_vblank) if vblank + frame_duration / 2 < last_vblank + target.max(frame_duration) => {
+                None
+            }
+            _ => Some(vblank),
+        }
+    }
+}
+
+impl FrameCounter {
+    fn committed(&self) {
+        if self.enabled.load(Ordering::SeqCst) {
+            self.committed.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    fn flip_requested(&self) {
+        self.watchdog.lock().unwrap().pending_since = Some(Instant::now());
+    }
+
+    // Fails once, if the last requested flip event did not arrive within the timeout
+    fn check_flip_timeout(&self, crtc: crtc::Handle) -> Result<(), Error> {
+        let mut watchdog = self.watchdog.lock().unwrap();
+        if watchdog.timed_out(Instant::now()) {
+            watchdog.pending_since = None;
+            return Err(Error::FlipTimeout(crtc));
+        }
+        Ok(())
+    }
+
+    pub(super) fn vblank(&self, sequence: u32) {
+        self.watchdog.lock().unwrap().pending_since = None;
+        self.pacing.lock().unwrap().last_flip = Some(Instant::now());
+
+        if !self.enabled.load(Ordering::SeqCst) {
+            return;
+        }
+
+        let mut last_sequence = self.last_sequence.lock().unwrap();
+        if let Some(last) = last_sequence.replace(sequence) {
+            // every vblank in between two flips is a frame we did not present
+            let skipped = sequence.wrapping_sub(last).saturating_sub(1);
+            self.missed.fetch_add(skipped as u64, Ordering::SeqCst);
+        }
+    }
+}
+
+type ReleaseCallback = Box<dyn Fn(framebuffer::Handle) + Send + Sync>;
+
+/// Tracks the framebuffers scanned out by the planes of a [`DrmSurface`],
+/// to notify about framebuffers no longer in use after a page flip.
+#[derive(Default)]
+pub(super) struct BufferRelease {
+    callback: Mutex<Option<ReleaseCallback>>,
+    // framebuffers currently scanned out
+    scanout: Mutex<HashMap<plane::Handle, framebuffer::Handle>>,
+    // framebuffers of commits, that did not complete yet
+    queued: Mutex<Vec<(plane::Handle, Option<framebuffer::Handle>)>>,
+}
+
+impl fmt::Debug for BufferRelease {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BufferRelease")
+            .field("scanout", &self.scanout)
+            .field("queued", &self.queued)
+            .finish_non_exhaustive()
+    }
+}
+
+impl BufferRelease {
+    fn queue(&self, planes: impl IntoIterator<Item = (plane::Handle, Option<framebuffer::Handle>)>) {
+        // the kernel does not accept a new commit, while another one is still pending,
+        // so any previously queued commit has to be completed by now.
+        self.flipped();
+        self.queued.lock().unwrap().extend(planes);
+    }
+
+    pub(super) fn flipped(&self) {
+        let queued = std::mem::take(&mut *self.queued.lock().unwrap());
+        if queued.is_empty() {
+            return;
+        }
+
+        let mut released = Vec::new();
+        {
+            let mut scanout = self.scanout.lock().unwrap();
+            for (plane, fb) in queued {
+                let previous = match fb {
+                    Some(fb) => scanout.insert(plane, fb),
+                    None => scanout.remove(&plane),
+                };
+                if let Some(previous) = previous.filter(|previous| Some(*previous) != fb) {
+                    released.push(previous);
+                }
+            }
+        }
+
+        if let Some(callback) = self.callback.lock().unwrap().as_ref() {
+            for fb in released {
+                callback(fb);
+            }
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -53,8 +332,13 @@ impl Drop for PlaneDamageInner {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 /// Helper for `FB_DAMAGE_CLIPS`
+///
+/// Cloning is cheap and shares the underlying blob, which is destroyed once the last clone is dropped.
+/// Multiple planes of the same commit can reference the same blob via [`blob`](PlaneDamageClips::blob),
+/// avoiding the creation of redundant blobs, as long as the damage maps onto their framebuffers
+/// in the same way, e.g. because the planes use the same `src` and `dst` rectangles.
 pub struct PlaneDamageClips {
     inner: Arc<PlaneDamageInner>,
 }
@@ -67,38 +351,25 @@ impl PlaneDamageClips {
 }
 
 impl PlaneDamageClips {
-    /// Initialize damage clips for a a plane
-    pub fn from_damage(
+    /// Initialize damage clips for a a plane from damage on the crtc
+    ///
+    /// - `src` and `dst` are the source and destination rectangles of the plane,
+    ///   see [`PlaneConfig::src`] and [`PlaneConfig::dst`]
+    /// - `mode` is the size of the mode of the crtc, limiting the visible area
+    /// - `damage` is expected in the coordinate space of the crtc, like the `dst` of the plane,
+    ///   *not* relative to the plane
+    ///
+    /// The damage is clipped to the plane and to the visible area of the crtc, translated by the position
+    /// of the plane and then mapped onto the `src` rectangle of the framebuffer.
+    /// Damage outside the visible area is dropped, so no clips are created for off-screen regions.
+    pub fn from_crtc_damage(
         device: &DrmDeviceFd,
         src: Rectangle<f64, Buffer>,
         dst: Rectangle<i32, Physical>,
+        mode: Size<i32, Physical>,
         damage: impl IntoIterator<Item = Rectangle<i32, Physical>>,
     ) -> Result<Option<Self>, drm_ffi::result::SystemError> {
-        let scale = src.size / dst.size.to_logical(1).to_buffer(1, Transform::Normal).to_f64();
-
-        let mut rects = damage
-            .into_iter()
-            .map(|rect| {
-                let mut rect = rect
-                    .to_f64()
-                    .to_logical(1f64)
-                    .to_buffer(
-                        1f64,
-                        Transform::Normal,
-                        &src.size.to_logical(1f64, Transform::Normal),
-                    )
-                    .upscale(scale);
-                rect.loc += src.loc;
-                let rect = rect.to_i32_up();
-
-                drm_ffi::drm_mode_rect {
-                    x1: rect.loc.x,
-                    y1: rect.loc.y,
-                    x2: rect.loc.x.saturating_add(rect.size.w),
-                    y2: rect.loc.y.saturating_add(rect.size.h),
-                }
-            })
-            .collect::<Vec<_>>();
+        let mut rects = damage_rects(src, dst, mode, damage);
 
         if rects.is_empty() {
             return Ok(None);
@@ -122,27 +393,100 @@ impl PlaneDamageClips {
     }
 }
 
-impl Clone for PlaneDamageClips {
-    fn clone(&self) -> Self {
-        Self {
-            inner: self.inner.clone(),
-        }
-    }
+// Maps damage in crtc coordinates onto the framebuffer of a plane
+fn damage_rects(
+    src: Rectangle<f64, Buffer>,
+    dst: Rectangle<i32, Physical>,
+    mode: Size<i32, Physical>,
+    damage: impl IntoIterator<Item = Rectangle<i32, Physical>>,
+) -> Vec<drm_ffi::drm_mode_rect> {
+    let scale = src.size / dst.size.to_logical(1).to_buffer(1, Transform::Normal).to_f64();
+    let visible = Rectangle::from_loc_and_size((0, 0), mode);
+
+    damage
+        .into_iter()
+        .filter_map(|rect| rect.intersection(dst))
+        .filter_map(|rect| rect.intersection(visible))
+        .filter(|rect| !rect.is_empty())
+        .map(|mut rect| {
+            // make the damage relative to the plane
+            rect.loc -= dst.loc;
+            let mut rect = rect
+                .to_f64()
+                .to_logical(1f64)
+                .to_buffer(
+                    1f64,
+                    Transform::Normal,
+                    &src.size.to_logical(1f64, Transform::Normal),
+                )
+                .upscale(scale);
+            rect.loc += src.loc;
+            let rect = rect.to_i32_up();
+
+            drm_ffi::drm_mode_rect {
+                x1: rect.loc.x,
+                y1: rect.loc.y,
+                x2: rect.loc.x.saturating_add(rect.size.w),
+                y2: rect.loc.y.saturating_add(rect.size.h),
+            }
+        })
+        .collect()
 }
 
 /// State of a single plane
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct PlaneState<'a> {
     /// Handle of the plane
     pub handle: plane::Handle,
     /// Configuration for that plane
     ///
-    /// Can be `None` if nothing is attached
+    /// Can be `None` to clear the plane. On atomic devices the plane is detached (`FB_ID` and `CRTC_ID` set to 0)
+    /// in the same request as the other planes of the update, so e.g. several overlays can be removed while
+    /// the primary plane is updated and the crtc stays active.
+    /// The primary plane cannot be cleared while the crtc is active, atomic commits and page flips
+    /// fail with [`Error::MissingPrimaryPlane`] instead. To turn the output off, disable the crtc
+    /// via [`DrmSurface::set_active`]. Legacy devices always require a framebuffer on the primary plane.
     pub config: Option<PlaneConfig<'a>>,
 }
 
+impl<'a> PlaneState<'a> {
+    /// Returns the states of `next`, which differ from the states of the same planes in `previous`
+    ///
+    /// This can be used to only submit the planes that changed between two commits.
+    /// Planes that are not part of `previous` are always included, this includes planes
+    /// being cleared by having no config. Planes only part of `previous` are not included,
+    /// as planes missing from a commit are left untouched anyway.
+    pub fn diff(previous: &[PlaneState<'_>], next: &[PlaneState<'a>]) -> Vec<PlaneState<'a>> {
+        next.iter()
+            .filter(|state| {
+                previous
+                    .iter()
+                    .find(|prev| prev.handle == state.handle)
+                    .map(|prev| prev.config != state.config)
+                    .unwrap_or(true)
+            })
+            .cloned()
+            .collect()
+    }
+
+    // Detaches the state from the borrowed damage clips to remember it after a commit.
+    // The blob id of the damage clips may get stale, so this may only be used for comparisons.
+    fn detach(self) -> PlaneState<'static> {
+        PlaneState {
+            handle: self.handle,
+            config: self.config.map(|config| PlaneConfig {
+                damage_clips: config.damage_clips.map(|value| match value {
+                    property::Value::Blob(blob) => property::Value::Blob(blob),
+                    value => property::Value::Unknown(value.into()),
+                }),
+                ..config
+            }),
+        }
+    }
+}
+
 /// Configuration for a single plane
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub struct PlaneConfig<'a> {
     /// Source [`Rectangle`] of the attached framebuffer
     pub src: Rectangle<f64, Buffer>,
@@ -156,6 +500,340 @@ pub struct PlaneConfig<'a> {
     pub damage_clips: Option<drm::control::property::Value<'a>>,
     /// Framebuffer handle
     pub fb: framebuffer::Handle,
+    /// Pixel blend mode of the plane
+    ///
+    /// `None` leaves the currently programmed blend mode untouched.
+    pub blend_mode: Option<PixelBlendMode>,
+    /// Filter used when scaling the framebuffer onto the plane
+    ///
+    /// `None` leaves the currently programmed filter untouched.
+    pub scaling_filter: Option<ScalingFilter>,
+    /// Skip waiting for the implicit fences of the framebuffer before scanning it out
+    ///
+    /// Only set this, if rendering into the framebuffer is known to be complete, e.g. because
+    /// the compositor already waited for an explicit fence, to avoid redundant waits in the kernel.
+    /// This is implemented by passing an already signalled fence via `IN_FENCE_FD`, which makes kernels,
+    /// that don't merge explicit and implicit fences, skip the implicit ones. Ignored on legacy devices
+    /// and on devices without support for syncobjs.
+    pub skip_implicit_sync: bool,
+}
+
+/// A [`PlaneConfig`] not borrowing any data, see [`PlaneConfig::into_owned`]
+///
+/// Keeps its [`PlaneDamageClips`] alive, so it can be stored between frames and committed later
+/// by borrowing it as a [`PlaneConfig`] again via [`as_config`](OwnedPlaneConfig::as_config) or [`From`].
+#[derive(Debug, Clone)]
+pub struct OwnedPlaneConfig {
+    /// Source [`Rectangle`] of the attached framebuffer
+    pub src: Rectangle<f64, Buffer>,
+    /// Destination [`Rectangle`] on the CRTC
+    pub dst: Rectangle<i32, Physical>,
+    /// Transform for the attached framebuffer
+    pub transform: Transform,
+    /// Alpha value for the plane
+    pub alpha: f32,
+    /// Damage clips of the attached framebuffer
+    pub damage_clips: Option<PlaneDamageClips>,
+    /// Framebuffer handle
+    pub fb: framebuffer::Handle,
+    /// Pixel blend mode of the plane, see [`PlaneConfig::blend_mode`]
+    pub blend_mode: Option<PixelBlendMode>,
+    /// Filter used when scaling the framebuffer onto the plane, see [`PlaneConfig::scaling_filter`]
+    pub scaling_filter: Option<ScalingFilter>,
+    /// Skip waiting for the implicit fences of the framebuffer, see [`PlaneConfig::skip_implicit_sync`]
+    pub skip_implicit_sync: bool,
+}
+
+impl OwnedPlaneConfig {
+    /// Borrows the config as a [`PlaneConfig`], e.g. to commit it
+    pub fn as_config(&self) -> PlaneConfig<'_> {
+        PlaneConfig {
+            src: self.src,
+            dst: self.dst,
+            transform: self.transform,
+            alpha: self.alpha,
+            damage_clips: self.damage_clips.as_ref().map(PlaneDamageClips::blob),
+            fb: self.fb,
+            blend_mode: self.blend_mode,
+            scaling_filter: self.scaling_filter,
+            skip_implicit_sync: self.skip_implicit_sync,
+        }
+    }
+}
+
+impl<'a> From<&'a OwnedPlaneConfig> for PlaneConfig<'a> {
+    fn from(config: &'a OwnedPlaneConfig) -> Self {
+        config.as_config()
+    }
+}
+
+impl<'a> PlaneConfig<'a> {
+    /// Converts the config into an [`OwnedPlaneConfig`], which can be stored between frames,
+    /// e.g. to [`diff`](PlaneState::diff) it against the next frame.
+    ///
+    /// `damage_clips` are the [`PlaneDamageClips`] the [`damage_clips`](PlaneConfig::damage_clips)
+    /// of this config were borrowed from. They replace the borrowed blob and are kept alive by the owned config.
+    pub fn into_owned(self, damage_clips: Option<PlaneDamageClips>) -> OwnedPlaneConfig {
+        debug_assert_eq!(
+            self.damage_clips,
+            damage_clips.as_ref().map(PlaneDamageClips::blob),
+            "damage clips do not match the config"
+        );
+        OwnedPlaneConfig {
+            src: self.src,
+            dst: self.dst,
+            transform: self.transform,
+            alpha: self.alpha,
+            damage_clips,
+            fb: self.fb,
+            blend_mode: self.blend_mode,
+            scaling_filter: self.scaling_filter,
+            skip_implicit_sync: self.skip_implicit_sync,
+        }
+    }
+
+    /// Returns the source rectangle as submitted to the `SRC_X`, `SRC_Y`, `SRC_W` and `SRC_H`
+    /// properties in 16.16 fixed point, rounded to the nearest representable value.
+    pub fn src_fixed(&self) -> (u32, u32, u32, u32) {
+        (
+            atomic::to_fixed(self.src.loc.x),
+            atomic::to_fixed(self.src.loc.y),
+            atomic::to_fixed(self.src.size.w),
+            atomic::to_fixed(self.src.size.h),
+        )
+    }
+}
+
+/// Information about a tile of a tiled display, as reported by the `TILE` connector property
+///
+/// Tiled displays (e.g. some 8K monitors) are exposed as multiple connectors,
+/// each driving one tile. Tiles of the same display share the same [`group_id`](TileInfo::group_id).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TileInfo {
+    /// Id of the group of tiles forming one display
+    pub group_id: u32,
+    /// Whether all tiles are part of a single physical monitor
+    pub single_monitor: bool,
+    /// Number of horizontal and vertical tiles of the display
+    pub num_tiles: Size<u32, Physical>,
+    /// Horizontal and vertical index of this tile
+    pub location: Point<u32, Physical>,
+    /// Size of this tile in pixels
+    pub tile_size: Size<u32, Physical>,
+}
+
+impl TileInfo {
+    // parses the `TILE` blob, formatted as `group:single:h_tiles:v_tiles:h_loc:v_loc:h_size:v_size`
+    fn from_blob(data: &[u8]) -> Option<TileInfo> {
+        let data = std::str::from_utf8(data).ok()?.trim_end_matches('\0');
+        let values = data
+            .split(':')
+            .map(|value| value.trim().parse::<u32>().ok())
+            .collect::<Option<Vec<_>>>()?;
+        let [group_id, single_monitor, h_tiles, v_tiles, h_loc, v_loc, h_size, v_size] = values[..] else {
+            return None;
+        };
+
+        Some(TileInfo {
+            group_id,
+            single_monitor: single_monitor != 0,
+            num_tiles: (h_tiles, v_tiles).into(),
+            location: (h_loc, v_loc).into(),
+            tile_size: (h_size, v_size).into(),
+        })
+    }
+
+    /// Returns the position of this tile in the tiled display in pixels
+    ///
+    /// *Note*: This assumes all tiles of the display have the same size.
+    pub fn position(&self) -> Point<u32, Physical> {
+        (
+            self.location.x * self.tile_size.w,
+            self.location.y * self.tile_size.h,
+        )
+            .into()
+    }
+
+    /// Returns the size of the whole tiled display in pixels
+    ///
+    /// *Note*: This assumes all tiles of the display have the same size.
+    pub fn total_size(&self) -> Size<u32, Physical> {
+        (
+            self.num_tiles.w * self.tile_size.w,
+            self.num_tiles.h * self.tile_size.h,
+        )
+            .into()
+    }
+}
+
+/// Kind of update performed by [`DrmSurface::commit`]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum CommitKind {
+    /// The connectors or mode of the surface changed, which required a full modeset
+    Modeset,
+    /// Only the planes were updated
+    PageFlip,
+    /// Nothing was changed, as no planes were given and no state was pending
+    NoOp,
+}
+
+/// Formats a plane can scan out, packaged for a dmabuf feedback tranche
+///
+/// See [`DrmSurface::dmabuf_feedback_tranche`]. The tranche can be advertised with the `scanout` flag, e.g. via
+/// `DmabufFeedbackBuilder::add_preference_tranche(tranche.target_device, Some(TrancheFlags::Scanout), tranche.formats)`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FeedbackTranche {
+    /// Device id of the drm device scanning out the buffers
+    pub target_device: dev_t,
+    /// Formats supported by the plane
+    pub formats: HashSet<Format>,
+}
+
+/// Variable refresh rate capability of a surface, see [`DrmSurface::vrr_capability`]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct VrrCapability {
+    /// Whether variable refresh rate can be enabled on the surface
+    pub supported: bool,
+    /// Minimal refresh rate in Hz supported by the displays, `0` if unknown
+    pub min_hz: u32,
+    /// Maximal refresh rate in Hz supported by the displays, `0` if unknown
+    pub max_hz: u32,
+}
+
+/// Limits of the modes, that can be driven on a connector, see [`DrmSurface::connector_limits`]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ConnectorLimits {
+    /// Maximum pixel clock in kHz (like [`Mode::clock`]) supported by the display, if advertised
+    pub max_pixel_clock: Option<u32>,
+    /// Maximum width and height of the framebuffers of the device
+    pub max_size: Size<u32, Physical>,
+}
+
+/// Desired content luminance of a display in cd/m², as advertised by the HDR static metadata of its EDID
+///
+/// See [`DrmSurface::hdr_luminance`].
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct HdrLuminance {
+    /// Desired content max luminance
+    pub max_luminance: Option<f32>,
+    /// Desired content max frame-average luminance
+    pub max_frame_average_luminance: Option<f32>,
+    /// Desired content min luminance
+    pub min_luminance: Option<f32>,
+}
+
+/// Drm master status of a surface, see [`DrmSurface::master_status`]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum MasterStatus {
+    /// The device is the drm master and may modify the display
+    Master,
+    /// The device is active, but is not the drm master (anymore)
+    NotMaster,
+    /// The device was paused, e.g. because the session is inactive
+    SessionInactive,
+    /// The device could not become drm master when it was opened, so master status is not tracked
+    Unprivileged,
+}
+
+/// How buffers for scan-out on a plane should be allocated, see [`DrmSurface::scanout_modifier_policy`]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ModifierPolicy {
+    /// The driver accepts framebuffers with explicit modifiers,
+    /// buffers should be allocated with one of the modifiers supported by the plane
+    ExplicitSupported,
+    /// The driver does not accept explicit modifiers,
+    /// buffers should be allocated without modifiers (implicit, [`Modifier::Invalid`])
+    ImplicitOnly,
+    /// The driver does not accept explicit modifiers, but the plane requires a linear layout,
+    /// buffers should be allocated with [`Modifier::Linear`]
+    ForceLinear,
+}
+
+/// Blend mode of a plane, describing how the alpha channel of its pixels is interpreted
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum PixelBlendMode {
+    /// The alpha channel of the pixels is ignored
+    None,
+    /// The pixels are already pre-multiplied with their alpha value (the kernel default)
+    PreMultiplied,
+    /// The pixels are not pre-multiplied, the alpha value is applied on blending
+    Coverage,
+}
+
+impl PixelBlendMode {
+    pub(super) fn drm_name(&self) -> &'static str {
+        match self {
+            PixelBlendMode::None => "None",
+            PixelBlendMode::PreMultiplied => "Pre-multiplied",
+            PixelBlendMode::Coverage => "Coverage",
+        }
+    }
+}
+
+/// Filter used by a plane for scaling its framebuffer, as set by the `SCALING_FILTER` property
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ScalingFilter {
+    /// The default filter of the driver, usually some kind of linear interpolation
+    Default,
+    /// Nearest neighbor filtering, keeping integer-scaled content sharp
+    Nearest,
+}
+
+impl ScalingFilter {
+    pub(super) fn drm_name(&self) -> &'static str {
+        match self {
+            ScalingFilter::Default => "Default",
+            ScalingFilter::Nearest => "Nearest Neighbor",
+        }
+    }
+}
+
+/// Scaling mode of a connector, describing how non-native modes are displayed by the sink
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ScalingMode {
+    /// No scaling, the mode is displayed as is
+    None,
+    /// The image is stretched to the full size of the display
+    Full,
+    /// The image is centered without scaling, surrounded by black borders
+    Center,
+    /// The image is scaled to the size of the display, keeping its aspect ratio
+    FullAspect,
+}
+
+impl ScalingMode {
+    pub(super) fn drm_name(&self) -> &'static str {
+        match self {
+            ScalingMode::None => "None",
+            ScalingMode::Full => "Full",
+            ScalingMode::Center => "Center",
+            ScalingMode::FullAspect => "Full aspect",
+        }
+    }
+}
+
+/// Power state of the connectors of a surface
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum DpmsState {
+    /// The display is turned on
+    On,
+    /// The display is in standby
+    Standby,
+    /// The display is suspended
+    Suspend,
+    /// The display is turned off
+    Off,
+}
+
+impl DpmsState {
+    pub(super) fn drm_value(&self) -> u64 {
+        match self {
+            DpmsState::On => 0,      /* DRM_MODE_DPMS_ON */
+            DpmsState::Standby => 1, /* DRM_MODE_DPMS_STANDBY */
+            DpmsState::Suspend => 2, /* DRM_MODE_DPMS_SUSPEND */
+            DpmsState::Off => 3,     /* DRM_MODE_DPMS_OFF */
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -190,6 +868,68 @@ impl DrmSurface {
         self.crtc
     }
 
+    /// Returns a read-only handle to this surface, that does not keep it alive.
+    ///
+    /// This can be used to query the state of the surface from other threads.
+    pub fn weak(&self) -> WeakDrmSurface {
+        WeakDrmSurface {
+            crtc: self.crtc,
+            internal: Arc::downgrade(&self.internal),
+            frame_counter: Arc::downgrade(&self.frame_counter),
+        }
+    }
+
+    /// Returns whether this surface and `other` were created from the same drm device
+    ///
+    /// Framebuffers of one surface may only be used on the other, if this returns `true`.
+    pub fn same_device(&self, other: &DrmSurface) -> bool {
+        self.dev_id == other.dev_id
+    }
+
+    /// Returns the value of a driver capability of the underlying device
+    ///
+    /// The result is cached for the lifetime of the device, see [`DrmDeviceFd::cached_driver_capability`].
+    pub fn driver_capability(&self, cap: DriverCapability) -> Result<u64, Error> {
+        self.device_fd().cached_driver_capability(cap)
+    }
+
+    /// Returns whether the driver expects to be notified about updated framebuffer contents
+    /// via [`dirty_framebuffer`](DrmSurface::dirty_framebuffer).
+    ///
+    /// This is the case for displays, that are not scanned out directly, but have their contents
+    /// copied on demand, like USB (e.g. DisplayLink) or SPI displays.
+    /// The kernel does not expose this, so this is a heuristic based on the driver name
+    /// and whether the driver prefers shadow buffers for dumb buffers.
+    pub fn needs_dirty_fb(&self) -> bool {
+        let known_driver = self
+            .get_driver()
+            .map(|driver| DIRTY_FB_DRIVERS.iter().any(|name| driver.name() == *name))
+            .unwrap_or(false);
+        known_driver
+            || self
+                .driver_capability(DriverCapability::DumbPreferShadow)
+                .map(|prefer_shadow| prefer_shadow == 1)
+                .unwrap_or(false)
+    }
+
+    /// Marks the given regions of a framebuffer as updated, pushing the new contents to the display.
+    ///
+    /// `damage` is given in framebuffer coordinates, an empty slice marks the whole framebuffer as dirty.
+    /// This is necessary even after a commit on drivers, for which [`needs_dirty_fb`](DrmSurface::needs_dirty_fb)
+    /// returns `true`. Drivers without support for it fail with `ENOSYS`.
+    pub fn dirty_framebuffer(
+        &self,
+        fb: framebuffer::Handle,
+        damage: &[Rectangle<i32, Physical>],
+    ) -> Result<(), Error> {
+        let clips = damage.iter().map(|rect| clip_rect(*rect)).collect::<Vec<_>>();
+        ControlDevice::dirty_framebuffer(self, fb, &clips).map_err(|source| Error::Access {
+            errmsg: "Failed to mark framebuffer dirty",
+            dev: self.dev_path(),
+            source,
+        })
+    }
+
     /// Returns the underlying primary [`plane`](drm::control::plane) of this surface
     pub fn plane(&self) -> plane::Handle {
         self.primary
@@ -212,6 +952,45 @@ impl DrmSurface {
         }
     }
 
+    /// Returns all [`connector`](drm::control::connector)s, that could be driven by
+    /// the underlying [`crtc`](drm::control::crtc).
+    ///
+    /// A connector is considered compatible, if any of its [`encoder`](drm::control::encoder)s
+    /// may be used with this surface's crtc. This does neither check the connection state of the
+    /// connector, nor if the pending [`Mode`] is supported by it, nor does it change the pending state.
+    pub fn compatible_connectors(&self) -> Result<Vec<connector::Handle>, Error> {
+        let resources = self.resource_handles().map_err(|source| Error::Access {
+            errmsg: "Error loading resources",
+            dev: self.dev_path(),
+            source,
+        })?;
+
+        let mut connectors = Vec::new();
+        for conn in resources.connectors() {
+            let info = self.get_connector(*conn, false).map_err(|source| Error::Access {
+                errmsg: "Error loading connector info",
+                dev: self.dev_path(),
+                source,
+            })?;
+            for encoder in info.encoders().iter() {
+                let encoder_info = self.get_encoder(*encoder).map_err(|source| Error::Access {
+                    errmsg: "Error loading encoder info",
+                    dev: self.dev_path(),
+                    source,
+                })?;
+                if resources
+                    .filter_crtcs(encoder_info.possible_crtcs())
+                    .contains(&self.crtc)
+                {
+                    connectors.push(*conn);
+                    break;
+                }
+            }
+        }
+
+        Ok(connectors)
+    }
+
     /// Tries to add a new [`connector`](drm::control::connector)
     /// to be used after the next commit.
     ///
@@ -231,16 +1010,101 @@ impl DrmSurface {
         }
     }
 
-    /// Tries to mark a [`connector`](drm::control::connector)
-    /// for removal on the next commit.
-    pub fn remove_connector(&self, connector: connector::Handle) -> Result<(), Error> {
-        match &*self.internal {
-            DrmSurfaceInternal::Atomic(surf) => surf.remove_connector(connector),
-            DrmSurfaceInternal::Legacy(surf) => surf.remove_connector(connector),
+    /// Returns the [`encoder`](drm::control::encoder)s of the given connector, that may be used with the
+    /// underlying [`crtc`](drm::control::crtc).
+    pub fn available_encoders(&self, connector: connector::Handle) -> Result<Vec<encoder::Handle>, Error> {
+        let resources = self.resource_handles().map_err(|source| Error::Access {
+            errmsg: "Error loading resources",
+            dev: self.dev_path(),
+            source,
+        })?;
+        let info = self
+            .get_connector(connector, false)
+            .map_err(|source| Error::Access {
+                errmsg: "Error loading connector info",
+                dev: self.dev_path(),
+                source,
+            })?;
+
+        let mut encoders = Vec::new();
+        for encoder in info.encoders().iter() {
+            let encoder_info = self.get_encoder(*encoder).map_err(|source| Error::Access {
+                errmsg: "Error loading encoder info",
+                dev: self.dev_path(),
+                source,
+            })?;
+            if resources
+                .filter_crtcs(encoder_info.possible_crtcs())
+                .contains(&self.crtc)
+            {
+                encoders.push(*encoder);
+            }
         }
+
+        Ok(encoders)
     }
 
-    /// Tries to replace the current connector set with the newly provided one on the next commit.
+    /// Tries to add a new [`connector`](drm::control::connector) driven by the given
+    /// [`encoder`](drm::control::encoder) to be used after the next commit.
+    ///
+    /// Fails with [`Error::NoSuitableEncoder`], if the encoder is not one of the
+    /// [`available_encoders`](DrmSurface::available_encoders) of the connector.
+    /// Otherwise this behaves like [`add_connector`](DrmSurface::add_connector).
+    ///
+    /// *Note*: Neither the atomic nor the legacy drm api allow to explicitly route a connector through an encoder,
+    /// the kernel always selects the encoder on commit. For connectors with a single possible encoder
+    /// for this crtc (e.g. a specific DP-MST stream encoder) the selection is deterministic,
+    /// otherwise the kernel may pick a different one. The encoder in use can be checked after
+    /// the commit via the `current_encoder` of the connector info.
+    pub fn add_connector_with_encoder(
+        &self,
+        connector: connector::Handle,
+        encoder: encoder::Handle,
+    ) -> Result<(), Error> {
+        if !self.available_encoders(connector)?.contains(&encoder) {
+            return Err(Error::NoSuitableEncoder {
+                connector,
+                crtc: self.crtc,
+            });
+        }
+        self.add_connector(connector)
+    }
+
+    /// Returns the [`encoder`](drm::control::encoder) currently used by each of the
+    /// [`current_connectors`](DrmSurface::current_connectors) of this surface.
+    ///
+    /// The kernel selects the encoders on commit
+    /// (see [`add_connector_with_encoder`](DrmSurface::add_connector_with_encoder)),
+    /// this allows to inspect the selection, e.g. to debug clone setups on legacy devices.
+    /// Connectors without an active encoder are omitted.
+    pub fn connector_encoders(&self) -> Result<Vec<(connector::Handle, encoder::Handle)>, Error> {
+        let mut encoders = Vec::new();
+        for connector in self.current_connectors() {
+            let info = self
+                .get_connector(connector, false)
+                .map_err(|source| Error::Access {
+                    errmsg: "Error loading connector info",
+                    dev: self.dev_path(),
+                    source,
+                })?;
+            if let Some(encoder) = info.current_encoder() {
+                encoders.push((connector, encoder));
+            }
+        }
+
+        Ok(encoders)
+    }
+
+    /// Tries to mark a [`connector`](drm::control::connector)
+    /// for removal on the next commit.
+    pub fn remove_connector(&self, connector: connector::Handle) -> Result<(), Error> {
+        match &*self.internal {
+            DrmSurfaceInternal::Atomic(surf) => surf.remove_connector(connector),
+            DrmSurfaceInternal::Legacy(surf) => surf.remove_connector(connector),
+        }
+    }
+
+    /// Tries to replace the current connector set with the newly provided one on the next commit.
     ///
     /// Fails if one new `connector` is not compatible with the underlying [`crtc`](drm::control::crtc)
     /// (e.g. no suitable [`encoder`](drm::control::encoder) may be found)
@@ -253,6 +1117,21 @@ impl DrmSurface {
         }
     }
 
+    /// Tries to replace the current connector set and the mode together on the next commit.
+    ///
+    /// Unlike calling [`set_connectors`](DrmSurface::set_connectors) followed by [`use_mode`](DrmSurface::use_mode),
+    /// the new connectors are only validated against the new mode, so switching to displays not supporting
+    /// the currently pending mode (e.g. when docking) does not fail. On atomic devices both changes are
+    /// validated with a single test commit. If the validation fails, the pending state is left untouched.
+    ///
+    /// *Note*: Legacy devices only accept modes advertised by all of the connectors.
+    pub fn reconfigure(&self, connectors: &[connector::Handle], mode: Mode) -> Result<(), Error> {
+        match &*self.internal {
+            DrmSurfaceInternal::Atomic(surf) => surf.reconfigure(connectors, mode),
+            DrmSurfaceInternal::Legacy(surf) => surf.reconfigure(connectors, mode),
+        }
+    }
+
     /// Returns the currently active [`Mode`](drm::control::Mode)
     /// of the underlying [`crtc`](drm::control::crtc)
     pub fn current_mode(&self) -> Mode {
@@ -271,12 +1150,47 @@ impl DrmSurface {
         }
     }
 
+    /// Returns the `MODE_ID` blob created for the currently pending [`Mode`](drm::control::Mode).
+    ///
+    /// This is only meant for debugging, e.g. to cross-reference the blob with dumps of the atomic state.
+    /// The blob is destroyed once the pending mode is replaced and no commit uses it anymore.
+    /// Returns `None` on legacy devices, which do not use mode blobs.
+    pub fn pending_mode_blob(&self) -> Option<property::Value<'_>> {
+        match &*self.internal {
+            DrmSurfaceInternal::Atomic(surf) => surf.pending_mode_blob(),
+            DrmSurfaceInternal::Legacy(_) => None,
+        }
+    }
+
+    /// Returns the size of the currently active [`Mode`](drm::control::Mode)
+    pub fn current_mode_size(&self) -> Size<i32, Physical> {
+        mode_size(&self.current_mode())
+    }
+
+    /// Returns the size of the currently pending [`Mode`](drm::control::Mode)
+    /// to be used after the next commit.
+    pub fn pending_mode_size(&self) -> Size<i32, Physical> {
+        mode_size(&self.pending_mode())
+    }
+
+    /// Returns the logical size of the output using the currently active [`Mode`](drm::control::Mode).
+    ///
+    /// The mode size is rotated by `transform` and divided by `scale`, if any, rounding up
+    /// like the output geometry of a `Space`.
+    pub fn logical_size(&self, transform: Transform, scale: impl Into<Option<f64>>) -> Size<i32, Logical> {
+        logical_size(self.current_mode_size(), transform, scale.into())
+    }
+
     /// Tries to set a new [`Mode`](drm::control::Mode)
     /// to be used after the next commit.
     ///
     /// Fails if the mode is not compatible with the underlying
     /// [`crtc`](drm::control::crtc) or any of the
     /// pending [`connector`](drm::control::connector)s.
+    ///
+    /// On atomic devices the mode is validated with a test commit, so custom modes
+    /// (e.g. generated by [`cvt_mode`](super::cvt_mode)) can be used. Legacy devices
+    /// only accept modes advertised by the connectors.
     pub fn use_mode(&self, mode: Mode) -> Result<(), Error> {
         match &*self.internal {
             DrmSurfaceInternal::Atomic(surf) => surf.use_mode(mode),
@@ -284,15 +1198,464 @@ impl DrmSurface {
         }
     }
 
+    /// Tries to set a new [`ScalingMode`] for all connectors of this surface.
+    ///
+    /// Fails if any of the pending connectors does not support the `scaling mode` property
+    /// or the given mode.
+    ///
+    /// This is stored in the pending state and applied on the next [`commit`](DrmSurface::commit).
+    /// Connectors added later will use the same scaling mode.
+    pub fn set_scaling_mode(&self, mode: ScalingMode) -> Result<(), Error> {
+        match &*self.internal {
+            DrmSurfaceInternal::Atomic(surf) => surf.set_scaling_mode(mode),
+            DrmSurfaceInternal::Legacy(surf) => surf.set_scaling_mode(mode),
+        }
+    }
+
+    /// Returns the position suggested for the given connector by the `suggested X` and `suggested Y` properties.
+    ///
+    /// These are exposed by virtual gpus (e.g. `virtio-gpu` or `qxl`) to hint where the display
+    /// is placed in the layout of the host. Returns `None` if the properties are not available.
+    pub fn suggested_position(
+        &self,
+        connector: connector::Handle,
+    ) -> Result<Option<Point<i32, Logical>>, Error> {
+        let x = self.property_value(connector, "suggested X")?;
+        let y = self.property_value(connector, "suggested Y")?;
+        Ok(x.zip(y).map(|((_, x), (_, y))| (x as i32, y as i32).into()))
+    }
+
+    /// Returns the [`TileInfo`] of the given connector, if it drives a tile of a tiled display.
+    ///
+    /// Returns `None` if the connector has no `TILE` property or it is not set.
+    pub fn tile_info(&self, connector: connector::Handle) -> Result<Option<TileInfo>, Error> {
+        let Some((_, blob)) = self.property_value(connector, "TILE")? else {
+            return Ok(None);
+        };
+        if blob == 0 {
+            return Ok(None);
+        }
+
+        let data = self.get_property_blob(blob).map_err(|source| Error::Access {
+            errmsg: "Failed to query property blob data",
+            dev: self.dev_path(),
+            source,
+        })?;
+        let info = TileInfo::from_blob(&data);
+        if info.is_none() {
+            warn!(
+                ?connector,
+                "Failed to parse TILE property: {:?}",
+                String::from_utf8_lossy(&data)
+            );
+        }
+        Ok(info)
+    }
+
+    /// Returns the range of refresh rates in Hz, the displays of the current connectors support with variable refresh rate.
+    ///
+    /// The range is read from the display range limits of the EDID of each connector.
+    /// Frames should be submitted at least at the minimum rate while adaptive sync is enabled,
+    /// otherwise the display may flicker. If multiple connectors are used, the intersection of their ranges
+    /// is returned. Returns `None` if any connector does not advertise a range or the ranges do not overlap.
+    pub fn vrr_active_range(&self) -> Result<Option<(u32, u32)>, Error> {
+        let mut range: Option<(u32, u32)> = None;
+        for connector in self.current_connectors() {
+            let Some(edid) = self.connector_edid(connector)? else {
+                return Ok(None);
+            };
+            let Some((min, max)) = edid::vrr_range(&edid) else {
+                return Ok(None);
+            };
+            range = Some(match range {
+                Some((prev_min, prev_max)) => (prev_min.max(min), prev_max.min(max)),
+                None => (min, max),
+            });
+        }
+        Ok(range.filter(|(min, max)| min <= max))
+    }
+
+    /// Returns the limits for modes on the given connector, e.g. to filter modes generated via [`cvt_mode`](super::cvt_mode).
+    ///
+    /// The maximum pixel clock is read from the display range limits of the EDID of the connected display.
+    /// Limits of the link itself (e.g. the number of DisplayPort lanes) cannot be queried from the kernel,
+    /// so a mode within these limits may still be rejected. The maximum size is derived from the largest framebuffer
+    /// supported by the device.
+    pub fn connector_limits(&self, connector: connector::Handle) -> Result<ConnectorLimits, Error> {
+        let max_pixel_clock = self
+            .connector_edid(connector)?
+            .and_then(|edid| edid::max_pixel_clock(&edid));
+
+        let resources = self.resource_handles().map_err(|source| Error::Access {
+            errmsg: "Error loading resources",
+            dev: self.dev_path(),
+            source,
+        })?;
+        let max = |bound: Bound<&u32>| match bound {
+            Bound::Included(max) => *max,
+            Bound::Excluded(max) => max.saturating_sub(1),
+            Bound::Unbounded => u32::MAX,
+        };
+        let max_size = (
+            max(resources.supported_fb_width().end_bound()),
+            max(resources.supported_fb_height().end_bound()),
+        )
+            .into();
+
+        Ok(ConnectorLimits {
+            max_pixel_clock,
+            max_size,
+        })
+    }
+
+    /// Returns the desired content luminance of the display connected to `connector`.
+    ///
+    /// The values are read from the HDR static metadata block of the CTA-861 extension of the EDID and
+    /// can be used as the target of tone mapping. Returns `None` if the display does not advertise HDR static metadata.
+    pub fn hdr_luminance(&self, connector: connector::Handle) -> Result<Option<HdrLuminance>, Error> {
+        Ok(self
+            .connector_edid(connector)?
+            .and_then(|edid| edid::hdr_luminance(&edid)))
+    }
+
+    /// Returns whether the display connected to `connector` accepts `mode` with YCbCr 4:2:0 output.
+    ///
+    /// YCbCr 4:2:0 halves the bandwidth needed for a mode and can be the only way to drive high resolution
+    /// modes on bandwidth-limited links, e.g. 4K at 60 Hz over HDMI 2.0. The kernel does not report YCbCr 4:2:0
+    /// support in the flags of a mode, so the size, refresh rate and picture aspect ratio of the mode are matched
+    /// against the YCbCr 4:2:0 data blocks of the EDID. This only covers the video formats commonly offered with
+    /// YCbCr 4:2:0 (2160p and 4320p). The picture aspect ratio is only taken into account, if it is part
+    /// of the mode flags, which requires the `DRM_CLIENT_CAP_ASPECT_RATIO` client capability.
+    /// Interlaced modes never support YCbCr 4:2:0.
+    ///
+    /// *Note*: There is no property to choose the output format. Drivers use YCbCr 4:2:0 automatically,
+    /// if the mode can't be driven otherwise. The `Colorspace` property only changes the colorimetry
+    /// signalled to the display, not the pixel encoding.
+    pub fn connector_supports_ycbcr420(
+        &self,
+        connector: connector::Handle,
+        mode: Mode,
+    ) -> Result<bool, Error> {
+        let flags = drm_ffi::drm_mode_modeinfo::from(mode).flags;
+        if flags & drm_ffi::DRM_MODE_FLAG_INTERLACE != 0 {
+            return Ok(false);
+        }
+        let aspect_ratio = flags & drm_ffi::DRM_MODE_FLAG_PIC_AR_MASK;
+
+        let (w, h) = mode.size();
+        Ok(self
+            .connector_edid(connector)?
+            .map(|edid| edid::supports_ycbcr420(&edid, (w, h), mode.vrefresh(), aspect_ratio))
+            .unwrap_or(false))
+    }
+
+    /// Returns whether variable refresh rate is usable on the current connectors.
+    ///
+    /// This is the case, if the crtc has a `VRR_ENABLED` property, all current connectors report
+    /// to be `vrr_capable` and their displays advertise an overlapping range of refresh rates,
+    /// see [`vrr_active_range`](DrmSurface::vrr_active_range).
+    pub fn vrr_capability(&self) -> Result<VrrCapability, Error> {
+        let (min_hz, max_hz) = self.vrr_active_range()?.unwrap_or((0, 0));
+        let mut supported = max_hz != 0 && self.property_value(self.crtc, "VRR_ENABLED")?.is_some();
+        for connector in self.current_connectors() {
+            if !supported {
+                break;
+            }
+            supported = matches!(self.property_value(connector, "vrr_capable")?, Some((_, 1)));
+        }
+
+        Ok(VrrCapability {
+            supported,
+            min_hz,
+            max_hz,
+        })
+    }
+
+    // Returns the content of the `EDID` property of the given connector
+    fn connector_edid(&self, connector: connector::Handle) -> Result<Option<Vec<u8>>, Error> {
+        let Some((_, blob)) = self.property_value(connector, "EDID")? else {
+            return Ok(None);
+        };
+        if blob == 0 {
+            return Ok(None);
+        }
+
+        self.get_property_blob(blob)
+            .map(Some)
+            .map_err(|source| Error::Access {
+                errmsg: "Failed to query property blob data",
+                dev: self.dev_path(),
+                source,
+            })
+    }
+
+    /// Captures the current value of all mutable properties of the given connector.
+    ///
+    /// This includes the color related state like `Colorspace`, `HDR_OUTPUT_METADATA` or
+    /// `max bpc`, which may be restored later via [`restore_connector_properties`](DrmSurface::restore_connector_properties),
+    /// e.g. after another client changed them. `CRTC_ID` and `DPMS` are managed by the surface and not captured.
+    pub fn connector_property_snapshot(
+        &self,
+        connector: connector::Handle,
+    ) -> Result<PropertySnapshot, Error> {
+        PropertySnapshot::new(self.device_fd(), connector)
+    }
+
+    /// Stages all values of a [`PropertySnapshot`] to be restored on the next [`commit`](DrmSurface::commit).
+    ///
+    /// All properties are applied together in that single commit.
+    /// Staging another snapshot of the same connector replaces the previous one.
+    pub fn restore_connector_properties(&self, snapshot: &PropertySnapshot) -> Result<(), Error> {
+        match &*self.internal {
+            DrmSurfaceInternal::Atomic(surf) => surf.restore_connector_properties(snapshot),
+            DrmSurfaceInternal::Legacy(surf) => surf.restore_connector_properties(snapshot),
+        }
+    }
+
+    /// Powers the crtc of this surface up or down.
+    ///
+    /// On atomic devices this sets the `ACTIVE` property of the crtc, which powers down the whole pipe
+    /// and saves more power than just disabling all planes. On legacy devices this maps
+    /// to [`set_dpms`](DrmSurface::set_dpms) with [`DpmsState::Off`] or [`DpmsState::On`].
+    /// This is applied immediately and does not touch the pending state.
+    ///
+    /// The next [`commit`](DrmSurface::commit) or [`page_flip`](DrmSurface::page_flip)
+    /// re-activates the crtc automatically.
+    pub fn set_active(&self, active: bool) -> Result<(), Error> {
+        self.ensure_not_test_only()?;
+        match &*self.internal {
+            DrmSurfaceInternal::Atomic(surf) => surf.set_active(active),
+            DrmSurfaceInternal::Legacy(surf) => {
+                surf.set_dpms(if active { DpmsState::On } else { DpmsState::Off })
+            }
+        }
+        .map_err(|err| self.map_error(err))
+    }
+
+    /// Returns whether the crtc of this surface is currently powered,
+    /// see [`set_active`](DrmSurface::set_active).
+    pub fn is_crtc_active(&self) -> bool {
+        match &*self.internal {
+            DrmSurfaceInternal::Atomic(surf) => surf.is_crtc_active(),
+            DrmSurfaceInternal::Legacy(surf) => surf.is_crtc_active(),
+        }
+    }
+
+    /// Returns the number of entries of the legacy gamma ramp of the crtc.
+    pub fn gamma_length(&self) -> Result<u32, Error> {
+        let info = self.get_crtc(self.crtc).map_err(|source| Error::Access {
+            errmsg: "Error loading crtc info",
+            dev: self.dev_path(),
+            source,
+        })?;
+        Ok(info.gamma_length())
+    }
+
+    /// Sets the legacy gamma ramp of the crtc.
+    ///
+    /// Every channel needs to have [`gamma_length`](DrmSurface::gamma_length) entries.
+    /// This is applied immediately and does not touch the pending state.
+    pub fn set_gamma(&self, red: &[u16], green: &[u16], blue: &[u16]) -> Result<(), Error> {
+        self.ensure_not_test_only()?;
+        ControlDevice::set_gamma(self, self.crtc, red, green, blue)
+            .map_err(|source| Error::Access {
+                errmsg: "Failed to set gamma",
+                dev: self.dev_path(),
+                source,
+            })
+            .map_err(|err| self.map_error(err))
+    }
+
+    /// Returns the current gamma ramp of the crtc.
+    ///
+    /// On atomic devices with a `GAMMA_LUT` set, the ramp has
+    /// [`gamma_lut_size`](DrmSurface::gamma_lut_size) entries and can be restored via
+    /// [`DrmDevice::commit_gamma`](super::DrmDevice::commit_gamma). Otherwise the legacy gamma ramp
+    /// is returned, which has [`gamma_length`](DrmSurface::gamma_length) entries and can be restored via
+    /// [`set_gamma`](DrmSurface::set_gamma).
+    pub fn get_gamma(&self) -> Result<GammaRamp, Error> {
+        if let DrmSurfaceInternal::Atomic(_) = &*self.internal {
+            if let Some((_, blob)) = self.property_value(self.crtc, "GAMMA_LUT")? {
+                if blob != 0 {
+                    let data = self.get_property_blob(blob).map_err(|source| Error::Access {
+                        errmsg: "Failed to query property blob data",
+                        dev: self.dev_path(),
+                        source,
+                    })?;
+                    return Ok(GammaRamp::from_lut_blob(&data));
+                }
+            }
+        }
+
+        let length = self.gamma_length()? as usize;
+        let mut ramp = GammaRamp {
+            red: vec![0; length],
+            green: vec![0; length],
+            blue: vec![0; length],
+        };
+        ControlDevice::get_gamma(self, self.crtc, &mut ramp.red, &mut ramp.green, &mut ramp.blue).map_err(
+            |source| Error::Access {
+                errmsg: "Failed to get gamma",
+                dev: self.dev_path(),
+                source,
+            },
+        )?;
+        Ok(ramp)
+    }
+
+    /// Returns the number of entries of the `GAMMA_LUT` of the crtc,
+    /// or `None` if the device does not expose one.
+    ///
+    /// The `GAMMA_LUT` usually has a much higher resolution than the legacy gamma ramp
+    /// and is used by [`set_gamma_curve`](DrmSurface::set_gamma_curve) if available.
+    /// Only atomic devices expose it.
+    pub fn gamma_lut_size(&self) -> Result<Option<u32>, Error> {
+        match &*self.internal {
+            DrmSurfaceInternal::Atomic(surf) => surf.gamma_lut_size(),
+            DrmSurfaceInternal::Legacy(_) => Ok(None),
+        }
+    }
+
+    /// Sets a piecewise linear gamma curve described by a number of [`GammaCurveSegment`]s.
+    ///
+    /// The segments are evaluated into the `GAMMA_LUT` of the crtc, if the device exposes it
+    /// (see [`gamma_lut_size`](DrmSurface::gamma_lut_size)), otherwise they are evaluated into
+    /// the flat gamma ramp set by [`set_gamma`](DrmSurface::set_gamma).
+    /// This is applied immediately and does not touch the pending state.
+    ///
+    /// *Note*: Segmented hardware luts, as exposed by some newer hardware, are neither detected nor used.
+    /// The curve is always sampled into one of the flat luts above, so its precision is limited by their size.
+    pub fn set_gamma_curve(&self, segments: &[GammaCurveSegment]) -> Result<(), Error> {
+        self.ensure_not_test_only()?;
+        if let DrmSurfaceInternal::Atomic(surf) = &*self.internal {
+            if let Some(size) = surf.gamma_lut_size()? {
+                let lut = gamma::sample_segments(segments, size as usize);
+                return surf.set_gamma_lut(&lut).map_err(|err| self.map_error(err));
+            }
+        }
+
+        let lut = gamma::sample_segments(segments, self.gamma_length()? as usize);
+        let (red, (green, blue)): (Vec<u16>, (Vec<u16>, Vec<u16>)) =
+            lut.into_iter().map(|[r, g, b]| (r, (g, b))).unzip();
+        self.set_gamma(&red, &green, &blue)
+    }
+
+    /// Changes the power state of the connectors of this surface.
+    ///
+    /// On legacy devices this sets the `DPMS` property of the current connectors.
+    /// Atomic devices do not support the intermediate power levels, so any state other than
+    /// [`DpmsState::On`] disables the crtc, while [`DpmsState::On`] enables it again using the current mode.
+    /// This is applied immediately and does not touch the pending state.
+    ///
+    /// *Note*: The next [`commit`](DrmSurface::commit) or [`page_flip`](DrmSurface::page_flip)
+    /// will turn the display on again.
+    pub fn set_dpms(&self, state: DpmsState) -> Result<(), Error> {
+        self.ensure_not_test_only()?;
+        match &*self.internal {
+            DrmSurfaceInternal::Atomic(surf) => surf.set_dpms(state),
+            DrmSurfaceInternal::Legacy(surf) => surf.set_dpms(state),
+        }
+        .map_err(|err| self.map_error(err))
+    }
+
+    /// Returns whether the given connector has a power state, that can be controlled via [`set_dpms`](DrmSurface::set_dpms).
+    ///
+    /// This is the case for connectors exposing a `DPMS` property, which all connectors driving
+    /// a physical display do. Special connectors like writeback connectors lack it.
+    /// Returns `false` if the properties of the connector can't be queried.
+    pub fn supports_dpms(&self, connector: connector::Handle) -> bool {
+        matches!(self.property_value(connector, "DPMS"), Ok(Some(_)))
+    }
+
+    /// Enables or disables panel self-refresh on the current connectors.
+    ///
+    /// This requires the driver to expose a `PSR` connector property. Mainline kernels currently only
+    /// allow to control panel self-refresh via debugfs, in which case this fails with
+    /// [`Error::UnknownProperty`] and the kernel default stays in effect.
+    /// This is applied immediately and does not touch the pending state.
+    pub fn set_psr(&self, enabled: bool) -> Result<(), Error> {
+        self.ensure_not_test_only()?;
+        for conn in self.current_connectors() {
+            let (info, _) = self
+                .property_value(conn, PSR_PROPERTY)?
+                .ok_or(Error::UnknownProperty {
+                    handle: conn.into(),
+                    name: PSR_PROPERTY,
+                })?;
+            trace!(connector = ?conn, "Setting PSR {}", enabled);
+            self.set_property(conn, info.handle(), enabled as u64)
+                .map_err(|source| Error::Access {
+                    errmsg: "Failed to set PSR state",
+                    dev: self.dev_path(),
+                    source,
+                })
+                .map_err(|err| self.map_error(err))?;
+        }
+        Ok(())
+    }
+
+    /// Returns whether panel self-refresh is enabled on all current connectors exposing a `PSR` property.
+    ///
+    /// Returns `None`, if none of the connectors exposes it, see [`set_psr`](DrmSurface::set_psr).
+    pub fn psr_state(&self) -> Result<Option<bool>, Error> {
+        let mut state = None;
+        for conn in self.current_connectors() {
+            if let Some((_, value)) = self.property_value(conn, PSR_PROPERTY)? {
+                state = Some(state.unwrap_or(true) && value != 0);
+            }
+        }
+        Ok(state)
+    }
+
+    /// Attaches a writeback connector to the crtc, capturing the composited output into `fb`.
+    ///
+    /// The next frame scanned out by the crtc is written into the framebuffer. If `out_fence` is set,
+    /// a sync_file is returned, which signals once the framebuffer was written.
+    /// This is applied immediately and fails while another update is still pending, just like a page flip.
+    /// The connector stays attached to the crtc until the surface is dropped and has to be attached with
+    /// a new framebuffer for every frame, that should be captured.
+    ///
+    /// Writeback connectors are only listed by the kernel after enabling the
+    /// `WritebackConnectors` client capability on the device. Legacy devices do not support them and fail
+    /// with [`Error::UnknownProperty`].
+    pub fn attach_writeback(
+        &self,
+        connector: connector::Handle,
+        fb: framebuffer::Handle,
+        out_fence: bool,
+    ) -> Result<Option<OwnedFd>, Error> {
+        self.ensure_not_test_only()?;
+        match &*self.internal {
+            DrmSurfaceInternal::Atomic(surf) => surf.attach_writeback(connector, fb, out_fence),
+            DrmSurfaceInternal::Legacy(_) => Err(Error::UnknownProperty {
+                handle: connector.into(),
+                name: "WRITEBACK_FB_ID",
+            }),
+        }
+        .map_err(|err| self.map_error(err))
+    }
+
     /// Disables the given plane.
     ///
     /// Errors if the plane is not supported by this crtc or if the underlying
     /// implementation does not support the use of planes.
     pub fn clear_plane(&self, plane: plane::Handle) -> Result<(), Error> {
+        self.ensure_not_test_only()?;
         match &*self.internal {
             DrmSurfaceInternal::Atomic(surf) => surf.clear_plane(plane),
             DrmSurfaceInternal::Legacy(_) => Err(Error::NonPrimaryPlane(plane)),
-        }
+        }?;
+        // clearing a plane blocks until the commit completed
+        self.buffer_release.queue([(plane, None)]);
+        self.buffer_release.flipped();
+        update_committed_planes(
+            &mut self.committed_planes.lock().unwrap(),
+            [PlaneState {
+                handle: plane,
+                config: None,
+            }],
+        );
+        Ok(())
     }
 
     /// Returns true whenever any state changes are pending to be commited
@@ -308,30 +1671,52 @@ impl DrmSurface {
         }
     }
 
+    /// Discards all pending changes, resetting the pending state to the current one.
+    ///
+    /// Afterwards [`commit_pending`](DrmSurface::commit_pending) returns `false`,
+    /// until the pending state is modified again.
+    pub fn discard_pending(&self) {
+        match &*self.internal {
+            DrmSurfaceInternal::Atomic(surf) => surf.discard_pending(),
+            DrmSurfaceInternal::Legacy(surf) => surf.discard_pending(),
+        }
+    }
+
     /// Test a state given a set of framebuffers.
     ///
-    /// *Note*: This will always return `Ok` for legacy devices if `allow_modeset = false`.
+    /// Framebuffers registered via [`register_external_framebuffer`](DrmSurface::register_external_framebuffer)
+    /// are additionally checked against the formats supported by their plane and against their source rectangle.
+    ///
+    /// *Note*: This will always return `Ok` for legacy devices if `allow_modeset = false`
+    /// or the surface is in [test-only mode](DrmSurface::set_test_only).
     /// The legacy drm api has no way to test a buffer without triggering a modeset.
     pub fn test_state<'a>(
         &self,
         planes: impl IntoIterator<Item = PlaneState<'a>>,
         allow_modeset: bool,
     ) -> Result<(), Error> {
+        let planes = planes.into_iter().collect::<Vec<_>>();
+        self.validate_external_framebuffers(&planes)?;
+        #[cfg(feature = "backend_drm_failure_injection")]
+        self.injected_failures
+            .check(super::test::Operation::TestState, self)?;
+
         match &*self.internal {
             DrmSurfaceInternal::Atomic(surf) => surf.test_state(planes, allow_modeset),
             DrmSurfaceInternal::Legacy(surf) => {
                 let fb = ensure_legacy_planes(self, planes)?;
 
-                if allow_modeset {
+                if allow_modeset && !self.is_test_only() {
                     surf.test_buffer(fb, &self.pending_mode())
                 } else {
                     // Legacy can not test a buffer without triggering a modeset, so we can
                     // only assume it works and hope for the best. A later call to commit or
-                    // page_flip will show the correct result
+                    // page_flip will show the correct result (test-only surfaces never modeset)
                     Ok(())
                 }
             }
         }
+        .map_err(|err| self.map_error(err))
     }
 
     /// Commit the pending state rendering a given set of framebuffers.
@@ -344,43 +1729,814 @@ impl DrmSurface {
     /// but will trigger a `vblank` event once done.
     /// Make sure to have the device registered in your event loop prior to invoking this, to not miss
     /// any generated event.
+    ///
+    /// The primary plane needs to be part of `planes`, unless a previous commit already attached
+    /// a framebuffer to it, otherwise this fails with [`Error::MissingPrimaryPlane`].
+    ///
+    /// Returns the [`CommitKind`] of the performed update.
     pub fn commit<'a>(
         &self,
         planes: impl IntoIterator<Item = PlaneState<'a>>,
         event: bool,
-    ) -> Result<(), Error> {
-        match &*self.internal {
-            DrmSurfaceInternal::Atomic(surf) => surf.commit(planes, event),
-            DrmSurfaceInternal::Legacy(surf) => {
-                let fb = ensure_legacy_planes(self, planes)?;
-                surf.commit(fb, event)
-            }
-        }
+    ) -> Result<CommitKind, Error> {
+        self.commit_with_nonblock(planes, event, false)
     }
 
-    /// Page-flip the underlying [`crtc`](drm::control::crtc)
-    /// to a new given set of [`framebuffer`]s.
+    /// Commit the pending state rendering a given set of framebuffers, retrying without damage clips
+    /// if the driver rejects them.
     ///
-    /// This will not cause the crtc to modeset.
+    /// Behaves like [`commit`](DrmSurface::commit), but if the commit or its preceding atomic test fails
+    /// with `EINVAL` and any plane carries [`damage_clips`](PlaneConfig::damage_clips), the commit is retried
+    /// once with the damage clips removed from all planes, updating the whole planes instead.
+    /// This works around drivers rejecting valid `FB_DAMAGE_CLIPS`.
     ///
-    /// This operation is not blocking and will produce a `vblank` event once swapping is done.
-    /// Make sure to have the device registered in your event loop to not miss the event.
-    pub fn page_flip<'a>(
+    /// Returns the [`CommitKind`] of the performed update and whether the fallback was used.
+    pub fn commit_with_damage_fallback<'a>(
         &self,
         planes: impl IntoIterator<Item = PlaneState<'a>>,
         event: bool,
-    ) -> Result<(), Error> {
-        match &*self.internal {
-            DrmSurfaceInternal::Atomic(surf) => surf.page_flip(planes, event),
+    ) -> Result<(CommitKind, bool), Error> {
+        with_damage_fallback(planes.into_iter().collect(), |planes| self.commit(planes, event))
+    }
+
+    /// Commit the pending state rendering a given set of framebuffers, optionally without blocking.
+    ///
+    /// Behaves like [`commit`](DrmSurface::commit), which is equal to calling this with `nonblock = false`.
+    ///
+    /// If `nonblock` is set, the commit is submitted with `DRM_MODE_ATOMIC_NONBLOCK` and returns
+    /// as soon as it was queued instead of waiting for the modeset to complete. Completion can then only be
+    /// observed through the `vblank` event, so `nonblock` should be combined with `event = true`.
+    /// If a previous commit is still pending, the kernel rejects the commit with `EBUSY`
+    /// instead of waiting for it. The resulting [`Error::Access`] converts into a
+    /// [`SwapBuffersError::TemporaryFailure`](crate::backend::SwapBuffersError::TemporaryFailure),
+    /// so the commit may just be retried after the next `vblank` event.
+    ///
+    /// *Note*: Legacy devices cannot modeset without blocking, `nonblock` is ignored for them.
+    pub fn commit_with_nonblock<'a>(
+        &self,
+        planes: impl IntoIterator<Item = PlaneState<'a>>,
+        event: bool,
+        nonblock: bool,
+    ) -> Result<CommitKind, Error> {
+        self.commit_internal(planes.into_iter().collect(), event, nonblock, true, true)
+    }
+
+    /// Tests the pending state with the given set of framebuffers and commits it, if the test succeeds.
+    ///
+    /// Behaves like [`commit`](DrmSurface::commit), which on atomic devices already tests the request
+    /// with `TEST_ONLY` and reuses it for the actual commit, so calling [`test_state`](DrmSurface::test_state)
+    /// beforehand is not necessary.
+    ///
+    /// If `allow_modeset` is `false`, the commit fails with [`Error::TestFailed`], if it would require a modeset,
+    /// e.g. because connectors or the mode are pending.
+    ///
+    /// Returns the [`CommitKind`] of the performed update.
+    pub fn test_and_commit<'a>(
+        &self,
+        planes: impl IntoIterator<Item = PlaneState<'a>>,
+        allow_modeset: bool,
+        event: bool,
+    ) -> Result<CommitKind, Error> {
+        #[cfg(feature = "backend_drm_failure_injection")]
+        self.injected_failures
+            .check(super::test::Operation::TestState, self)?;
+        let planes = planes.into_iter().collect::<Vec<_>>();
+        self.validate_external_framebuffers(&planes)?;
+        if !allow_modeset && self.commit_pending() {
+            return Err(Error::TestFailed(self.crtc, None));
+        }
+
+        self.commit_internal(planes, event, false, allow_modeset, true)
+    }
+
+    fn commit_internal(
+        &self,
+        planes: Vec<PlaneState<'_>>,
+        event: bool,
+        nonblock: bool,
+        allow_modeset: bool,
+        paced: bool,
+    ) -> Result<CommitKind, Error> {
+        self.ensure_not_test_only()?;
+        #[cfg(feature = "backend_drm_failure_injection")]
+        self.injected_failures
+            .check(super::test::Operation::Commit, self)?;
+        self.frame_counter.check_flip_timeout(self.crtc)?;
+        let kind = if self.commit_pending() {
+            CommitKind::Modeset
+        } else if planes.is_empty() {
+            CommitKind::NoOp
+        } else {
+            CommitKind::PageFlip
+        };
+        let vblank = if paced { Some(self.paced_vblank()?) } else { None };
+        let owned_planes = planes.iter().cloned().map(PlaneState::detach).collect::<Vec<_>>();
+
+        let framebuffers = match &*self.internal {
+            DrmSurfaceInternal::Atomic(surf) => {
+                let framebuffers = plane_framebuffers(&planes);
+                surf.commit(planes, event, nonblock, allow_modeset)
+                    .map(|_| framebuffers)
+            }
             DrmSurfaceInternal::Legacy(surf) => {
                 let fb = ensure_legacy_planes(self, planes)?;
-                surf.page_flip(fb, event)
+                surf.commit(fb, event).map(|_| vec![(self.primary, Some(fb))])
+            }
+        }
+        .map_err(|err| self.map_error(err))?;
+        self.buffer_release.queue(framebuffers);
+        update_committed_planes(&mut self.committed_planes.lock().unwrap(), owned_planes);
+
+        if event {
+            self.frame_counter.flip_requested();
+        }
+        if kind != CommitKind::NoOp {
+            self.frame_counter.committed();
+            if let Some(vblank) = vblank {
+                self.frame_counter.pacing.lock().unwrap().last_vblank = Some(vblank);
             }
         }
+        if kind == CommitKind::Modeset {
+            debug!(crtc = ?self.crtc, "Commit performed a modeset");
+        }
+        Ok(kind)
+    }
+
+    /// Commit the pending connectors and mode, keeping the currently attached planes.
+    ///
+    /// This is useful to e.g. add another connector to an already running surface for mirroring,
+    /// without having to provide a new set of framebuffers.
+    /// On atomic devices the plane state is left untouched, on legacy devices the framebuffer
+    /// currently scanned out by the crtc is used again.
+    ///
+    /// *Note*: Like [`commit`](DrmSurface::commit) this will trigger a full modeset.
+    /// If the pending mode has a different size, the current plane configuration
+    /// might not be valid anymore and this will fail.
+    pub fn commit_connectors(&self, event: bool) -> Result<(), Error> {
+        self.ensure_not_test_only()?;
+        match &*self.internal {
+            DrmSurfaceInternal::Atomic(surf) => {
+                surf.commit(std::iter::empty::<PlaneState<'_>>(), event, false, true)
+            }
+            DrmSurfaceInternal::Legacy(surf) => {
+                let fb = self
+                    .get_crtc(self.crtc)
+                    .map_err(|source| Error::Access {
+                        errmsg: "Error loading crtc info",
+                        dev: self.dev_path(),
+                        source,
+                    })?
+                    .framebuffer()
+                    .ok_or(Error::NoFramebuffer(self.primary))?;
+                surf.commit(fb, event)
+            }
+        }
+        .map_err(|err| self.map_error(err))
+    }
+
+    /// Commit the pending state rendering a given set of framebuffers and
+    /// block until the resulting page flip has been completed.
+    ///
+    /// This is useful for paths, where no event loop is running anymore,
+    /// e.g. on shutdown or before a VT switch.
+    ///
+    /// *Note*: This reads events directly from the device. Any events for other crtcs
+    /// of the same device received while waiting are discarded, so this should not be used
+    /// while the [`DrmDeviceNotifier`](super::DrmDeviceNotifier) is still being dispatched.
+    ///
+    /// The commit still requests a page flip event, as that is the only way the device reports
+    /// the completed flip, but the event is consumed here instead of being delivered to the notifier.
+    /// Unlike [`commit`](DrmSurface::commit) this is not subject to
+    /// [frame pacing](DrmSurface::enable_frame_pacing).
+    ///
+    /// Fails with [`Error::FlipTimeout`] if no page flip was observed within `timeout`.
+    pub fn commit_blocking<'a>(
+        &self,
+        planes: impl IntoIterator<Item = PlaneState<'a>>,
+        timeout: Duration,
+    ) -> Result<(), Error> {
+        self.commit_internal(planes.into_iter().collect(), true, false, true, false)?;
+        self.wait_for_page_flip(timeout)
+    }
+
+    fn wait_for_page_flip(&self, timeout: Duration) -> Result<(), Error> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(Error::FlipTimeout(self.crtc));
+            }
+
+            let mut fds = [nix::poll::PollFd::new(
+                self.as_fd().as_raw_fd(),
+                nix::poll::PollFlags::POLLIN,
+            )];
+            match nix::poll::poll(&mut fds, remaining.as_millis().min(i32::MAX as u128) as i32) {
+                Ok(0) | Err(nix::errno::Errno::EINTR) => continue,
+                Ok(_) => {}
+                Err(errno) => {
+                    return Err(Error::Access {
+                        errmsg: "Failed to poll for page flip",
+                        dev: self.dev_path(),
+                        source: errno.into(),
+                    })
+                }
+            }
+
+            let events = self.receive_events().map_err(|source| Error::Access {
+                errmsg: "Error processing drm events",
+                dev: self.dev_path(),
+                source,
+            })?;
+            for event in events {
+                if let Event::PageFlip(event) = event {
+                    if event.crtc == self.crtc {
+                        self.frame_counter.vblank(event.frame);
+                        self.buffer_release.flipped();
+                        return Ok(());
+                    }
+                    trace!("Discarding page-flip event for crtc ({:?})", event.crtc);
+                }
+            }
+        }
+    }
+
+    /// Page-flip the underlying [`crtc`](drm::control::crtc)
+    /// to a new given set of [`framebuffer`]s.
+    ///
+    /// This will not cause the crtc to modeset.
+    ///
+    /// This operation is not blocking and will produce a `vblank` event once swapping is done.
+    /// Make sure to have the device registered in your event loop to not miss the event.
+    ///
+    /// Like [`commit`](DrmSurface::commit), this fails with [`Error::MissingPrimaryPlane`]
+    /// if the primary plane would be left without a framebuffer.
+    pub fn page_flip<'a>(
+        &self,
+        planes: impl IntoIterator<Item = PlaneState<'a>>,
+        event: bool,
+    ) -> Result<(), Error> {
+        self.page_flip_internal(planes, event, false)
+    }
+
+    /// Page-flip the underlying [`crtc`](drm::control::crtc) asynchronously,
+    /// without waiting for the next vblank.
+    ///
+    /// Behaves like [`page_flip`](DrmSurface::page_flip), but the new framebuffers are scanned out immediately,
+    /// which reduces latency at the cost of visible tearing. As tearing should always be an explicit choice,
+    /// this fails with [`Error::TearingNotAllowed`] unless it was enabled via
+    /// [`set_allow_tearing`](DrmSurface::set_allow_tearing).
+    ///
+    /// *Note*: Many drivers only support asynchronous flips that just change the framebuffer of the primary plane,
+    /// any other configuration (e.g. additional planes or changed plane positions) will be rejected by the kernel.
+    /// Asynchronous flips are only supported on atomic devices by recent kernels.
+    pub fn page_flip_async<'a>(
+        &self,
+        planes: impl IntoIterator<Item = PlaneState<'a>>,
+        event: bool,
+    ) -> Result<(), Error> {
+        if !self.allows_tearing() {
+            return Err(Error::TearingNotAllowed(self.crtc));
+        }
+        self.page_flip_internal(planes, event, true)
+    }
+
+    fn page_flip_internal<'a>(
+        &self,
+        planes: impl IntoIterator<Item = PlaneState<'a>>,
+        event: bool,
+        asynchronous: bool,
+    ) -> Result<(), Error> {
+        self.ensure_not_test_only()?;
+        #[cfg(feature = "backend_drm_failure_injection")]
+        self.injected_failures
+            .check(super::test::Operation::PageFlip, self)?;
+        self.frame_counter.check_flip_timeout(self.crtc)?;
+        let vblank = self.paced_vblank()?;
+        let planes = planes.into_iter().collect::<Vec<_>>();
+        let owned_planes = planes.iter().cloned().map(PlaneState::detach).collect::<Vec<_>>();
+        let framebuffers = match &*self.internal {
+            DrmSurfaceInternal::Atomic(surf) => {
+                let framebuffers = plane_framebuffers(&planes);
+                surf.page_flip(planes, event, asynchronous).map(|_| framebuffers)
+            }
+            DrmSurfaceInternal::Legacy(surf) => {
+                let fb = ensure_legacy_planes(self, planes)?;
+                surf.page_flip(fb, event, asynchronous)
+                    .map(|_| vec![(self.primary, Some(fb))])
+            }
+        }
+        .map_err(|err| self.map_error(err))?;
+        self.buffer_release.queue(framebuffers);
+        update_committed_planes(&mut self.committed_planes.lock().unwrap(), owned_planes);
+        if event {
+            self.frame_counter.flip_requested();
+        }
+        self.frame_counter.committed();
+        self.frame_counter.pacing.lock().unwrap().last_vblank = Some(vblank);
+        Ok(())
+    }
+
+    /// Returns whether committing `planes` would change anything compared to the
+    /// last successful [`commit`](DrmSurface::commit) or [`page_flip`](DrmSurface::page_flip).
+    ///
+    /// The planes are compared with the previously committed ones via [`PlaneState::diff`],
+    /// planes missing from earlier updates are considered changed. This only compares the configurations,
+    /// so a framebuffer, that was rendered to again, needs to be flipped anyway.
+    /// A powered down crtc (see [`set_active`](DrmSurface::set_active)) always needs a flip.
+    /// The pending connectors and mode are not taken into account,
+    /// see [`commit_pending`](DrmSurface::commit_pending).
+    ///
+    /// This allows to skip no-op page flips, e.g. while the displayed content is static.
+    pub fn flip_needed(&self, planes: &[PlaneState<'_>]) -> bool {
+        if !self.is_crtc_active() {
+            return true;
+        }
+        let committed = self.committed_planes.lock().unwrap();
+        !PlaneState::diff(&committed, planes).is_empty()
+    }
+
+    /// Enables frame pacing, limiting commits to one per vblank.
+    ///
+    /// While enabled [`commit`](DrmSurface::commit) and [`page_flip`](DrmSurface::page_flip) fail with
+    /// [`Error::TooEarly`], if the update would be presented less than `target` after the previously paced one.
+    /// The expected vblank is calculated from the last page flip processed by the
+    /// [`DrmDeviceNotifier`](super::DrmDeviceNotifier) and the frame duration of the pending mode.
+    /// A `target` shorter than the frame duration of the mode (e.g. [`Duration::ZERO`]) just
+    /// limits the updates to the refresh rate of the display.
+    ///
+    /// Commits are never rejected before the first page flip event has been received.
+    pub fn enable_frame_pacing(&self, target: Duration) {
+        self.frame_counter.pacing.lock().unwrap().target = Some(target);
+    }
+
+    /// Disables frame pacing, see [`DrmSurface::enable_frame_pacing`].
+    pub fn disable_frame_pacing(&self) {
+        let mut pacing = self.frame_counter.pacing.lock().unwrap();
+        pacing.target = None;
+        pacing.last_vblank = None;
+    }
+
+    /// Sets a timeout for page flip events requested by a commit or page flip, `None` disables it.
+    ///
+    /// Some drivers fail to deliver the event of a commit, that was accepted by the kernel.
+    /// If no page flip was processed by the [`DrmDeviceNotifier`](super::DrmDeviceNotifier) within `timeout`
+    /// after an update requesting an event, the next [`commit`](DrmSurface::commit) or
+    /// [`page_flip`](DrmSurface::page_flip) fails once with [`Error::FlipTimeout`], indicating
+    /// that the output should be re-initialized. As a frame loop waiting for the event would not issue
+    /// another update, [`flip_timed_out`](DrmSurface::flip_timed_out) can be polled, e.g. from a timer.
+    pub fn set_flip_timeout(&self, timeout: impl Into<Option<Duration>>) {
+        self.frame_counter.watchdog.lock().unwrap().timeout = timeout.into();
+    }
+
+    /// Returns whether the event of the last update did not arrive within the timeout,
+    /// see [`set_flip_timeout`](DrmSurface::set_flip_timeout).
+    pub fn flip_timed_out(&self) -> bool {
+        self.frame_counter
+            .watchdog
+            .lock()
+            .unwrap()
+            .timed_out(Instant::now())
+    }
+
+    fn paced_vblank(&self) -> Result<Instant, Error> {
+        let refresh = mode_refresh_mhz(&self.pending_mode());
+        let frame_duration = if refresh == 0 {
+            Duration::ZERO
+        } else {
+            Duration::from_nanos(1_000_000_000_000 / refresh as u64)
+        };
+
+        self.frame_counter
+            .pacing
+            .lock()
+            .unwrap()
+            .next_vblank(Instant::now(), frame_duration)
+            .ok_or(Error::TooEarly(self.crtc))
+    }
+
+    /// Enables or disables counting of committed and missed frames.
+    ///
+    /// Missed frames are computed by comparing the sequence numbers of consecutive page flip events
+    /// processed by the [`DrmDeviceNotifier`](super::DrmDeviceNotifier), so the counters are only updated
+    /// for commits requesting an event. Note that vblanks without any commit, e.g. while the output is idle,
+    /// are counted as missed as well.
+    ///
+    /// Enabling the counter again does not reset the current counts.
+    pub fn set_frame_counter_enabled(&self, enabled: bool) {
+        // the sequence is not continuous anymore if we stopped watching it
+        *self.frame_counter.last_sequence.lock().unwrap() = None;
+        self.frame_counter.enabled.store(enabled, Ordering::SeqCst);
+    }
+
+    /// Returns the number of frames committed, while the frame counter was enabled.
+    ///
+    /// See [`DrmSurface::set_frame_counter_enabled`].
+    pub fn frames_committed(&self) -> u64 {
+        self.frame_counter.committed.load(Ordering::SeqCst)
+    }
+
+    /// Returns the number of vblanks skipped between page flips, while the frame counter was enabled.
+    ///
+    /// See [`DrmSurface::set_frame_counter_enabled`].
+    pub fn frames_missed(&self) -> u64 {
+        self.frame_counter.missed.load(Ordering::SeqCst)
+    }
+
+    /// Sets a callback invoked with every framebuffer, that is no longer scanned out by any plane of this surface.
+    ///
+    /// A framebuffer is released once a page flip replacing it was observed by the
+    /// [`DrmDeviceNotifier`](super::DrmDeviceNotifier) or by the next commit, as the kernel does not accept
+    /// a new commit before the previous one completed. For commits without an event, the framebuffers
+    /// are thus only released on the next commit.
+    ///
+    /// The callback is invoked from the thread dispatching the [`DrmDeviceNotifier`](super::DrmDeviceNotifier)
+    /// or committing to this surface.
+    pub fn set_buffer_release_callback(
+        &self,
+        callback: impl Fn(framebuffer::Handle) + Send + Sync + 'static,
+    ) {
+        *self.buffer_release.callback.lock().unwrap() = Some(Box::new(callback));
+    }
+
+    /// Registers a framebuffer, that was created outside of smithay, with its format and size.
+    ///
+    /// The metadata is used by [`test_state`](DrmSurface::test_state) to catch framebuffers
+    /// with a format unsupported by the plane or a source rectangle exceeding the buffer early.
+    /// Registering the same framebuffer again replaces the previous metadata.
+    pub fn register_external_framebuffer(
+        &self,
+        fb: framebuffer::Handle,
+        format: Format,
+        size: Size<i32, Buffer>,
+    ) {
+        self.external_framebuffers
+            .lock()
+            .unwrap()
+            .insert(fb, ExternalFramebuffer { format, size });
+    }
+
+    /// Removes the metadata of a framebuffer previously registered by
+    /// [`register_external_framebuffer`](DrmSurface::register_external_framebuffer).
+    ///
+    /// This should be called before the framebuffer is destroyed, as its handle may be reused.
+    /// Returns `false` if the framebuffer was not registered.
+    pub fn unregister_external_framebuffer(&self, fb: framebuffer::Handle) -> bool {
+        self.external_framebuffers.lock().unwrap().remove(&fb).is_some()
+    }
+
+    fn validate_external_framebuffers(&self, planes: &[PlaneState<'_>]) -> Result<(), Error> {
+        let external_framebuffers = self.external_framebuffers.lock().unwrap();
+        if external_framebuffers.is_empty() {
+            return Ok(());
+        }
+
+        for state in planes {
+            let Some(config) = state.config.as_ref() else {
+                continue;
+            };
+            let Some(external) = external_framebuffers.get(&config.fb) else {
+                continue;
+            };
+
+            if !Rectangle::from_loc_and_size((0.0, 0.0), external.size.to_f64()).contains_rect(config.src) {
+                warn!(
+                    fb = ?config.fb,
+                    src = ?config.src,
+                    size = ?external.size,
+                    "Source rectangle exceeds external framebuffer"
+                );
+                return Err(Error::UnsupportedPlaneConfiguration(state.handle));
+            }
+
+            if !self.supported_formats(state.handle)?.contains(&external.format) {
+                warn!(
+                    fb = ?config.fb,
+                    format = ?external.format,
+                    plane = ?state.handle,
+                    "Format of external framebuffer is not supported by plane"
+                );
+                return Err(Error::UnsupportedPlaneConfiguration(state.handle));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns whether the given configuration passes all static checks for being scanned out on `plane`
+    ///
+    /// This checks without any kernel commit, that
+    /// - the plane can be used with this surface,
+    /// - the format of the framebuffer is supported by the plane and the source rectangle is within the framebuffer,
+    ///   if its metadata was registered via [`register_external_framebuffer`](DrmSurface::register_external_framebuffer),
+    /// - the plane supports scaling, if the source and destination size differ,
+    /// - the destination rectangle is visible on the pending mode,
+    /// - the plane supports the requested transform, alpha and blend mode.
+    ///
+    /// A positive result does not guarantee the configuration to work, as drivers may have additional
+    /// undiscoverable limitations. It is meant as a cheap filter before [`test_state`](DrmSurface::test_state).
+    pub fn can_scanout(&self, plane: plane::Handle, config: &PlaneConfig<'_>) -> Result<bool, Error> {
+        let planes = self.planes()?;
+        let type_ = if planes.primary.handle == plane {
+            PlaneType::Primary
+        } else if planes.cursor.map(|info| info.handle) == Some(plane) {
+            PlaneType::Cursor
+        } else if planes.overlay.iter().any(|info| info.handle == plane) {
+            PlaneType::Overlay
+        } else {
+            trace!(?plane, "Plane is not usable with this surface");
+            return Ok(false);
+        };
+
+        if let Some(external) = self
+            .external_framebuffers
+            .lock()
+            .unwrap()
+            .get(&config.fb)
+            .copied()
+        {
+            if !Rectangle::from_loc_and_size((0.0, 0.0), external.size.to_f64()).contains_rect(config.src)
+                || !self.supported_formats(plane)?.contains(&external.format)
+            {
+                trace!(?plane, fb = ?config.fb, "Framebuffer is not supported by plane");
+                return Ok(false);
+            }
+        }
+
+        let mode_size = self.pending_mode_size();
+        let scaled =
+            config.src.size.to_i32_round() != config.dst.size.to_logical(1).to_buffer(1, config.transform);
+        if let DrmSurfaceInternal::Legacy(_) = &*self.internal {
+            // legacy can only show the primary plane covering the whole crtc
+            return Ok(!scaled
+                && config.dst == Rectangle::from_loc_and_size((0, 0), mode_size)
+                && config.transform == Transform::Normal
+                && config.alpha == 1.0
+                && config.blend_mode.is_none()
+                && config.scaling_filter.is_none());
+        }
+
+        if scaled && type_ == PlaneType::Cursor {
+            trace!(?plane, "Cursor planes do not support scaling");
+            return Ok(false);
+        }
+
+        if config.dst.is_empty() || !Rectangle::from_loc_and_size((0, 0), mode_size).overlaps(config.dst) {
+            trace!(?plane, dst = ?config.dst, "Destination is not visible on the pending mode");
+            return Ok(false);
+        }
+
+        let mut required = Vec::new();
+        if config.transform != Transform::Normal {
+            required.push("rotation");
+        }
+        if config.alpha != 1.0 {
+            required.push("alpha");
+        }
+        if config.blend_mode.is_some() {
+            required.push("pixel blend mode");
+        }
+        if config.scaling_filter.is_some() {
+            required.push("SCALING_FILTER");
+        }
+        for name in required {
+            if self.property_value(plane, name)?.is_none() {
+                trace!(?plane, "Plane is missing the `{}` property", name);
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Checks if the format and modifier of the given framebuffer are supported by `plane`
+    ///
+    /// The format and modifier are read from the kernel (via `drmModeGetFB2`), so this works for any framebuffer.
+    /// Attaching an unsupported framebuffer would otherwise only fail on commit.
+    ///
+    /// Returns [`Error::UnsupportedFramebufferModifier`] if the combination is not supported by the plane.
+    pub fn validate_framebuffer(&self, plane: plane::Handle, fb: framebuffer::Handle) -> Result<(), Error> {
+        let Some(format) = self.framebuffer_format(fb)? else {
+            // the kernel knows formats, that we do not, so we cannot have it in our supported set
+            return Err(Error::UnsupportedPlaneConfiguration(plane));
+        };
+
+        if !self.supported_formats(plane)?.contains(&format) {
+            return Err(Error::UnsupportedFramebufferModifier {
+                plane,
+                fb,
+                format: format.code,
+                modifier: format.modifier,
+            });
+        }
+        Ok(())
+    }
+
+    // Reads the format and modifier of a framebuffer,
+    // returns `None` if the fourcc code is unknown.
+    fn framebuffer_format(&self, fb: framebuffer::Handle) -> Result<Option<Format>, Error> {
+        // `drmModeGetFB2` directly, to also close the handles of framebuffers with unknown formats
+        let info =
+            drm_ffi::mode::get_framebuffer2(self.as_fd().as_raw_fd(), fb.into()).map_err(|source| {
+                Error::Access {
+                    errmsg: "Failed to query framebuffer info",
+                    dev: self.dev_path(),
+                    source,
+                }
+            })?;
+        // the drm master gets new handles to the buffers, which we don't need
+        close_gem_handles(self, info.handles);
+
+        // framebuffers created without modifiers use the implicit modifier of the buffer
+        let modifier = if info.flags & drm_ffi::DRM_MODE_FB_MODIFIERS != 0 {
+            Modifier::from(info.modifier[0])
+        } else {
+            Modifier::Invalid
+        };
+        Ok(Fourcc::try_from(info.pixel_format)
+            .ok()
+            .map(|code| Format { code, modifier }))
     }
 
     /// Returns a set of supported pixel formats for attached buffers
     pub fn supported_formats(&self, plane: plane::Handle) -> Result<HashSet<Format>, Error> {
+        self.probe_formats(plane, None)
+    }
+
+    /// Returns a set of supported pixel formats for attached buffers,
+    /// giving up on querying further modifiers once `timeout` has passed.
+    ///
+    /// Querying the supported modifiers can be slow on some (e.g. virtualized) drivers.
+    /// If the timeout is exceeded, the formats gathered so far are returned and a warning is logged.
+    /// A partial set always includes `Argb8888` with an implicit modifier as a fallback.
+    pub fn supported_formats_timeout(
+        &self,
+        plane: plane::Handle,
+        timeout: Duration,
+    ) -> Result<HashSet<Format>, Error> {
+        self.probe_formats(plane, Some(Instant::now() + timeout))
+    }
+
+    /// Returns the distinct pixel formats supported by the given plane, ignoring modifiers.
+    ///
+    /// This only reads the format list of the plane, which contains the same codes as `IN_FORMATS`,
+    /// and is thus much cheaper than [`supported_formats`](DrmSurface::supported_formats).
+    /// Use it to quickly check, if a plane supports a format at all.
+    pub fn plane_fourccs(&self, plane: plane::Handle) -> Result<HashSet<Fourcc>, Error> {
+        let plane_info = self.get_plane(plane).map_err(|source| Error::Access {
+            errmsg: "Error loading plane info",
+            dev: self.dev_path(),
+            source,
+        })?;
+        Ok(plane_info
+            .formats()
+            .iter()
+            .flat_map(|x| Fourcc::try_from(*x).ok())
+            .collect())
+    }
+
+    /// Returns the formats supported by the given plane together with the id of the device as a [`FeedbackTranche`]
+    ///
+    /// This allows clients to allocate buffers suitable for direct scan-out on the plane.
+    pub fn dmabuf_feedback_tranche(&self, plane: plane::Handle) -> Result<FeedbackTranche, Error> {
+        Ok(FeedbackTranche {
+            target_device: self.dev_id,
+            formats: self.supported_formats(plane)?,
+        })
+    }
+
+    /// Returns how buffers for scan-out on the given plane should be allocated.
+    ///
+    /// This mirrors the formats returned by [`supported_formats`](DrmSurface::supported_formats):
+    /// If the driver does not support framebuffers with modifiers, cursor planes are
+    /// assumed to require a [linear](ModifierPolicy::ForceLinear) layout, while other planes
+    /// use the [implicit](ModifierPolicy::ImplicitOnly) layout chosen by the driver.
+    pub fn scanout_modifier_policy(&self, plane: plane::Handle) -> Result<ModifierPolicy, Error> {
+        if let Ok(1) = self.driver_capability(DriverCapability::AddFB2Modifiers) {
+            Ok(ModifierPolicy::ExplicitSupported)
+        } else if plane_type(self, plane)? == PlaneType::Cursor {
+            Ok(ModifierPolicy::ForceLinear)
+        } else {
+            Ok(ModifierPolicy::ImplicitOnly)
+        }
+    }
+
+    /// Returns whether the given plane can scan out buffers with explicit (tiled or compressed) modifiers.
+    ///
+    /// This is the case, if the driver supports framebuffers with modifiers and the plane advertises
+    /// at least one modifier besides [`Modifier::Invalid`] and [`Modifier::Linear`].
+    /// If this returns `false`, buffers for the plane should be allocated without explicit modifiers.
+    pub fn plane_supports_explicit_modifiers(&self, plane: plane::Handle) -> Result<bool, Error> {
+        if !matches!(self.driver_capability(DriverCapability::AddFB2Modifiers), Ok(1)) {
+            return Ok(false);
+        }
+
+        Ok(self
+            .supported_formats(plane)?
+            .iter()
+            .any(|format| !matches!(format.modifier, Modifier::Invalid | Modifier::Linear)))
+    }
+
+    /// Picks a format and modifier for allocating scan-out buffers for the given plane.
+    ///
+    /// `allocator_modifiers` are the modifiers the allocator can create buffers of with the given `fourcc`,
+    /// in order of preference. Of those supported by the plane, explicit (tiled or compressed) modifiers
+    /// are preferred over [`Modifier::Linear`], which is preferred over [`Modifier::Invalid`].
+    ///
+    /// Returns `None` if the plane supports none of the modifiers with the given format.
+    pub fn choose_scanout_format(
+        &self,
+        plane: plane::Handle,
+        fourcc: Fourcc,
+        allocator_modifiers: &[Modifier],
+    ) -> Result<Option<(Fourcc, Modifier)>, Error> {
+        let formats = self.supported_formats(plane)?;
+        Ok(scanout_modifier(&formats, fourcc, allocator_modifiers).map(|modifier| (fourcc, modifier)))
+    }
+
+    /// Returns whether buffers with the given format and modifier can be scanned out on the given plane.
+    ///
+    /// Buffers may be allocated with modifiers, that are only supported for rendering.
+    /// This checks the combination against the formats advertised by the plane (via the `IN_FORMATS` property),
+    /// which avoids attaching such buffers to the plane and failing the commit.
+    ///
+    /// *Note*: [`Modifier::Invalid`] (an implicit modifier) is considered scanout-capable for every format of the plane.
+    pub fn modifier_scanout_capable(
+        &self,
+        plane: plane::Handle,
+        fourcc: Fourcc,
+        modifier: Modifier,
+    ) -> Result<bool, Error> {
+        Ok(self.supported_formats(plane)?.contains(&Format {
+            code: fourcc,
+            modifier,
+        }))
+    }
+
+    /// Returns the subset of [`supported_formats`](DrmSurface::supported_formats), that can actually
+    /// be scanned out on the given plane at the pending mode.
+    ///
+    /// Bandwidth limitations may prevent some formats from being used at high resolutions or refresh rates.
+    /// On atomic devices this runs a test commit with a linear dumb buffer for every format code,
+    /// so this is fairly expensive and should be cached.
+    /// Format codes that cannot be allocated as dumb buffers (e.g. multi-planar formats) cannot be tested
+    /// and are always included. Legacy devices cannot test configurations, so nothing is filtered.
+    pub fn supported_formats_for_scanout(&self, plane: plane::Handle) -> Result<HashSet<Format>, Error> {
+        let mut formats = self.supported_formats(plane)?;
+        let DrmSurfaceInternal::Atomic(surf) = &*self.internal else {
+            return Ok(formats);
+        };
+
+        let codes = formats.iter().map(|format| format.code).collect::<HashSet<_>>();
+        for code in codes {
+            if surf.test_scanout_format(plane, code)? == Some(false) {
+                trace!(
+                    ?plane,
+                    "Format {:?} cannot be scanned out at the pending mode",
+                    code
+                );
+                formats.retain(|format| format.code != code);
+            }
+        }
+
+        Ok(formats)
+    }
+
+    /// Returns a rough estimate of the memory bandwidth in bytes per second needed to scan out
+    /// the given planes at the current mode.
+    ///
+    /// The estimate sums up bytes per pixel × destination area × refresh rate for every configured plane.
+    /// It ignores scaling, compression and the actual limits of the display controller,
+    /// so it is only meant as a first-pass filter before doing a test commit.
+    /// Framebuffers with unknown formats are assumed to use 32 bits per pixel.
+    pub fn estimate_bandwidth(&self, planes: &[PlaneState<'_>]) -> u64 {
+        let refresh = mode_refresh_mhz(&self.current_mode()) as u64;
+        planes
+            .iter()
+            .filter_map(|state| state.config.as_ref())
+            .map(|config| {
+                let external = self
+                    .external_framebuffers
+                    .lock()
+                    .unwrap()
+                    .get(&config.fb)
+                    .copied();
+                let format = match external {
+                    Some(external) => Some(external.format),
+                    None => self.framebuffer_format(config.fb).ok().flatten(),
+                };
+                let bpp = format.and_then(|format| get_bpp(format.code)).unwrap_or(32) as u64;
+                let area = config.dst.size.w.max(0) as u64 * config.dst.size.h.max(0) as u64;
+                bpp * area * refresh / 8000
+            })
+            .sum()
+    }
+
+    fn probe_formats(
+        &self,
+        plane: plane::Handle,
+        deadline: Option<Instant>,
+    ) -> Result<HashSet<Format>, Error> {
         // get plane formats
         let plane_info = self.get_plane(plane).map_err(|source| Error::Access {
             errmsg: "Error loading plane info",
@@ -399,82 +2555,14 @@ impl DrmSurface {
             });
         }
 
-        if let Ok(1) = self.get_driver_capability(DriverCapability::AddFB2Modifiers) {
-            let set = self.get_properties(plane).map_err(|source| Error::Access {
-                errmsg: "Failed to query properties",
-                dev: self.dev_path(),
-                source,
-            })?;
-            let (handles, _) = set.as_props_and_values();
-            // for every handle ...
-            let prop = handles
-                .iter()
-                .find(|handle| {
-                    // get information of that property
-                    if let Ok(info) = self.get_property(**handle) {
-                        // to find out, if we got the handle of the "IN_FORMATS" property ...
-                        if info.name().to_str().map(|x| x == "IN_FORMATS").unwrap_or(false) {
-                            // so we can use that to get formats
-                            return true;
-                        }
-                    }
-                    false
-                })
-                .copied();
-            if let Some(prop) = prop {
-                let prop_info = self.get_property(prop).map_err(|source| Error::Access {
-                    errmsg: "Failed to query property",
-                    dev: self.dev_path(),
-                    source,
-                })?;
-                let (handles, raw_values) = set.as_props_and_values();
-                let raw_value = raw_values[handles
-                    .iter()
-                    .enumerate()
-                    .find_map(|(i, handle)| if *handle == prop { Some(i) } else { None })
-                    .unwrap()];
-                if let property::Value::Blob(blob) = prop_info.value_type().convert_value(raw_value) {
-                    let data = self.get_property_blob(blob).map_err(|source| Error::Access {
-                        errmsg: "Failed to query property blob data",
-                        dev: self.dev_path(),
-                        source,
-                    })?;
-                    // be careful here, we have no idea about the alignment inside the blob, so always copy using `read_unaligned`,
-                    // although slice::from_raw_parts would be so much nicer to iterate and to read.
-                    unsafe {
-                        let fmt_mod_blob_ptr = data.as_ptr() as *const drm_ffi::drm_format_modifier_blob;
-                        let fmt_mod_blob = &*fmt_mod_blob_ptr;
-
-                        let formats_ptr: *const u32 = fmt_mod_blob_ptr
-                            .cast::<u8>()
-                            .offset(fmt_mod_blob.formats_offset as isize)
-                            as *const _;
-                        let modifiers_ptr: *const drm_ffi::drm_format_modifier = fmt_mod_blob_ptr
-                            .cast::<u8>()
-                            .offset(fmt_mod_blob.modifiers_offset as isize)
-                            as *const _;
-                        let formats_ptr = formats_ptr as *const u32;
-                        let modifiers_ptr = modifiers_ptr as *const drm_ffi::drm_format_modifier;
-
-                        for i in 0..fmt_mod_blob.count_modifiers {
-                            let mod_info = modifiers_ptr.offset(i as isize).read_unaligned();
-                            for j in 0..64 {
-                                if mod_info.formats & (1u64 << j) != 0 {
-                                    let code = Fourcc::try_from(
-                                        formats_ptr
-                                            .offset((j + mod_info.offset) as isize)
-                                            .read_unaligned(),
-                                    )
-                                    .ok();
-                                    let modifier = Modifier::from(mod_info.modifier);
-                                    if let Some(code) = code {
-                                        formats.insert(Format { code, modifier });
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
+        let mut complete = true;
+        if let Ok(1) = self.driver_capability(DriverCapability::AddFB2Modifiers) {
+            complete = self.probe_modifiers(plane, &mut formats, deadline)?;
+            if !complete {
+                warn!(
+                    ?plane,
+                    "Timed out querying the supported modifiers of plane, formats might be incomplete"
+                );
             }
         } else if plane_type(self, plane)? == PlaneType::Cursor {
             // Force a LINEAR layout for the cursor if the driver doesn't support modifiers
@@ -486,42 +2574,515 @@ impl DrmSurface {
             }
         }
 
-        if formats.is_empty() {
-            formats.insert(Format {
-                code: Fourcc::Argb8888,
-                modifier: Modifier::Invalid,
-            });
+        if formats.is_empty() || !complete {
+            formats.insert(Format {
+                code: Fourcc::Argb8888,
+                modifier: Modifier::Invalid,
+            });
+        }
+
+        trace!(
+            "Supported scan-out formats for plane ({:?}): {:?}",
+            plane,
+            formats
+        );
+
+        Ok(formats)
+    }
+
+    // Looks up a property of a drm object by its name and returns its info and current value
+    fn property_value<T: ResourceHandle>(
+        &self,
+        handle: T,
+        name: &str,
+    ) -> Result<Option<(property::Info, property::RawValue)>, Error> {
+        let props = self.get_properties(handle).map_err(|source| Error::Access {
+            errmsg: "Failed to query properties",
+            dev: self.dev_path(),
+            source,
+        })?;
+        let (handles, values) = props.as_props_and_values();
+        for (&prop, &value) in handles.iter().zip(values.iter()) {
+            let info = self.get_property(prop).map_err(|source| Error::Access {
+                errmsg: "Failed to query property",
+                dev: self.dev_path(),
+                source,
+            })?;
+            if info.name().to_str().map(|x| x == name).unwrap_or(false) {
+                return Ok(Some((info, value)));
+            }
+        }
+        Ok(None)
+    }
+
+    // Adds the formats of the `IN_FORMATS` property of the plane.
+    // Returns `false` if the deadline passed before all formats were added.
+    fn probe_modifiers(
+        &self,
+        plane: plane::Handle,
+        formats: &mut HashSet<Format>,
+        deadline: Option<Instant>,
+    ) -> Result<bool, Error> {
+        let expired = || {
+            deadline
+                .map(|deadline| Instant::now() >= deadline)
+                .unwrap_or(false)
+        };
+
+        let set = self.get_properties(plane).map_err(|source| Error::Access {
+            errmsg: "Failed to query properties",
+            dev: self.dev_path(),
+            source,
+        })?;
+        let (handles, _) = set.as_props_and_values();
+        // for every handle ...
+        let mut prop = None;
+        for handle in handles {
+            if expired() {
+                return Ok(false);
+            }
+            // get information of that property
+            if let Ok(info) = self.get_property(*handle) {
+                // to find out, if we got the handle of the "IN_FORMATS" property ...
+                if info.name().to_str().map(|x| x == "IN_FORMATS").unwrap_or(false) {
+                    // so we can use that to get formats
+                    prop = Some(*handle);
+                    break;
+                }
+            }
+        }
+        let Some(prop) = prop else {
+            return Ok(true);
+        };
+
+        let prop_info = self.get_property(prop).map_err(|source| Error::Access {
+            errmsg: "Failed to query property",
+            dev: self.dev_path(),
+            source,
+        })?;
+        let (handles, raw_values) = set.as_props_and_values();
+        let raw_value = raw_values[handles
+            .iter()
+            .enumerate()
+            .find_map(|(i, handle)| if *handle == prop { Some(i) } else { None })
+            .unwrap()];
+        if let property::Value::Blob(blob) = prop_info.value_type().convert_value(raw_value) {
+            if expired() {
+                return Ok(false);
+            }
+            let data = self.get_property_blob(blob).map_err(|source| Error::Access {
+                errmsg: "Failed to query property blob data",
+                dev: self.dev_path(),
+                source,
+            })?;
+            // be careful here, we have no idea about the alignment inside the blob, so always copy using `read_unaligned`,
+            // although slice::from_raw_parts would be so much nicer to iterate and to read.
+            unsafe {
+                let fmt_mod_blob_ptr = data.as_ptr() as *const drm_ffi::drm_format_modifier_blob;
+                let fmt_mod_blob = &*fmt_mod_blob_ptr;
+
+                let formats_ptr: *const u32 = fmt_mod_blob_ptr
+                    .cast::<u8>()
+                    .offset(fmt_mod_blob.formats_offset as isize)
+                    as *const _;
+                let modifiers_ptr: *const drm_ffi::drm_format_modifier = fmt_mod_blob_ptr
+                    .cast::<u8>()
+                    .offset(fmt_mod_blob.modifiers_offset as isize)
+                    as *const _;
+                let formats_ptr = formats_ptr as *const u32;
+                let modifiers_ptr = modifiers_ptr as *const drm_ffi::drm_format_modifier;
+
+                for i in 0..fmt_mod_blob.count_modifiers {
+                    let mod_info = modifiers_ptr.offset(i as isize).read_unaligned();
+                    for j in 0..64 {
+                        if mod_info.formats & (1u64 << j) != 0 {
+                            let code = Fourcc::try_from(
+                                formats_ptr
+                                    .offset((j + mod_info.offset) as isize)
+                                    .read_unaligned(),
+                            )
+                            .ok();
+                            let modifier = Modifier::from(mod_info.modifier);
+                            if let Some(code) = code {
+                                formats.insert(Format { code, modifier });
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Returns the modifiers supported by both of the given planes for a fourcc code
+    ///
+    /// This is useful to allocate buffers, that can be moved between e.g. the primary and an overlay plane
+    /// without reallocation. The modifiers are returned in ascending order of their values.
+    pub fn shared_modifiers(
+        &self,
+        plane_a: plane::Handle,
+        plane_b: plane::Handle,
+        code: Fourcc,
+    ) -> Result<Vec<Modifier>, Error> {
+        let formats_b = self.supported_formats(plane_b)?;
+        let mut modifiers = self
+            .supported_formats(plane_a)?
+            .into_iter()
+            .filter(|format| format.code == code && formats_b.contains(format))
+            .map(|format| format.modifier)
+            .collect::<Vec<_>>();
+        modifiers.sort_by_key(|modifier| u64::from(*modifier));
+        Ok(modifiers)
+    }
+
+    /// Returns a set of available planes for this surface
+    pub fn planes(&self) -> Result<Planes, Error> {
+        let has_universal_planes = match &*self.internal {
+            DrmSurfaceInternal::Atomic(_) => self.has_universal_planes,
+            // Disable the planes on legacy, we do not support them on legacy anyway
+            DrmSurfaceInternal::Legacy(_) => false,
+        };
+
+        planes(self, &self.crtc, has_universal_planes)
+    }
+
+    /// Returns the maximum number of planes of this surface, that can be enabled at the same time.
+    ///
+    /// `None` means no limit could be discovered, so all planes returned by [`planes`](DrmSurface::planes)
+    /// may be used at once. Legacy devices only support the primary plane and always return `Some(1)`.
+    ///
+    /// There is no way to query this limit from the driver, so on atomic devices this is determined
+    /// by test commits enabling the primary plane and an increasing number of overlay planes,
+    /// all showing a buffer of the size of the pending mode. This is fairly expensive and the result is
+    /// conservative, as smaller or differently configured planes might still fit.
+    /// The cursor plane is not taken into account.
+    pub fn max_active_planes(&self) -> Result<Option<u32>, Error> {
+        let DrmSurfaceInternal::Atomic(surf) = &*self.internal else {
+            return Ok(Some(1));
+        };
+
+        let overlays = self
+            .planes()?
+            .overlay
+            .iter()
+            .map(|info| info.handle)
+            .collect::<Vec<_>>();
+        if surf.test_active_planes(&overlays)? {
+            return Ok(None);
+        }
+
+        // binary search for the number of overlays still passing the test
+        let (mut passing, mut failing) = (0, overlays.len());
+        while failing - passing > 1 {
+            let count = (passing + failing) / 2;
+            if surf.test_active_planes(&overlays[..count])? {
+                passing = count;
+            } else {
+                failing = count;
+            }
+        }
+
+        // the primary plane is always enabled
+        Ok(Some(passing as u32 + 1))
+    }
+
+    /// Claim a plane so that it won't be used by a different crtc
+    ///  
+    /// Returns `None` if the plane could not be claimed
+    pub fn claim_plane(&self, plane: plane::Handle) -> Option<PlaneClaim> {
+        self.plane_claim_storage.claim(plane, self.crtc)
+    }
+
+    /// Claim multiple planes at once so that they won't be used by a different crtc
+    ///
+    /// Either all planes are claimed or none of them, in which case `None` is returned.
+    pub fn claim_planes(&self, planes: &[plane::Handle]) -> Option<Vec<PlaneClaim>> {
+        self.plane_claim_storage.claim_all(planes, self.crtc)
+    }
+
+    /// Returns the planes currently claimed for the crtc of this surface,
+    /// see [`claim_plane`](DrmSurface::claim_plane).
+    ///
+    /// A plane stays claimed as long as any [`PlaneClaim`] for it is alive.
+    pub fn claimed_planes(&self) -> Vec<plane::Handle> {
+        self.plane_claim_storage.claimed(self.crtc)
+    }
+
+    /// Sets the framebuffer of the hardware cursor, or hides the cursor if `None` is given.
+    ///
+    /// The `hotspot` is the point of the cursor image placed at the position given to
+    /// [`move_cursor`](DrmSurface::move_cursor).
+    ///
+    /// On legacy devices this uses the cursor ioctls. On atomic devices this submits a non-blocking
+    /// update of just the cursor plane, which fails while another page flip is still pending.
+    /// In that case, prefer to include the cursor plane in the next frame instead.
+    /// Cursor updates are not subject to [frame pacing](DrmSurface::enable_frame_pacing)
+    /// and are not counted as frames.
+    pub fn set_cursor(
+        &self,
+        fb: Option<framebuffer::Handle>,
+        hotspot: Point<i32, Buffer>,
+    ) -> Result<(), Error> {
+        self.ensure_not_test_only()?;
+        let mut cursor = *self.cursor.lock().unwrap();
+        cursor.fb = fb;
+        cursor.hotspot = hotspot;
+        if let Some(fb) = fb {
+            let info = self.get_framebuffer(fb).map_err(|source| Error::Access {
+                errmsg: "Failed to get framebuffer info",
+                dev: self.dev_path(),
+                source,
+            })?;
+            // the drm master gets a new handle to the buffer, which we don't need
+            close_gem_handles(self, info.buffer().map(u32::from));
+            let (w, h) = info.size();
+            cursor.size = (w as i32, h as i32).into();
+        }
+
+        match &*self.internal {
+            DrmSurfaceInternal::Atomic(surf) => self.commit_cursor(surf, &mut cursor),
+            DrmSurfaceInternal::Legacy(surf) => surf
+                .set_cursor(fb, hotspot.into())
+                .and_then(|_| surf.move_cursor(cursor.origin().into()))
+                .map_err(|err| self.map_error(err)),
+        }?;
+
+        *self.cursor.lock().unwrap() = cursor;
+        Ok(())
+    }
+
+    /// Moves the hotspot of the hardware cursor to the given position on the crtc.
+    ///
+    /// See [`set_cursor`](DrmSurface::set_cursor) for the restrictions on atomic devices.
+    pub fn move_cursor(&self, position: Point<i32, Physical>) -> Result<(), Error> {
+        self.ensure_not_test_only()?;
+        let mut cursor = *self.cursor.lock().unwrap();
+        cursor.position = position;
+
+        match &*self.internal {
+            DrmSurfaceInternal::Atomic(surf) => self.commit_cursor(surf, &mut cursor),
+            DrmSurfaceInternal::Legacy(surf) => surf
+                .move_cursor(cursor.origin().into())
+                .map_err(|err| self.map_error(err)),
+        }?;
+
+        *self.cursor.lock().unwrap() = cursor;
+        Ok(())
+    }
+
+    // updates the cursor plane of atomic devices, bypassing the frame bookkeeping of `page_flip`
+    fn commit_cursor(&self, surf: &AtomicDrmSurface, cursor: &mut CursorState) -> Result<(), Error> {
+        let plane = match cursor.plane {
+            Some(plane) => plane,
+            None => *cursor
+                .plane
+                .insert(self.planes()?.cursor.ok_or(Error::NoPlane)?.handle),
+        };
+        let config = cursor.fb.map(|fb| PlaneConfig {
+            src: Rectangle::from_loc_and_size(Point::default(), cursor.size).to_f64(),
+            dst: Rectangle::from_loc_and_size(cursor.origin(), (cursor.size.w, cursor.size.h)),
+            transform: Transform::Normal,
+            alpha: 1.0,
+            damage_clips: None,
+            fb,
+            blend_mode: None,
+            scaling_filter: None,
+            skip_implicit_sync: false,
+        });
+        surf.page_flip(
+            [PlaneState {
+                handle: plane,
+                config,
+            }],
+            false,
+            false,
+        )
+        .map_err(|err| self.map_error(err))
+    }
+
+    /// Returns the crtcs the given plane can be attached to.
+    ///
+    /// This resolves the `possible_crtcs` bitmask of the plane, which is indexed by the position
+    /// of the crtcs in the resource list of the device.
+    pub fn plane_possible_crtcs(&self, plane: plane::Handle) -> Result<Vec<crtc::Handle>, Error> {
+        let info = self.get_plane(plane).map_err(|source| Error::Access {
+            errmsg: "Error loading plane info",
+            dev: self.dev_path(),
+            source,
+        })?;
+        let resources = self.resource_handles().map_err(|source| Error::Access {
+            errmsg: "Error loading resources",
+            dev: self.dev_path(),
+            source,
+        })?;
+        Ok(resources.filter_crtcs(info.possible_crtcs()))
+    }
+
+    /// Returns the crtc the given plane is currently bound to according to the kernel,
+    /// or `None` if the plane is unused.
+    ///
+    /// Unlike [`claim_plane`](DrmSurface::claim_plane) this also reflects bindings made
+    /// outside of smithay, e.g. by a previous DRM master.
+    pub fn plane_current_crtc(&self, plane: plane::Handle) -> Result<Option<crtc::Handle>, Error> {
+        let info = self.get_plane(plane).map_err(|source| Error::Access {
+            errmsg: "Error loading plane info",
+            dev: self.dev_path(),
+            source,
+        })?;
+        Ok(info.crtc())
+    }
+
+    /// Returns the format of the framebuffer currently bound to the given plane according to the kernel.
+    ///
+    /// The format and modifier are read via `drmModeGetFB2`, so this also reflects framebuffers attached
+    /// outside of smithay, e.g. by a previous DRM master. The buffer handles `drmModeGetFB2` creates
+    /// for the drm master are closed right away, so this can be called repeatedly.
+    /// Returns `None` if no framebuffer is bound or its format is unknown to smithay.
+    pub fn current_format(&self, plane: plane::Handle) -> Result<Option<Format>, Error> {
+        let info = self.get_plane(plane).map_err(|source| Error::Access {
+            errmsg: "Error loading plane info",
+            dev: self.dev_path(),
+            source,
+        })?;
+        match info.framebuffer() {
+            Some(fb) => self.framebuffer_format(fb),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the [`ScalingFilter`]s supported by the `SCALING_FILTER` property of the given plane.
+    ///
+    /// Planes without a `SCALING_FILTER` property, as well as all planes of legacy devices,
+    /// only support [`ScalingFilter::Default`].
+    pub fn plane_scaling_filters(&self, plane: plane::Handle) -> Result<Vec<ScalingFilter>, Error> {
+        if let DrmSurfaceInternal::Legacy(_) = &*self.internal {
+            return Ok(vec![ScalingFilter::Default]);
         }
 
-        trace!(
-            "Supported scan-out formats for plane ({:?}): {:?}",
-            plane,
-            formats
-        );
-
-        Ok(formats)
+        let Some((info, _)) = self.property_value(plane, "SCALING_FILTER")? else {
+            return Ok(vec![ScalingFilter::Default]);
+        };
+        let property::ValueType::Enum(values) = info.value_type() else {
+            return Ok(vec![ScalingFilter::Default]);
+        };
+        let (_, values) = values.values();
+        Ok([ScalingFilter::Default, ScalingFilter::Nearest]
+            .into_iter()
+            .filter(|filter| {
+                values.iter().any(|value| {
+                    value
+                        .name()
+                        .to_str()
+                        .map(|x| x == filter.drm_name())
+                        .unwrap_or(false)
+                })
+            })
+            .collect())
     }
 
-    /// Returns a set of available planes for this surface
-    pub fn planes(&self) -> Result<Planes, Error> {
-        let has_universal_planes = match &*self.internal {
-            DrmSurfaceInternal::Atomic(_) => self.has_universal_planes,
-            // Disable the planes on legacy, we do not support them on legacy anyway
-            DrmSurfaceInternal::Legacy(_) => false,
+    /// Returns the [`Transform`] currently programmed via the `rotation` property of the given plane.
+    ///
+    /// Planes without a `rotation` property, as well as all planes of legacy devices,
+    /// always return [`Transform::Normal`].
+    pub fn plane_current_rotation(&self, plane: plane::Handle) -> Result<Transform, Error> {
+        if let DrmSurfaceInternal::Legacy(_) = &*self.internal {
+            return Ok(Transform::Normal);
+        }
+
+        let Some((_, value)) = self.property_value(plane, "rotation")? else {
+            return Ok(Transform::Normal);
         };
+        atomic::DrmRotation::from_bits_truncate(value as u8)
+            .to_transform()
+            .ok_or(Error::UnsupportedPlaneConfiguration(plane))
+    }
 
-        planes(self, &self.crtc, has_universal_planes)
+    /// Returns the [`Transform`]s supported by the `rotation` property of the given plane.
+    ///
+    /// This allows to skip proposing unsupported transforms for a plane without a test commit,
+    /// though a supported transform may still be rejected in combination with other properties.
+    /// Planes without a `rotation` property, as well as all planes of legacy devices,
+    /// only support [`Transform::Normal`].
+    pub fn plane_supported_transforms(&self, plane: plane::Handle) -> Result<Vec<Transform>, Error> {
+        if let DrmSurfaceInternal::Legacy(_) = &*self.internal {
+            return Ok(vec![Transform::Normal]);
+        }
+
+        let Some((info, _)) = self.property_value(plane, "rotation")? else {
+            return Ok(vec![Transform::Normal]);
+        };
+        // the drm crate does not expose the bits of bitmask properties
+        let mut enums = Vec::new();
+        drm_ffi::mode::get_property(
+            self.as_fd().as_raw_fd(),
+            info.handle().into(),
+            None,
+            Some(&mut enums),
+        )
+        .map_err(|source| Error::Access {
+            errmsg: "Failed to query property",
+            dev: self.dev_path(),
+            source,
+        })?;
+        let supported = enums
+            .iter()
+            .filter(|entry| entry.value < 8)
+            .fold(atomic::DrmRotation::empty(), |bits, entry| {
+                bits | atomic::DrmRotation::from_bits_truncate(1 << entry.value)
+            });
+        Ok(supported.transforms())
     }
 
-    /// Claim a plane so that it won't be used by a different crtc
-    ///  
-    /// Returns `None` if the plane could not be claimed
-    pub fn claim_plane(&self, plane: plane::Handle) -> Option<PlaneClaim> {
-        self.plane_claim_storage.claim(plane, self.crtc)
+    /// Returns whether the state of the connectors or the mode of the crtc were changed
+    /// outside of smithay, compared to the last committed state.
+    ///
+    /// This happens e.g. when a KVM switch disconnects the display and connects it again.
+    /// Use [`reset_state`](DrmSurface::reset_state) to re-evaluate the state and commit a new configuration,
+    /// if this returns `true`.
+    pub fn connector_signal_changed(&self) -> Result<bool, Error> {
+        let crtc_info = self.get_crtc(self.crtc).map_err(|source| Error::Access {
+            errmsg: "Error loading crtc info",
+            dev: self.dev_path(),
+            source,
+        })?;
+        if crtc_info.mode() != Some(self.current_mode()) {
+            return Ok(true);
+        }
+
+        for conn in self.current_connectors() {
+            let info = self.get_connector(conn, false).map_err(|source| Error::Access {
+                errmsg: "Error loading connector info",
+                dev: self.dev_path(),
+                source,
+            })?;
+            if info.state() != connector::State::Connected {
+                return Ok(true);
+            }
+
+            let crtc = match info.current_encoder() {
+                Some(encoder) => self
+                    .get_encoder(encoder)
+                    .map_err(|source| Error::Access {
+                        errmsg: "Error loading encoder info",
+                        dev: self.dev_path(),
+                        source,
+                    })?
+                    .crtc(),
+                None => None,
+            };
+            if crtc != Some(self.crtc) {
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
     }
 
     /// Re-evaluates the current state of the crtc.
     ///
+    /// Fails with [`Error::NotMaster`], if the device lost its drm master status.
+    ///
     /// It is recommended to call this function after this used [`Session`]
     /// gets re-activated / VT switched to.
     ///
@@ -529,14 +3090,168 @@ impl DrmSurface {
     /// the state of the crtc is modified elsewhere, you may call this function
     /// to reset it's internal state.
     pub fn reset_state(&self) -> Result<(), Error> {
+        let fd = self.device_fd();
+        if fd.is_privileged() && !fd.is_master() {
+            return Err(Error::NotMaster(self.dev_path()));
+        }
+
+        // the planes may have been changed elsewhere
+        self.committed_planes.lock().unwrap().clear();
         match &*self.internal {
             DrmSurfaceInternal::Atomic(surf) => surf.reset_state::<Self>(None),
             DrmSurfaceInternal::Legacy(surf) => surf.reset_state::<Self>(None),
         }
     }
 
-    #[cfg(feature = "backend_gbm")]
-    pub(super) fn span(&self) -> &tracing::Span {
+    /// Puts the surface into test-only mode, or back into normal operation.
+    ///
+    /// While in test-only mode, the surface rejects any operation that would change the state of
+    /// the display, like [`commit`](DrmSurface::commit), [`page_flip`](DrmSurface::page_flip),
+    /// [`clear_plane`](DrmSurface::clear_plane) or [`set_dpms`](DrmSurface::set_dpms),
+    /// with [`Error::TestOnly`]. Modifying the pending state (e.g. via [`use_mode`](DrmSurface::use_mode)
+    /// or [`add_connector`](DrmSurface::add_connector)) and [`test_state`](DrmSurface::test_state) work as usual.
+    ///
+    /// This allows to safely check whether a configuration would work, without ever touching the display.
+    ///
+    /// *Note*: As legacy devices can only test a buffer by performing a modeset,
+    /// [`test_state`](DrmSurface::test_state) always returns `Ok` for them in test-only mode.
+    pub fn set_test_only(&self, test_only: bool) {
+        self.test_only.store(test_only, Ordering::SeqCst);
+    }
+
+    /// Returns whether the surface is in test-only mode, see [`set_test_only`](DrmSurface::set_test_only).
+    pub fn is_test_only(&self) -> bool {
+        self.test_only.load(Ordering::SeqCst)
+    }
+
+    /// Returns the failures injected into this surface, see the [`test`](super::test) module.
+    #[cfg(feature = "backend_drm_failure_injection")]
+    pub fn injected_failures(&self) -> &super::test::InjectedFailures {
+        &self.injected_failures
+    }
+
+    /// Sets whether the crtc should be restored to its previous state, when the surface is dropped.
+    ///
+    /// By default the crtc is disabled on drop and only restored together with the rest of the device
+    /// once the [`DrmDevice`](super::DrmDevice) is dropped, leaving the display blank in between.
+    /// If enabled, the configuration captured on creation of the device is applied instead, e.g. showing
+    /// the framebuffer console again. Crtcs, that were not in use on creation, are still disabled.
+    pub fn restore_on_drop(&self, restore: bool) {
+        let flag = match &*self.internal {
+            DrmSurfaceInternal::Atomic(surf) => &surf.restore_on_drop,
+            DrmSurfaceInternal::Legacy(surf) => &surf.restore_on_drop,
+        };
+        flag.store(restore, Ordering::SeqCst);
+    }
+
+    /// Allows or forbids tearing updates via [`page_flip_async`](DrmSurface::page_flip_async).
+    ///
+    /// Tearing is not allowed by default.
+    pub fn set_allow_tearing(&self, allow: bool) {
+        self.allow_tearing.store(allow, Ordering::SeqCst);
+    }
+
+    /// Returns whether tearing updates are allowed, see [`set_allow_tearing`](DrmSurface::set_allow_tearing).
+    pub fn allows_tearing(&self) -> bool {
+        self.allow_tearing.load(Ordering::SeqCst)
+    }
+
+    fn ensure_not_test_only(&self) -> Result<(), Error> {
+        if self.is_test_only() {
+            return Err(Error::TestOnly(self.crtc));
+        }
+        Ok(())
+    }
+
+    // Maps errors caused by the state of the device to more specific errors
+    fn map_error(&self, err: Error) -> Error {
+        let err = err.map_removed();
+        // other errors are caused by the request itself, even if we lost master in the meantime
+        if matches!(
+            err,
+            Error::Access {
+                source: drm::SystemError::PermissionDenied,
+                ..
+            } | Error::TestFailed(_, Some(drm::SystemError::PermissionDenied))
+        ) {
+            match self.master_status() {
+                MasterStatus::SessionInactive => return Error::SessionInactive(self.dev_path()),
+                MasterStatus::NotMaster => return Error::NotMaster(self.dev_path()),
+                MasterStatus::Master | MasterStatus::Unprivileged => {}
+            }
+        }
+        err
+    }
+
+    /// Returns whether the surface may currently modify the display, to reason about permission errors.
+    ///
+    /// Commits failing with `EACCES` are already mapped to [`Error::SessionInactive`] or [`Error::NotMaster`]
+    /// accordingly. The session is considered inactive, if the device was paused via
+    /// [`DrmDevice::pause`](super::DrmDevice::pause), which should be wired up to the pause events of the session.
+    pub fn master_status(&self) -> MasterStatus {
+        let active = match &*self.internal {
+            DrmSurfaceInternal::Atomic(surf) => surf.active.load(Ordering::SeqCst),
+            DrmSurfaceInternal::Legacy(surf) => surf.active.load(Ordering::SeqCst),
+        };
+        let fd = self.device_fd();
+        if !active {
+            MasterStatus::SessionInactive
+        } else if !fd.is_privileged() {
+            MasterStatus::Unprivileged
+        } else if fd.is_master() {
+            MasterStatus::Master
+        } else {
+            MasterStatus::NotMaster
+        }
+    }
+
+    /// Returns whether the device currently accepts commits for this surface.
+    ///
+    /// After a session got re-activated the device might not be usable right away,
+    /// e.g. because drm master is re-acquired asynchronously. This may be polled
+    /// before committing to avoid running into permission errors.
+    ///
+    /// The probe is cheap: it consists of a master check and on atomic devices an additional
+    /// empty test-only commit, which is a single ioctl that does not modify any state.
+    pub fn is_ready(&self) -> bool {
+        let fd = self.device_fd();
+        if fd.is_privileged() && !fd.is_master() {
+            return false;
+        }
+
+        match &*self.internal {
+            DrmSurfaceInternal::Atomic(surf) => surf.is_ready(),
+            DrmSurfaceInternal::Legacy(surf) => surf.active.load(Ordering::SeqCst),
+        }
+    }
+
+    /// Returns the number of `MODE_ID` property blobs created by this surface,
+    /// that are still alive without being referenced by the current or pending state.
+    ///
+    /// Mode blobs are destroyed once they are replaced, so this should always be `0`.
+    /// Legacy surfaces do not use blobs and always return `0`.
+    ///
+    /// Only available with the `backend_drm_debug` feature.
+    #[cfg(feature = "backend_drm_debug")]
+    pub fn blob_leak_count(&self) -> usize {
+        match &*self.internal {
+            DrmSurfaceInternal::Atomic(surf) => surf.blob_leak_count(),
+            DrmSurfaceInternal::Legacy(_) => 0,
+        }
+    }
+
+    /// Sets a human-readable label, e.g. the name of the output, recorded on the tracing span of this surface.
+    ///
+    /// The label will show up in all events emitted by the surface, which makes logs of multiple
+    /// outputs easier to tell apart.
+    pub fn set_label(&self, name: impl Into<String>) {
+        self.span().record("label", name.into().as_str());
+    }
+
+    /// Returns the tracing span of this surface
+    ///
+    /// This can be used as a parent to emit events in the context of this surface.
+    pub fn span(&self) -> &tracing::Span {
         match &*self.internal {
             DrmSurfaceInternal::Atomic(surf) => &surf.span,
             DrmSurfaceInternal::Legacy(surf) => &surf.span,
@@ -544,6 +3259,156 @@ impl DrmSurface {
     }
 }
 
+// connector property of drivers allowing to control panel self-refresh
+const PSR_PROPERTY: &str = "PSR";
+
+// drivers copying the framebuffer contents to the display on `DRM_IOCTL_MODE_DIRTYFB`
+const DIRTY_FB_DRIVERS: &[&str] = &[
+    "udl",
+    "gud",
+    "gm12u320",
+    "hx8357d",
+    "ili9163",
+    "ili9225",
+    "ili9341",
+    "ili9486",
+    "mi0283qt",
+    "panel-mipi-dbi",
+    "repaper",
+    "st7586",
+    "st7735r",
+];
+
+fn clip_rect(rect: Rectangle<i32, Physical>) -> ClipRect {
+    let clamp = |value: i32| value.clamp(0, u16::MAX as i32) as u16;
+    ClipRect {
+        x1: clamp(rect.loc.x),
+        y1: clamp(rect.loc.y),
+        x2: clamp(rect.loc.x.saturating_add(rect.size.w)),
+        y2: clamp(rect.loc.y.saturating_add(rect.size.h)),
+    }
+}
+
+fn mode_size(mode: &Mode) -> Size<i32, Physical> {
+    let (w, h) = mode.size();
+    Size::from((w as i32, h as i32))
+}
+
+fn logical_size(size: Size<i32, Physical>, transform: Transform, scale: Option<f64>) -> Size<i32, Logical> {
+    transform
+        .transform_size(size)
+        .to_f64()
+        .to_logical(scale.unwrap_or(1.0))
+        .to_i32_ceil()
+}
+
+// Picks the best modifier out of `allocator_modifiers`, that is supported with `fourcc`
+fn scanout_modifier(
+    formats: &HashSet<Format>,
+    fourcc: Fourcc,
+    allocator_modifiers: &[Modifier],
+) -> Option<Modifier> {
+    let rank = |modifier: &Modifier| match modifier {
+        Modifier::Invalid => 2,
+        Modifier::Linear => 1,
+        _ => 0,
+    };
+    allocator_modifiers
+        .iter()
+        .filter(|modifier| {
+            formats.contains(&Format {
+                code: fourcc,
+                modifier: **modifier,
+            })
+        })
+        // `min_by_key` keeps the first of equally ranked modifiers
+        .min_by_key(|modifier| rank(modifier))
+        .copied()
+}
+
+// Runs `commit`, retrying once without damage clips, if the damage clips were rejected
+fn with_damage_fallback<'a>(
+    planes: Vec<PlaneState<'a>>,
+    mut commit: impl FnMut(Vec<PlaneState<'a>>) -> Result<CommitKind, Error>,
+) -> Result<(CommitKind, bool), Error> {
+    let has_damage = planes.iter().any(|state| {
+        matches!(
+            state.config,
+            Some(PlaneConfig {
+                damage_clips: Some(_),
+                ..
+            })
+        )
+    });
+
+    match commit(planes.clone()) {
+        Err(err) if has_damage && is_invalid_argument(&err) => {
+            warn!(
+                "Commit with damage clips was rejected, retrying without damage clips: {}",
+                err
+            );
+            let planes = planes
+                .into_iter()
+                .map(|mut state| {
+                    if let Some(config) = state.config.as_mut() {
+                        config.damage_clips = None;
+                    }
+                    state
+                })
+                .collect();
+            commit(planes).map(|kind| (kind, true))
+        }
+        res => res.map(|kind| (kind, false)),
+    }
+}
+
+// Whether the kernel rejected a request with `EINVAL`, either while testing or committing it
+fn is_invalid_argument(err: &Error) -> bool {
+    match err {
+        Error::Access { source, .. } | Error::TestFailed(_, Some(source)) => {
+            matches!(source, drm::SystemError::InvalidArgument)
+        }
+        _ => false,
+    }
+}
+
+// Closes the gem handles returned by `GETFB` and `GETFB2`, which create new handles for the drm master
+pub(super) fn close_gem_handles(dev: &impl AsFd, handles: impl IntoIterator<Item = u32>) {
+    let mut closed = Vec::new();
+    for handle in handles {
+        // unused planes have no handle and planes of the same buffer share the handle
+        if handle == 0 || closed.contains(&handle) {
+            continue;
+        }
+        if let Err(err) = drm_ffi::gem::close(dev.as_fd().as_raw_fd(), handle) {
+            warn!("Failed to close gem handle: {}", err);
+        }
+        closed.push(handle);
+    }
+}
+
+// Merges the successfully committed `planes` into `committed`,
+// planes missing from the commit keep their previous state.
+fn update_committed_planes(
+    committed: &mut Vec<PlaneState<'static>>,
+    planes: impl IntoIterator<Item = PlaneState<'static>>,
+) {
+    for plane in planes {
+        match committed.iter_mut().find(|state| state.handle == plane.handle) {
+            Some(state) => *state = plane,
+            None => committed.push(plane),
+        }
+    }
+}
+
+// the framebuffers attached to the planes by a commit
+fn plane_framebuffers(planes: &[PlaneState<'_>]) -> Vec<(plane::Handle, Option<framebuffer::Handle>)> {
+    planes
+        .iter()
+        .map(|state| (state.handle, state.config.as_ref().map(|config| config.fb)))
+        .collect()
+}
+
 fn ensure_legacy_planes<'a>(
     dev: &(impl ControlDevice + DevPath),
     planes: impl IntoIterator<Item = PlaneState<'a>>,
@@ -585,3 +3450,513 @@ fn ensure_legacy_planes<'a>(
 
     Ok(config.fb)
 }
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+    use std::num::NonZeroU32;
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+    use drm::control::{crtc, framebuffer, plane};
+
+    use super::{
+        damage_rects, logical_size, scanout_modifier, update_committed_planes, BufferRelease, DrmSurface,
+        FrameCounter, FramePacing, PlaneConfig, PlaneDamageClips, PlaneDamageInner, PlaneState, TileInfo,
+        WeakDrmSurface,
+    };
+    use crate::backend::allocator::{Format, Fourcc, Modifier};
+    use crate::backend::drm::{error::Error, DrmDeviceFd};
+    use crate::utils::{DeviceFd, Rectangle, Size, Transform};
+
+    pub(super) fn plane(id: u32) -> plane::Handle {
+        plane::Handle::from(NonZeroU32::new(id).unwrap())
+    }
+
+    // Damage clips referencing an existing blob, destroying it on drop fails without a drm device
+    pub(super) fn damage_clips(blob: u64) -> PlaneDamageClips {
+        let fd = std::os::unix::io::OwnedFd::from(std::fs::File::open("/dev/null").unwrap());
+        PlaneDamageClips {
+            inner: Arc::new(PlaneDamageInner {
+                drm: DrmDeviceFd::new(DeviceFd::from(fd)),
+                blob: Some(drm::control::property::Value::Blob(blob)),
+            }),
+        }
+    }
+
+    pub(super) fn config(fb: u32) -> PlaneConfig<'static> {
+        PlaneConfig {
+            src: Rectangle::from_loc_and_size((0.0, 0.0), (64.0, 64.0)),
+            dst: Rectangle::from_loc_and_size((0, 0), (64, 64)),
+            transform: Transform::Normal,
+            alpha: 1.0,
+            damage_clips: None,
+            fb: framebuffer::Handle::from(NonZeroU32::new(fb).unwrap()),
+            blend_mode: None,
+            scaling_filter: None,
+            skip_implicit_sync: false,
+        }
+    }
+
+    #[test]
+    fn plane_state_diff() {
+        let previous = [
+            PlaneState {
+                handle: plane(1),
+                config: Some(config(10)),
+            },
+            PlaneState {
+                handle: plane(2),
+                config: Some(config(20)),
+            },
+            PlaneState {
+                handle: plane(3),
+                config: Some(config(30)),
+            },
+        ];
+        let next = [
+            // unchanged
+            PlaneState {
+                handle: plane(1),
+                config: Some(config(10)),
+            },
+            // new framebuffer
+            PlaneState {
+                handle: plane(2),
+                config: Some(config(21)),
+            },
+            // cleared
+            PlaneState {
+                handle: plane(3),
+                config: None,
+            },
+            // added
+            PlaneState {
+                handle: plane(4),
+                config: Some(config(40)),
+            },
+        ];
+
+        let diff = PlaneState::diff(&previous, &next);
+        assert_eq!(diff, &next[1..]);
+    }
+
+    #[test]
+    fn committed_planes() {
+        let mut committed = Vec::new();
+        update_committed_planes(
+            &mut committed,
+            [
+                PlaneState {
+                    handle: plane(1),
+                    config: Some(config(10)),
+                },
+                PlaneState {
+                    handle: plane(2),
+                    config: Some(config(20)),
+                },
+            ],
+        );
+        // planes missing from the update keep their state
+        update_committed_planes(
+            &mut committed,
+            [PlaneState {
+                handle: plane(2),
+                config: None,
+            }],
+        );
+
+        let unchanged = [
+            PlaneState {
+                handle: plane(1),
+                config: Some(config(10)),
+            },
+            PlaneState {
+                handle: plane(2),
+                config: None,
+            },
+        ];
+        assert!(PlaneState::diff(&committed, &unchanged).is_empty());
+        let flipped = [PlaneState {
+            handle: plane(1),
+            config: Some(config(11)),
+        }];
+        assert_eq!(PlaneState::diff(&committed, &flipped), flipped);
+    }
+
+    #[test]
+    fn plane_state_diff_damage() {
+        let previous = [PlaneState {
+            handle: plane(1),
+            config: Some(config(10)),
+        }];
+        let mut damaged = config(10);
+        damaged.damage_clips = Some(drm::control::property::Value::Blob(5));
+        let next = [PlaneState {
+            handle: plane(1),
+            config: Some(damaged),
+        }];
+
+        assert_eq!(PlaneState::diff(&previous, &next), &next[..]);
+        assert!(PlaneState::diff(&next, &next).is_empty());
+    }
+
+    #[test]
+    fn buffer_release_after_flip() {
+        let release = BufferRelease::default();
+        let released = Arc::new(Mutex::new(Vec::new()));
+        let released_clone = released.clone();
+        *release.callback.lock().unwrap() = Some(Box::new(move |fb| released_clone.lock().unwrap().push(fb)));
+
+        let fb = |id| framebuffer::Handle::from(NonZeroU32::new(id).unwrap());
+        release.queue([(plane(1), Some(fb(10))), (plane(2), Some(fb(20)))]);
+        release.flipped();
+        assert!(released.lock().unwrap().is_empty());
+
+        // the buffer of plane 1 is only released once the flip replacing it happened
+        release.queue([(plane(1), Some(fb(11))), (plane(2), Some(fb(20)))]);
+        assert!(released.lock().unwrap().is_empty());
+        release.flipped();
+        assert_eq!(*released.lock().unwrap(), vec![fb(10)]);
+
+        // a new commit implies the previous one completed
+        release.queue([(plane(2), None)]);
+        release.queue([]);
+        assert_eq!(*released.lock().unwrap(), vec![fb(10), fb(20)]);
+    }
+
+    #[test]
+    fn frame_pacing_next_vblank() {
+        let frame = Duration::from_millis(16);
+        let flip = Instant::now();
+        let mut pacing = FramePacing {
+            target: Some(Duration::ZERO),
+            last_flip: Some(flip),
+            last_vblank: None,
+        };
+
+        let vblank = pacing.next_vblank(flip + Duration::from_millis(4), frame);
+        assert_eq!(vblank, Some(flip + frame));
+        pacing.last_vblank = vblank;
+
+        // a second commit before that vblank would overdrive the display
+        assert_eq!(pacing.next_vblank(flip + Duration::from_millis(10), frame), None);
+        assert_eq!(
+            pacing.next_vblank(flip + Duration::from_millis(20), frame),
+            Some(flip + frame * 2)
+        );
+
+        // limiting to half the refresh rate skips every other vblank
+        pacing.target = Some(frame * 2);
+        assert_eq!(pacing.next_vblank(flip + Duration::from_millis(20), frame), None);
+        assert_eq!(
+            pacing.next_vblank(flip + Duration::from_millis(36), frame),
+            Some(flip + frame * 3)
+        );
+
+        pacing.target = None;
+        assert!(pacing
+            .next_vblank(flip + Duration::from_millis(10), frame)
+            .is_some());
+    }
+
+    #[test]
+    fn tile_info_from_blob() {
+        let info = TileInfo::from_blob(b"1:1:2:1:1:0:3840:4320\0").unwrap();
+        assert_eq!(info.group_id, 1);
+        assert!(info.single_monitor);
+        assert_eq!(info.position(), (3840, 0).into());
+        assert_eq!(info.total_size(), (7680, 4320).into());
+
+        assert_eq!(TileInfo::from_blob(b"1:1:2:1"), None);
+    }
+
+    #[cfg(feature = "backend_drm_failure_injection")]
+    #[test]
+    fn damage_fallback() {
+        use super::{with_damage_fallback, CommitKind};
+        use crate::backend::drm::test::{InjectedFailures, Operation};
+        use nix::errno::Errno;
+
+        let dev = std::fs::File::open("/dev/null").unwrap();
+        let failures = InjectedFailures::default();
+        let damaged = || {
+            let mut config = config(10);
+            config.damage_clips = Some(drm::control::property::Value::Blob(5));
+            vec![PlaneState {
+                handle: plane(1),
+                config: Some(config),
+            }]
+        };
+        // returns the result and whether the planes of each attempt had damage clips
+        let run = |planes: Vec<PlaneState<'static>>| {
+            let mut attempts = Vec::new();
+            let res = with_damage_fallback(planes, |planes| {
+                attempts.push(planes[0].config.unwrap().damage_clips.is_some());
+                failures
+                    .check(Operation::Commit, &dev)
+                    .map(|_| CommitKind::PageFlip)
+            });
+            (res.ok(), attempts)
+        };
+
+        assert_eq!(run(damaged()), (Some((CommitKind::PageFlip, false)), vec![true]));
+
+        failures.fail_next(Operation::Commit, Errno::EINVAL);
+        assert_eq!(
+            run(damaged()),
+            (Some((CommitKind::PageFlip, true)), vec![true, false])
+        );
+
+        // other errors are not caused by the damage clips
+        failures.fail_next(Operation::Commit, Errno::EBUSY);
+        assert_eq!(run(damaged()), (None, vec![true]));
+
+        // there is nothing to drop without damage clips
+        failures.fail_next(Operation::Commit, Errno::EINVAL);
+        let undamaged = vec![PlaneState {
+            handle: plane(1),
+            config: Some(config(10)),
+        }];
+        assert_eq!(run(undamaged), (None, vec![false]));
+
+        // a rejected atomic test falls back as well
+        let mut attempts = 0;
+        let res = with_damage_fallback(damaged(), |_| {
+            attempts += 1;
+            if attempts == 1 {
+                Err(Error::TestFailed(
+                    crtc::Handle::from(NonZeroU32::new(1).unwrap()),
+                    Some(Errno::EINVAL.into()),
+                ))
+            } else {
+                Ok(CommitKind::PageFlip)
+            }
+        });
+        assert!(matches!(res, Ok((CommitKind::PageFlip, true))));
+        assert_eq!(attempts, 2);
+    }
+
+    #[test]
+    fn plane_config_into_owned() {
+        let fd = std::os::unix::io::OwnedFd::from(std::fs::File::open("/dev/null").unwrap());
+        let drm = DrmDeviceFd::new(DeviceFd::from(fd));
+        let damage = PlaneDamageClips {
+            inner: Arc::new(PlaneDamageInner {
+                drm,
+                blob: Some(drm::control::property::Value::Blob(5)),
+            }),
+        };
+
+        let mut damaged = config(10);
+        damaged.damage_clips = Some(drm::control::property::Value::Blob(5));
+        let owned = PlaneConfig {
+            damage_clips: Some(damage.blob()),
+            ..damaged
+        }
+        .into_owned(Some(damage.clone()));
+        // the owned config keeps the blob alive
+        drop(damage);
+        let inner = &owned.damage_clips.as_ref().unwrap().inner;
+        assert_eq!(Arc::strong_count(inner), 1);
+        assert_eq!(inner.blob, Some(drm::control::property::Value::Blob(5)));
+
+        let state = PlaneState {
+            handle: plane(1),
+            config: Some(PlaneConfig::from(&owned)),
+        };
+        assert_eq!(state.config, Some(damaged));
+        assert!(PlaneState::diff(&[state.clone().detach()], &[state]).is_empty());
+    }
+
+    #[test]
+    fn damage_rects_offset_overlay() {
+        // overlay at (100, 50) showing a framebuffer scaled down by 2
+        let src = Rectangle::from_loc_and_size((0.0, 0.0), (400.0, 200.0));
+        let dst = Rectangle::from_loc_and_size((100, 50), (200, 100));
+        let damage = [
+            Rectangle::from_loc_and_size((110, 60), (20, 10)),
+            // partially outside of the plane
+            Rectangle::from_loc_and_size((90, 40), (20, 20)),
+            // not on the plane at all
+            Rectangle::from_loc_and_size((0, 0), (50, 50)),
+        ];
+
+        let rects = damage_rects(src, dst, (1920, 1080).into(), damage)
+            .into_iter()
+            .map(|rect| (rect.x1, rect.y1, rect.x2, rect.y2))
+            .collect::<Vec<_>>();
+        assert_eq!(rects, vec![(20, 20, 60, 40), (0, 0, 20, 20)]);
+    }
+
+    #[test]
+    fn damage_rects_clamped_to_mode() {
+        let mode = (1920, 1080).into();
+
+        // primary plane covering the mode
+        let src = Rectangle::from_loc_and_size((0.0, 0.0), (1920.0, 1080.0));
+        let dst = Rectangle::from_loc_and_size((0, 0), (1920, 1080));
+        let damage = [
+            Rectangle::from_loc_and_size((-10, -10), (20, 20)),
+            Rectangle::from_loc_and_size((1900, 1000), (100, 100)),
+            // completely off-screen
+            Rectangle::from_loc_and_size((1920, 0), (10, 10)),
+        ];
+        let rects = damage_rects(src, dst, mode, damage)
+            .into_iter()
+            .map(|rect| (rect.x1, rect.y1, rect.x2, rect.y2))
+            .collect::<Vec<_>>();
+        assert_eq!(rects, vec![(0, 0, 10, 10), (1900, 1000, 1920, 1080)]);
+
+        // overlay partially outside of the mode
+        let src = Rectangle::from_loc_and_size((0.0, 0.0), (200.0, 200.0));
+        let dst = Rectangle::from_loc_and_size((1800, 1000), (200, 200));
+        let damage = [Rectangle::from_loc_and_size((1850, 1050), (100, 100))];
+        let rects = damage_rects(src, dst, mode, damage)
+            .into_iter()
+            .map(|rect| (rect.x1, rect.y1, rect.x2, rect.y2))
+            .collect::<Vec<_>>();
+        assert_eq!(rects, vec![(50, 50, 120, 80)]);
+    }
+
+    #[test]
+    fn plane_config_src_fixed() {
+        let mut config = config(10);
+        config.src = Rectangle::from_loc_and_size((0.5, 10.25), (1919.75, 1080.0));
+
+        assert_eq!(config.src_fixed(), (0x8000, 0xa_4000, 0x77f_c000, 0x438_0000));
+    }
+
+    #[test]
+    fn scanout_modifier_preference() {
+        let tiled = Modifier::from(0x0100_0000_0000_0001);
+        let compressed = Modifier::from(0x0100_0000_0000_0004);
+        let formats = [Modifier::Invalid, Modifier::Linear, tiled, compressed]
+            .into_iter()
+            .map(|modifier| Format {
+                code: Fourcc::Argb8888,
+                modifier,
+            })
+            .collect::<HashSet<_>>();
+
+        let all = [Modifier::Invalid, Modifier::Linear, compressed, tiled];
+        assert_eq!(
+            scanout_modifier(&formats, Fourcc::Argb8888, &all),
+            Some(compressed)
+        );
+        assert_eq!(
+            scanout_modifier(&formats, Fourcc::Argb8888, &[Modifier::Invalid, Modifier::Linear]),
+            Some(Modifier::Linear)
+        );
+        assert_eq!(
+            scanout_modifier(&formats, Fourcc::Argb8888, &[Modifier::Invalid]),
+            Some(Modifier::Invalid)
+        );
+        // not supported by the plane
+        assert_eq!(scanout_modifier(&formats, Fourcc::Xrgb8888, &all), None);
+        assert_eq!(
+            scanout_modifier(
+                &formats,
+                Fourcc::Argb8888,
+                &[Modifier::from(0x0200_0000_0000_0001)]
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn logical_size_transformed() {
+        let size = Size::from((1920, 1080));
+        assert_eq!(
+            logical_size(size, Transform::Normal, None),
+            Size::from((1920, 1080))
+        );
+        assert_eq!(logical_size(size, Transform::_90, None), Size::from((1080, 1920)));
+        assert_eq!(
+            logical_size(size, Transform::Flipped270, Some(2.0)),
+            Size::from((540, 960))
+        );
+        assert_eq!(
+            logical_size(size, Transform::_180, Some(1.5)),
+            Size::from((1280, 720))
+        );
+        assert_eq!(
+            logical_size(size, Transform::Normal, Some(1.25)),
+            Size::from((1536, 864))
+        );
+        // fractional sizes are rounded up
+        assert_eq!(
+            logical_size(size, Transform::_270, Some(1.75)),
+            Size::from((618, 1098))
+        );
+    }
+
+    fn is_send_sync<S: Send + Sync>() {}
+
+    #[test]
+    fn surface_is_send_sync() {
+        is_send_sync::<DrmSurface>();
+        is_send_sync::<WeakDrmSurface>();
+    }
+
+    #[test]
+    fn shared_damage_blob() {
+        // two planes scanning out the same region, referencing one damage blob in the same commit
+        let clips = damage_clips(7);
+        let damage = clips.blob();
+        let mut primary = config(10);
+        primary.damage_clips = Some(damage);
+        let mut overlay = config(20);
+        overlay.damage_clips = Some(damage);
+        let commit = [
+            PlaneState {
+                handle: plane(1),
+                config: Some(primary),
+            },
+            PlaneState {
+                handle: plane(2),
+                config: Some(overlay),
+            },
+        ];
+
+        let previous = [
+            PlaneState {
+                handle: plane(1),
+                config: Some(config(10)),
+            },
+            PlaneState {
+                handle: plane(2),
+                config: Some(config(20)),
+            },
+        ];
+        // the shared damage is part of the state of both planes
+        assert_eq!(PlaneState::diff(&previous, &commit), commit.to_vec());
+
+        let owned = commit.iter().cloned().map(PlaneState::detach).collect::<Vec<_>>();
+        assert!(owned
+            .iter()
+            .all(|state| state.config.unwrap().damage_clips == Some(damage)));
+        assert!(PlaneState::diff(&commit, &owned).is_empty());
+    }
+
+    #[test]
+    fn flip_timeout() {
+        let crtc = crtc::Handle::from(NonZeroU32::new(1).unwrap());
+        let counter = FrameCounter::default();
+        counter.flip_requested();
+        assert!(counter.check_flip_timeout(crtc).is_ok());
+
+        counter.watchdog.lock().unwrap().timeout = Some(Duration::ZERO);
+        // the flip arrived in time
+        counter.vblank(1);
+        assert!(counter.check_flip_timeout(crtc).is_ok());
+
+        counter.flip_requested();
+        assert!(matches!(
+            counter.check_flip_timeout(crtc),
+            Err(Error::FlipTimeout(timed_out)) if timed_out == crtc
+        ));
+        // reported only once
+        assert!(counter.check_flip_timeout(crtc).is_ok());
+    }
+}
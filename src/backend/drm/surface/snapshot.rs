@@ -0,0 +1,142 @@
+//! Snapshots of the mutable properties of connectors
+
+use std::os::unix::io::AsRawFd;
+
+use drm::control::{connector, property, Device as ControlDevice};
+
+use crate::{
+    backend::drm::{error::Error, DrmDeviceFd},
+    utils::DevPath,
+};
+
+// Properties managed by the surface itself or not settable via atomic commits
+const EXCLUDED_PROPERTIES: &[&str] = &["CRTC_ID", "DPMS"];
+
+/// Snapshot of the mutable properties of a connector
+///
+/// Created by [`DrmSurface::connector_property_snapshot`](super::DrmSurface::connector_property_snapshot)
+/// and restored by [`DrmSurface::restore_connector_properties`](super::DrmSurface::restore_connector_properties).
+/// The contents of blob properties (like `HDR_OUTPUT_METADATA`) are copied,
+/// so the snapshot stays valid even if the original blob is destroyed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PropertySnapshot {
+    connector: connector::Handle,
+    values: Vec<(property::Handle, SnapshotValue)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) enum SnapshotValue {
+    Raw(property::RawValue),
+    Blob(Vec<u8>),
+}
+
+impl PropertySnapshot {
+    pub(super) fn new(
+        fd: &(impl ControlDevice + DevPath),
+        connector: connector::Handle,
+    ) -> Result<Self, Error> {
+        let props = fd.get_properties(connector).map_err(|source| Error::Access {
+            errmsg: "Failed to get properties for connector",
+            dev: fd.dev_path(),
+            source,
+        })?;
+        let (handles, raw_values) = props.as_props_and_values();
+
+        let mut values = Vec::new();
+        for (&handle, &raw_value) in handles.iter().zip(raw_values.iter()) {
+            let info = fd.get_property(handle).map_err(|source| Error::Access {
+                errmsg: "Failed to get property of connector",
+                dev: fd.dev_path(),
+                source,
+            })?;
+            if !info.mutable()
+                || info
+                    .name()
+                    .to_str()
+                    .map(|name| EXCLUDED_PROPERTIES.contains(&name))
+                    .unwrap_or(true)
+            {
+                continue;
+            }
+
+            let value = match info.value_type() {
+                property::ValueType::Blob if raw_value != 0 => {
+                    let data = fd.get_property_blob(raw_value).map_err(|source| Error::Access {
+                        errmsg: "Failed to query property blob data",
+                        dev: fd.dev_path(),
+                        source,
+                    })?;
+                    SnapshotValue::Blob(data)
+                }
+                _ => SnapshotValue::Raw(raw_value),
+            };
+            values.push((handle, value));
+        }
+
+        Ok(PropertySnapshot { connector, values })
+    }
+
+    /// Returns the connector this snapshot was taken of
+    pub fn connector(&self) -> connector::Handle {
+        self.connector
+    }
+
+    /// Returns the number of properties captured by this snapshot
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    /// Returns `true` if no mutable properties were captured
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Returns the raw values to set for every property of this snapshot.
+    ///
+    /// Blobs are created as necessary and destroyed again once the returned [`StagedBlob`]s are dropped,
+    /// which is fine as soon as the values are committed, as the kernel holds its own reference.
+    pub(super) fn stage(
+        &self,
+        fd: &DrmDeviceFd,
+    ) -> Result<(Vec<(property::Handle, property::RawValue)>, Vec<StagedBlob>), Error> {
+        let mut values = Vec::with_capacity(self.values.len());
+        let mut blobs = Vec::new();
+        for (handle, value) in self.values.iter() {
+            let raw_value = match value {
+                SnapshotValue::Raw(raw_value) => *raw_value,
+                SnapshotValue::Blob(data) => {
+                    let mut data = data.clone();
+                    let blob =
+                        drm_ffi::mode::create_property_blob(fd.as_raw_fd(), &mut data).map_err(|source| {
+                            Error::Access {
+                                errmsg: "Failed to create property blob",
+                                dev: fd.dev_path(),
+                                source,
+                            }
+                        })?;
+                    blobs.push(StagedBlob {
+                        fd: fd.clone(),
+                        id: blob.blob_id,
+                    });
+                    blob.blob_id as property::RawValue
+                }
+            };
+            values.push((*handle, raw_value));
+        }
+        Ok((values, blobs))
+    }
+}
+
+/// A property blob created for staging a [`PropertySnapshot`], destroyed on drop
+#[derive(Debug)]
+pub(super) struct StagedBlob {
+    fd: DrmDeviceFd,
+    id: u32,
+}
+
+impl Drop for StagedBlob {
+    fn drop(&mut self) {
+        // There is nothing we can do if that fails
+        let _ = self.fd.destroy_property_blob(self.id as u64);
+    }
+}
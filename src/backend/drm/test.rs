@@ -0,0 +1,133 @@
+//! Helpers for testing the error handling of code using a [`DrmSurface`](super::DrmSurface).
+//!
+//! Failures of specific operations can be injected into a surface via [`DrmSurface::injected_failures`](super::DrmSurface::injected_failures).
+//! An injected failure makes the operation fail with an [`Error::Access`](super::DrmError::Access) carrying the chosen errno,
+//! before anything is submitted to the device, just like the kernel rejecting the request would.
+//! This allows to exercise fallback paths, e.g. on a virtual device like `vkms` in CI.
+//!
+//! This module is only available with the `backend_drm_failure_injection` feature and should never be enabled
+//! for production builds.
+//!
+//! ```no_run
+//! # use smithay::backend::drm::{DrmSurface, test::Operation};
+//! # fn test(surface: &DrmSurface) {
+//! use nix::errno::Errno;
+//!
+//! // the next test commit is rejected, as if the configuration was not supported
+//! surface.injected_failures().fail_next(Operation::TestState, Errno::EINVAL);
+//! assert!(surface.test_state([], false).is_err());
+//! assert!(surface.test_state([], false).is_ok());
+//! # }
+//! ```
+
+use std::sync::{Arc, Mutex};
+
+use nix::errno::Errno;
+
+use super::error::Error;
+use crate::utils::DevPath;
+
+/// Operations of a [`DrmSurface`](super::DrmSurface) failures can be injected into
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Operation {
+    /// [`DrmSurface::test_state`](super::DrmSurface::test_state)
+    TestState,
+    /// [`DrmSurface::commit`](super::DrmSurface::commit) and its variants
+    Commit,
+    /// [`DrmSurface::page_flip`](super::DrmSurface::page_flip) and its variants
+    PageFlip,
+}
+
+#[derive(Debug)]
+struct Failure {
+    operation: Operation,
+    errno: Errno,
+    // `None` fails every call
+    remaining: Option<usize>,
+}
+
+/// Failures injected into a [`DrmSurface`](super::DrmSurface)
+///
+/// Cloned handles refer to the same set of failures.
+#[derive(Debug, Clone, Default)]
+pub struct InjectedFailures(Arc<Mutex<Vec<Failure>>>);
+
+impl InjectedFailures {
+    /// Makes the next call of `operation` fail with `errno`.
+    ///
+    /// Multiple queued failures of the same operation fail consecutive calls.
+    pub fn fail_next(&self, operation: Operation, errno: Errno) {
+        self.0.lock().unwrap().push(Failure {
+            operation,
+            errno,
+            remaining: Some(1),
+        });
+    }
+
+    /// Makes every call of `operation` fail with `errno`, until [`clear`](InjectedFailures::clear) is called.
+    pub fn fail_always(&self, operation: Operation, errno: Errno) {
+        self.0.lock().unwrap().push(Failure {
+            operation,
+            errno,
+            remaining: None,
+        });
+    }
+
+    /// Removes all injected failures
+    pub fn clear(&self) {
+        self.0.lock().unwrap().clear();
+    }
+
+    // Returns the errno the next call of `operation` should fail with
+    fn next(&self, operation: Operation) -> Option<Errno> {
+        let mut failures = self.0.lock().unwrap();
+        let index = failures
+            .iter()
+            .position(|failure| failure.operation == operation)?;
+        let failure = &mut failures[index];
+        let errno = failure.errno;
+        if let Some(remaining) = failure.remaining.as_mut() {
+            *remaining -= 1;
+            if *remaining == 0 {
+                failures.remove(index);
+            }
+        }
+        Some(errno)
+    }
+
+    pub(super) fn check(&self, operation: Operation, dev: &impl DevPath) -> Result<(), Error> {
+        match self.next(operation) {
+            Some(errno) => Err(Error::Access {
+                errmsg: "Injected failure",
+                dev: dev.dev_path(),
+                source: errno.into(),
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use nix::errno::Errno;
+
+    use super::{InjectedFailures, Operation};
+
+    #[test]
+    fn injected_failures() {
+        let failures = InjectedFailures::default();
+        failures.fail_next(Operation::Commit, Errno::EINVAL);
+        failures.fail_next(Operation::Commit, Errno::EBUSY);
+        failures.fail_always(Operation::PageFlip, Errno::EACCES);
+
+        assert_eq!(failures.next(Operation::TestState), None);
+        assert_eq!(failures.next(Operation::Commit), Some(Errno::EINVAL));
+        assert_eq!(failures.next(Operation::Commit), Some(Errno::EBUSY));
+        assert_eq!(failures.next(Operation::Commit), None);
+        assert_eq!(failures.next(Operation::PageFlip), Some(Errno::EACCES));
+        assert_eq!(failures.next(Operation::PageFlip), Some(Errno::EACCES));
+
+        failures.clear();
+        assert_eq!(failures.next(Operation::PageFlip), None);
+    }
+}
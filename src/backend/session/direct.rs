@@ -0,0 +1,416 @@
+//!
+//! Implementation of the [`Session`](::backend::session::Session) trait through direct device access.
+//!
+//! This requires the process to be allowed to open the devices directly, which usually means
+//! running as root, and to be started from a virtual terminal. It is meant for embedded use-cases,
+//! where no seat manager like seatd or logind is available.
+//!
+//! The session takes control of the virtual terminal it is started on: keyboard input to the
+//! terminal is disabled, the terminal is switched to graphics mode and vt switches are handled
+//! through the `SIGUSR1`/`SIGUSR2` signals, which are blocked for the thread creating the session
+//! and delivered through the [`DirectSessionNotifier`]. The previous terminal state is restored once
+//! the notifier is dropped.
+
+use std::{
+    borrow::Cow,
+    cell::Cell,
+    os::unix::io::RawFd,
+    path::{Path, PathBuf},
+    rc::{Rc, Weak},
+};
+
+use nix::{
+    errno::Errno,
+    fcntl::{self, OFlag},
+    sys::stat::{major, minor, Mode},
+    unistd::close,
+};
+
+use calloop::{
+    signals::{Event as SignalEvent, Signal, Signals},
+    EventSource, Poll, PostAction, Readiness, Token, TokenFactory,
+};
+
+use crate::backend::session::{ActivationCause, AsErrno, Event as SessionEvent, Session};
+
+use tracing::{debug, error, info, info_span, instrument, warn};
+
+#[allow(dead_code)]
+mod tty {
+    use nix::{ioctl_read, ioctl_read_bad, ioctl_write_int_bad, ioctl_write_ptr_bad};
+
+    ioctl_read!(tiocgdev, b'T', 0x32, u32);
+
+    ioctl_read_bad!(kd_get_mode, 0x4B3B, i32);
+    ioctl_write_int_bad!(kd_set_mode, 0x4B3A);
+    pub const KD_TEXT: i32 = 0x00;
+    pub const KD_GRAPHICS: i32 = 0x01;
+
+    ioctl_read_bad!(kd_get_kb_mode, 0x4B44, i32);
+    ioctl_write_int_bad!(kd_set_kb_mode, 0x4B45);
+    pub const K_OFF: i32 = 0x04;
+
+    ioctl_write_int_bad!(vt_activate, 0x5606);
+    ioctl_write_int_bad!(vt_rel_disp, 0x5605);
+    pub const VT_ACKACQ: i32 = 0x02;
+
+    #[repr(C)]
+    pub struct VtMode {
+        /// vt mode
+        pub mode: i8,
+        /// if set, hang on writes if not active
+        pub waitv: i8,
+        /// signal to raise on release req
+        pub relsig: i16,
+        /// signal to raise on acquisition
+        pub acqsig: i16,
+        /// unused (set to 0)
+        pub frsig: i16,
+    }
+    ioctl_write_ptr_bad!(vt_set_mode, 0x5602, VtMode);
+    pub const VT_AUTO: i8 = 0x00;
+    pub const VT_PROCESS: i8 = 0x01;
+
+    #[repr(C)]
+    #[derive(Default)]
+    pub struct VtState {
+        /// active vt
+        pub v_active: u16,
+        /// signal to send
+        pub v_signal: u16,
+        /// vt bitmask
+        pub v_state: u16,
+    }
+    ioctl_read_bad!(vt_get_state, 0x5603, VtState);
+
+    pub const TTY_MAJOR: u64 = 4;
+    pub const MAX_NR_CONSOLES: u64 = 63;
+}
+
+#[derive(Debug)]
+struct DirectSessionImpl {
+    tty: RawFd,
+    vt: i32,
+    old_keyboard_mode: i32,
+    // whether the session was active at any point
+    activated: Cell<bool>,
+}
+
+fn active_vt(tty: RawFd) -> Result<i32, Errno> {
+    let mut state = tty::VtState::default();
+    unsafe { tty::vt_get_state(tty, &mut state) }?;
+    Ok(state.v_active as i32)
+}
+
+impl Drop for DirectSessionImpl {
+    fn drop(&mut self) {
+        debug!("Restoring vt {}", self.vt);
+        if let Err(err) = unsafe { tty::kd_set_kb_mode(self.tty, self.old_keyboard_mode) } {
+            warn!("Unable to restore vt keyboard mode: {}", err);
+        }
+        if let Err(err) = unsafe { tty::kd_set_mode(self.tty, tty::KD_TEXT) } {
+            warn!("Unable to restore vt text mode: {}", err);
+        }
+        if let Err(err) = unsafe {
+            tty::vt_set_mode(
+                self.tty,
+                &tty::VtMode {
+                    mode: tty::VT_AUTO,
+                    waitv: 0,
+                    relsig: 0,
+                    acqsig: 0,
+                    frsig: 0,
+                },
+            )
+        } {
+            error!("Failed to reset vt handling: {}", err);
+        }
+        if let Err(err) = close(self.tty) {
+            error!("Failed to close tty file descriptor: {}", err);
+        }
+    }
+}
+
+/// [`Session`] via direct device access
+#[derive(Debug, Clone)]
+pub struct DirectSession {
+    internal: Weak<DirectSessionImpl>,
+    span: tracing::Span,
+}
+
+/// [`SessionNotifier`] via the vt switching signals
+#[derive(Debug)]
+pub struct DirectSessionNotifier {
+    internal: Rc<DirectSessionImpl>,
+    signals: Signals,
+    span: tracing::Span,
+}
+
+impl DirectSession {
+    /// Tries to create a new session by taking control of the virtual terminal `tty`.
+    ///
+    /// Defaults to the controlling terminal of the process (`/dev/tty`), if no path is given.
+    pub fn new(tty: Option<&Path>) -> Result<(DirectSession, DirectSessionNotifier), Error> {
+        let span = info_span!("backend_session", "type" = "direct");
+        let _guard = span.enter();
+
+        let path = tty.unwrap_or_else(|| Path::new("/dev/tty"));
+        let fd = fcntl::open(path, OFlag::O_RDWR | OFlag::O_CLOEXEC, Mode::empty()).map_err(|source| {
+            Error::FailedToOpenTTY {
+                path: path.to_path_buf(),
+                source,
+            }
+        })?;
+
+        let internal = match Self::setup_tty(fd) {
+            Ok(internal) => internal,
+            Err(err) => {
+                let _ = close(fd);
+                return Err(err);
+            }
+        };
+        info!("Took control of vt {}", internal.vt);
+
+        // The signals need to be blocked before we ask the kernel to send them,
+        // otherwise a vt switch would terminate the process.
+        let signals =
+            Signals::new(&[Signal::SIGUSR1, Signal::SIGUSR2]).map_err(Error::FailedToSetupSignals)?;
+        unsafe {
+            tty::vt_set_mode(
+                fd,
+                &tty::VtMode {
+                    mode: tty::VT_PROCESS,
+                    waitv: 0,
+                    relsig: Signal::SIGUSR1 as i16,
+                    acqsig: Signal::SIGUSR2 as i16,
+                    frsig: 0,
+                },
+            )
+        }
+        .map_err(Error::FailedToTakeControlOfTTY)?;
+
+        let internal = Rc::new(internal);
+        let session = DirectSession {
+            internal: Rc::downgrade(&internal),
+            span: span.clone(),
+        };
+
+        drop(_guard);
+        Ok((
+            session,
+            DirectSessionNotifier {
+                internal,
+                signals,
+                span,
+            },
+        ))
+    }
+
+    // Takes ownership of `fd` only on success, on error the caller has to close it.
+    fn setup_tty(fd: RawFd) -> Result<DirectSessionImpl, Error> {
+        // `/dev/tty` is an alias, ask the kernel for the terminal it refers to
+        let mut dev = 0;
+        unsafe { tty::tiocgdev(fd, &mut dev) }.map_err(|_| Error::NotRunningFromTTY)?;
+        let (major, minor) = (major(dev as u64), minor(dev as u64));
+        if major != tty::TTY_MAJOR || minor == 0 || minor > tty::MAX_NR_CONSOLES {
+            return Err(Error::NotRunningFromTTY);
+        }
+        let vt = minor as i32;
+        // query this before taking over the tty, dropping a `DirectSessionImpl` closes `fd`
+        let active = active_vt(fd).map_err(Error::FailedToTakeControlOfTTY)? == vt;
+
+        let mut old_keyboard_mode = 0;
+        unsafe { tty::kd_get_kb_mode(fd, &mut old_keyboard_mode) }
+            .map_err(Error::FailedToTakeControlOfTTY)?;
+        unsafe { tty::kd_set_kb_mode(fd, tty::K_OFF) }.map_err(Error::FailedToTakeControlOfTTY)?;
+        if let Err(err) = unsafe { tty::kd_set_mode(fd, tty::KD_GRAPHICS) } {
+            let _ = unsafe { tty::kd_set_kb_mode(fd, old_keyboard_mode) };
+            return Err(Error::FailedToTakeControlOfTTY(err));
+        }
+
+        Ok(DirectSessionImpl {
+            tty: fd,
+            vt,
+            old_keyboard_mode,
+            activated: Cell::new(active),
+        })
+    }
+
+    /// Returns the virtual terminal this session is running on.
+    pub fn vt(&self) -> Option<i32> {
+        self.internal.upgrade().map(|internal| internal.vt)
+    }
+}
+
+impl Session for DirectSession {
+    type Error = Error;
+
+    #[instrument(parent = &self.span, skip(self))]
+    fn open(&mut self, path: &Path, flags: OFlag) -> Result<RawFd, Self::Error> {
+        if self.internal.upgrade().is_some() {
+            debug!("Opening device: {:?}", path);
+            fcntl::open(path, flags, Mode::empty()).map_err(Error::FailedToOpenDevice)
+        } else {
+            Err(Error::SessionLost)
+        }
+    }
+
+    #[instrument(parent = &self.span, skip(self))]
+    fn close(&mut self, fd: RawFd) -> Result<(), Self::Error> {
+        debug!("Closing device: {:?}", fd);
+        close(fd).map_err(Error::FailedToCloseDevice)
+    }
+
+    #[instrument(parent = &self.span, skip(self))]
+    fn change_vt(&mut self, vt: i32) -> Result<(), Self::Error> {
+        if let Some(session) = self.internal.upgrade() {
+            debug!("Session switch: {:?}", vt);
+            unsafe { tty::vt_activate(session.tty, vt) }
+                .map(|_| ())
+                .map_err(Error::FailedToChangeVt)
+        } else {
+            Err(Error::SessionLost)
+        }
+    }
+
+    fn is_active(&self) -> bool {
+        if let Some(internal) = self.internal.upgrade() {
+            active_vt(internal.tty)
+                .map(|vt| vt == internal.vt)
+                .unwrap_or(false)
+        } else {
+            false
+        }
+    }
+
+    fn seat(&self) -> String {
+        String::from("seat0")
+    }
+
+    fn seat_name(&self) -> Cow<'_, str> {
+        Cow::Borrowed("seat0")
+    }
+}
+
+impl DirectSessionNotifier {
+    /// Creates a new session object belonging to this notifier.
+    pub fn session(&self) -> DirectSession {
+        DirectSession {
+            internal: Rc::downgrade(&self.internal),
+            span: self.span.clone(),
+        }
+    }
+}
+
+impl EventSource for DirectSessionNotifier {
+    type Event = SessionEvent;
+    type Metadata = ();
+    type Ret = ();
+    type Error = Error;
+
+    fn process_events<F>(
+        &mut self,
+        readiness: Readiness,
+        token: Token,
+        mut callback: F,
+    ) -> Result<PostAction, Error>
+    where
+        F: FnMut(SessionEvent, &mut ()),
+    {
+        let internal = &self.internal;
+        let mut result = Ok(());
+        let action = self
+            .signals
+            .process_events(readiness, token, |event: SignalEvent, _| match event.signal() {
+                Signal::SIGUSR1 => {
+                    debug!("Vt release requested");
+                    callback(SessionEvent::PauseSession, &mut ());
+                    // the devices have been paused, allow the kernel to switch away
+                    if let Err(err) = unsafe { tty::vt_rel_disp(internal.tty, 1) } {
+                        result = Err(Error::FailedToChangeVt(err));
+                    }
+                }
+                Signal::SIGUSR2 => {
+                    debug!("Vt acquired");
+                    if let Err(err) = unsafe { tty::vt_rel_disp(internal.tty, tty::VT_ACKACQ) } {
+                        result = Err(Error::FailedToChangeVt(err));
+                    }
+                    let cause = if internal.activated.replace(true) {
+                        ActivationCause::Resumed
+                    } else {
+                        ActivationCause::Initial
+                    };
+                    callback(SessionEvent::ActivateSession { cause }, &mut ());
+                }
+                _ => {}
+            })
+            .map_err(|_| Error::SessionLost)?;
+        result.map(|_| action)
+    }
+
+    fn register(&mut self, poll: &mut Poll, factory: &mut TokenFactory) -> calloop::Result<()> {
+        self.signals.register(poll, factory)
+    }
+
+    fn reregister(&mut self, poll: &mut Poll, factory: &mut TokenFactory) -> calloop::Result<()> {
+        self.signals.reregister(poll, factory)
+    }
+
+    fn unregister(&mut self, poll: &mut Poll) -> calloop::Result<()> {
+        self.signals.unregister(poll)
+    }
+}
+
+/// Errors related to direct/tty sessions
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    /// Failed to open tty
+    #[error("Failed to open tty `{path:?}`: {source}")]
+    FailedToOpenTTY {
+        /// Path of the tty
+        path: PathBuf,
+        /// Underlying error
+        source: Errno,
+    },
+
+    /// The tty is not a virtual terminal
+    #[error("Not running from a virtual terminal")]
+    NotRunningFromTTY,
+
+    /// Failed to take control of the tty
+    #[error("Failed to take control of the tty: {0}")]
+    FailedToTakeControlOfTTY(Errno),
+
+    /// Failed to setup vt switching signals
+    #[error("Failed to setup vt switching signals")]
+    FailedToSetupSignals(#[source] calloop::Error),
+
+    /// Failed to open device
+    #[error("Failed to open device: {0}")]
+    FailedToOpenDevice(Errno),
+
+    /// Failed to close device
+    #[error("Failed to close device: {0}")]
+    FailedToCloseDevice(Errno),
+
+    /// Failed to change vt
+    #[error("Failed to change vt: {0}")]
+    FailedToChangeVt(Errno),
+
+    /// Session is already closed,
+    #[error("Session is already closed")]
+    SessionLost,
+}
+
+impl AsErrno for Error {
+    fn as_errno(&self) -> Option<i32> {
+        match self {
+            &Self::FailedToOpenTTY { source: errno, .. }
+            | &Self::FailedToTakeControlOfTTY(errno)
+            | &Self::FailedToOpenDevice(errno)
+            | &Self::FailedToCloseDevice(errno)
+            | &Self::FailedToChangeVt(errno) => Some(errno as i32),
+            _ => None,
+        }
+    }
+}
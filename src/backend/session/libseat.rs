@@ -5,7 +5,8 @@
 
 use libseat::{Seat, SeatEvent};
 use std::{
-    cell::RefCell,
+    borrow::Cow,
+    cell::{Cell, RefCell},
     collections::HashMap,
     os::unix::io::RawFd,
     path::Path,
@@ -14,6 +15,7 @@ use std::{
         atomic::{AtomicBool, Ordering},
         Arc,
     },
+    time::Duration,
 };
 
 use nix::{errno::Errno, fcntl::OFlag, unistd::close};
@@ -23,15 +25,21 @@ use calloop::{
     EventSource, Poll, PostAction, Readiness, Token, TokenFactory,
 };
 
-use crate::backend::session::{AsErrno, Event as SessionEvent, Session};
+use crate::backend::session::{ActivationCause, AsErrno, Event as SessionEvent, Session};
 
-use tracing::{debug, error, info_span, instrument};
+use tracing::{debug, error, info_span, instrument, warn};
 
 #[derive(Debug)]
 struct LibSeatSessionImpl {
-    seat: RefCell<Seat>,
+    // `None` once the seat was closed by `Session::disable`
+    seat: RefCell<Option<Seat>>,
+    seat_name: String,
     active: Arc<AtomicBool>,
     devices: RefCell<HashMap<RawFd, i32>>,
+    // whether the session was active at any point
+    activated: Cell<bool>,
+    // number of retries and initial backoff for opening devices
+    open_retry: Cell<(u32, Duration)>,
 }
 
 impl Drop for LibSeatSessionImpl {
@@ -91,9 +99,12 @@ impl LibSeatSession {
             let active = matches!(rx.try_recv(), Ok(SeatEvent::Enable));
 
             let internal = Rc::new(LibSeatSessionImpl {
-                seat: RefCell::new(seat),
+                seat: RefCell::new(Some(seat)),
+                seat_name: seat_name.clone(),
                 active: Arc::new(AtomicBool::new(active)),
                 devices: RefCell::new(HashMap::new()),
+                activated: Cell::new(active),
+                open_retry: Cell::new((0, Duration::ZERO)),
             });
 
             let session = LibSeatSession {
@@ -113,6 +124,19 @@ impl LibSeatSession {
         })
         .map_err(|err| Error::FailedToOpenSession(Errno::from_i32(err.into())))
     }
+
+    /// Sets how often opening a device is retried, if it fails with `EAGAIN` or `ENODEV`.
+    ///
+    /// This can happen transiently, e.g. if seatd is still starting up.
+    /// The first retry happens after `backoff`, which is doubled for every following retry.
+    /// [`Session::open`] blocks while waiting for the next retry.
+    ///
+    /// Defaults to no retries. The setting is shared by all clones of this session.
+    pub fn set_open_retry(&self, retries: u32, backoff: Duration) {
+        if let Some(session) = self.internal.upgrade() {
+            session.open_retry.set((retries, backoff));
+        }
+    }
 }
 
 impl Session for LibSeatSession {
@@ -123,15 +147,30 @@ impl Session for LibSeatSession {
         if let Some(session) = self.internal.upgrade() {
             debug!("Opening device: {:?}", path);
 
-            session
-                .seat
-                .borrow_mut()
-                .open_device(&path)
-                .map(|(id, fd)| {
-                    session.devices.borrow_mut().insert(fd, id);
-                    fd
-                })
-                .map_err(|err| Error::FailedToOpenDevice(Errno::from_i32(err.into())))
+            let (retries, mut backoff) = session.open_retry.get();
+            let mut attempt = 0;
+            loop {
+                let result = match session.seat.borrow_mut().as_mut() {
+                    Some(seat) => seat.open_device(&path),
+                    None => return Err(Error::SessionLost),
+                };
+                match result {
+                    Ok((id, fd)) => {
+                        session.devices.borrow_mut().insert(fd, id);
+                        return Ok(fd);
+                    }
+                    Err(err) => {
+                        let errno = Errno::from_i32(err.into());
+                        if attempt >= retries || !matches!(errno, Errno::EAGAIN | Errno::ENODEV) {
+                            return Err(Error::FailedToOpenDevice(errno));
+                        }
+                        warn!("Failed to open device ({}), retrying in {:?}", errno, backoff);
+                    }
+                }
+                std::thread::sleep(backoff);
+                backoff = backoff.saturating_mul(2);
+                attempt += 1;
+            }
         } else {
             Err(Error::SessionLost)
         }
@@ -144,14 +183,12 @@ impl Session for LibSeatSession {
 
             let dev = session.devices.borrow().get(&fd).copied();
 
-            let out = if let Some(dev) = dev {
-                session
-                    .seat
-                    .borrow_mut()
+            // devices of a disabled session were already closed with the seat
+            let out = match (dev, session.seat.borrow_mut().as_mut()) {
+                (Some(dev), Some(seat)) => seat
                     .close_device(dev)
-                    .map_err(|err| Error::FailedToCloseDevice(Errno::from_i32(err.into())))
-            } else {
-                Ok(())
+                    .map_err(|err| Error::FailedToCloseDevice(Errno::from_i32(err.into()))),
+                _ => Ok(()),
             };
 
             close(fd).unwrap();
@@ -165,11 +202,10 @@ impl Session for LibSeatSession {
     #[instrument(parent = &self.span, skip(self))]
     fn change_vt(&mut self, vt: i32) -> Result<(), Self::Error> {
         if let Some(session) = self.internal.upgrade() {
+            let mut seat = session.seat.borrow_mut();
+            let seat = seat.as_mut().ok_or(Error::SessionLost)?;
             debug!("Session switch: {:?}", vt);
-            session
-                .seat
-                .borrow_mut()
-                .switch_session(vt)
+            seat.switch_session(vt)
                 .map_err(|err| Error::FailedToChangeVt(Errno::from_i32(err.into())))
         } else {
             Err(Error::SessionLost)
@@ -187,6 +223,43 @@ impl Session for LibSeatSession {
     fn seat(&self) -> String {
         self.seat_name.clone()
     }
+
+    fn seat_name(&self) -> Cow<'_, str> {
+        Cow::Borrowed(&self.seat_name)
+    }
+
+    /// Releases all devices still opened through this session in the order they were opened
+    /// and closes the seat, so that another session can take it over right away.
+    ///
+    /// File descriptors of devices still open are not closed, as they are owned by the caller.
+    #[instrument(parent = &self.span, skip(self))]
+    fn disable(&mut self) -> Result<(), Self::Error> {
+        if let Some(session) = self.internal.upgrade() {
+            let Some(mut seat) = session.seat.borrow_mut().take() else {
+                return Ok(());
+            };
+            debug!("Disabling session");
+
+            let mut devices = session.devices.borrow_mut().drain().collect::<Vec<_>>();
+            // libseat hands out increasing device ids
+            devices.sort_by_key(|(_, id)| *id);
+
+            let mut result = Ok(());
+            for (fd, id) in devices {
+                warn!("Device {:?} was not closed before disabling the session", fd);
+                if let Err(err) = seat.close_device(id) {
+                    result = Err(Error::FailedToCloseDevice(Errno::from_i32(err.into())));
+                }
+            }
+            session.active.store(false, Ordering::SeqCst);
+            debug!("Closing seat");
+            drop(seat);
+
+            result
+        } else {
+            Err(Error::SessionLost)
+        }
+    }
 }
 
 impl LibSeatSessionNotifier {
@@ -194,7 +267,7 @@ impl LibSeatSessionNotifier {
     pub fn session(&self) -> LibSeatSession {
         LibSeatSession {
             internal: Rc::downgrade(&self.internal),
-            seat_name: self.internal.seat.borrow_mut().name().to_owned(),
+            seat_name: self.internal.seat_name.clone(),
             span: self.span.clone(),
         }
     }
@@ -216,7 +289,9 @@ impl EventSource for LibSeatSessionNotifier {
         F: FnMut(SessionEvent, &mut ()),
     {
         if Some(token) == self.token {
-            self.internal.seat.borrow_mut().dispatch(0).unwrap();
+            if let Some(seat) = self.internal.seat.borrow_mut().as_mut() {
+                seat.dispatch(0).unwrap();
+            }
         }
 
         let internal = &self.internal;
@@ -225,16 +300,25 @@ impl EventSource for LibSeatSessionNotifier {
                 channel::Event::Msg(event) => match event {
                     SeatEvent::Enable => {
                         internal.active.store(true, Ordering::SeqCst);
-                        callback(SessionEvent::ActivateSession, &mut ());
+                        // libseat does not tell us why the seat was enabled, but it is only disabled
+                        // when switching away, so any activation after the first one is a resume.
+                        let cause = if internal.activated.replace(true) {
+                            ActivationCause::Resumed
+                        } else {
+                            ActivationCause::Initial
+                        };
+                        callback(SessionEvent::ActivateSession { cause }, &mut ());
                     }
                     SeatEvent::Disable => {
                         internal.active.store(false, Ordering::SeqCst);
-                        internal.seat.borrow_mut().disable().unwrap();
+                        if let Some(seat) = internal.seat.borrow_mut().as_mut() {
+                            seat.disable().unwrap();
+                        }
                         callback(SessionEvent::PauseSession, &mut ());
                     }
                 },
                 channel::Event::Closed => {
-                    // Tx is stored inside of Seat, and Rc<Seat> is stored in LibSeatSessionNotifier so this is unreachable
+                    // Tx is stored inside of Seat, which is only dropped once the session was disabled
                 }
             })
             .map_err(|_| Error::SessionLost)
@@ -243,9 +327,18 @@ impl EventSource for LibSeatSessionNotifier {
     fn register(&mut self, poll: &mut Poll, factory: &mut TokenFactory) -> calloop::Result<()> {
         self.rx.register(poll, factory)?;
 
+        let Some(seat) = self
+            .internal
+            .seat
+            .borrow_mut()
+            .as_mut()
+            .map(|seat| seat.get_fd().unwrap())
+        else {
+            return Ok(());
+        };
         self.token = Some(factory.token());
         poll.register(
-            self.internal.seat.borrow_mut().get_fd().unwrap(),
+            seat,
             calloop::Interest::READ,
             calloop::Mode::Level,
             self.token.unwrap(),
@@ -255,9 +348,20 @@ impl EventSource for LibSeatSessionNotifier {
     fn reregister(&mut self, poll: &mut Poll, factory: &mut TokenFactory) -> calloop::Result<()> {
         self.rx.reregister(poll, factory)?;
 
+        // the fd of a closed seat was removed from the poll when it was closed
+        let Some(seat) = self
+            .internal
+            .seat
+            .borrow_mut()
+            .as_mut()
+            .map(|seat| seat.get_fd().unwrap())
+        else {
+            self.token = None;
+            return Ok(());
+        };
         self.token = Some(factory.token());
         poll.reregister(
-            self.internal.seat.borrow_mut().get_fd().unwrap(),
+            seat,
             calloop::Interest::READ,
             calloop::Mode::Level,
             self.token.unwrap(),
@@ -268,7 +372,10 @@ impl EventSource for LibSeatSessionNotifier {
         self.rx.unregister(poll)?;
 
         self.token = None;
-        poll.unregister(self.internal.seat.borrow_mut().get_fd().unwrap())
+        match self.internal.seat.borrow_mut().as_mut() {
+            Some(seat) => poll.unregister(seat.get_fd().unwrap()),
+            None => Ok(()),
+        }
     }
 }
 
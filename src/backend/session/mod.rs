@@ -21,13 +21,16 @@
 //!
 //! ## Available providers
 //!
-//! This module provides just one session implementation, through [libseat](https://sr.ht/~kennylevinsen/seatd/),
-//! gated by the `backend_session_libseat` cargo feature.
+//! This module provides two session implementations:
+//! - through [libseat](https://sr.ht/~kennylevinsen/seatd/), gated by the `backend_session_libseat` cargo feature.
+//! - through direct device access for processes running as root on a virtual terminal,
+//!   gated by the `backend_session_direct` cargo feature.
 //!
 //! Other implementations can be provided out-of-tree.
 
 use nix::fcntl::OFlag;
 use std::{
+    borrow::Cow,
     cell::RefCell,
     os::unix::io::RawFd,
     path::Path,
@@ -56,6 +59,22 @@ pub trait Session {
     fn is_active(&self) -> bool;
     /// Which seat this session is on
     fn seat(&self) -> String;
+    /// Which seat this session is on, without allocating if the implementation stores the name
+    ///
+    /// Defaults to calling [`Session::seat`].
+    fn seat_name(&self) -> Cow<'_, str> {
+        Cow::Owned(self.seat())
+    }
+
+    /// Relinquishes the seat, so that the next session may take over immediately
+    ///
+    /// Should be called on shutdown after all devices were closed.
+    /// The session cannot be used to open devices afterwards.
+    ///
+    /// Defaults to doing nothing.
+    fn disable(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
 }
 
 /// Events that can be generated by a session
@@ -66,7 +85,27 @@ pub enum Event {
     /// All devices should be considered as paused
     PauseSession,
     /// The whole session has been activated
-    ActivateSession,
+    ActivateSession {
+        /// Why the session was activated
+        cause: ActivationCause,
+    },
+}
+
+/// Cause of an [`Event::ActivateSession`]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ActivationCause {
+    /// The session became active for the first time
+    ///
+    /// This happens if the session was not already active when it was created,
+    /// e.g. because it was started on a vt in the background.
+    Initial,
+    /// The session became active again after it was paused
+    ///
+    /// This happens e.g. when switching back to the vt of the session. The session backends
+    /// are not told why the session was paused, so this does not tell apart the reasons.
+    /// The devices of the session may have been used by other sessions in the meantime,
+    /// so their state should be restored.
+    Resumed,
 }
 
 impl Session for () {
@@ -89,6 +128,9 @@ impl Session for () {
     fn seat(&self) -> String {
         String::from("seat0")
     }
+    fn seat_name(&self) -> Cow<'_, str> {
+        Cow::Borrowed("seat0")
+    }
 }
 
 impl<S: Session> Session for Rc<RefCell<S>> {
@@ -113,6 +155,10 @@ impl<S: Session> Session for Rc<RefCell<S>> {
     fn seat(&self) -> String {
         self.borrow().seat()
     }
+
+    fn disable(&mut self) -> Result<(), Self::Error> {
+        self.borrow_mut().disable()
+    }
 }
 
 impl<S: Session> Session for Arc<Mutex<S>> {
@@ -137,6 +183,10 @@ impl<S: Session> Session for Arc<Mutex<S>> {
     fn seat(&self) -> String {
         self.lock().unwrap().seat()
     }
+
+    fn disable(&mut self) -> Result<(), Self::Error> {
+        self.lock().unwrap().disable()
+    }
 }
 
 /// Allows errors to be described by an error number
@@ -151,5 +201,7 @@ impl AsErrno for () {
     }
 }
 
+#[cfg(feature = "backend_session_direct")]
+pub mod direct;
 #[cfg(feature = "backend_session_libseat")]
 pub mod libseat;
@@ -59,7 +59,7 @@ use tracing::{info, instrument};
 #[cfg(feature = "wayland_frontend")]
 use crate::wayland::output::xdg::XdgOutput;
 #[cfg(feature = "backend_drm")]
-use drm::control::{Mode as DrmMode, ModeFlags};
+use drm::control::Mode as DrmMode;
 #[cfg(feature = "wayland_frontend")]
 use wayland_server::{backend::WeakHandle, protocol::wl_output::WlOutput};
 
@@ -84,23 +84,7 @@ pub struct Mode {
 #[cfg(feature = "backend_drm")]
 impl From<DrmMode> for Mode {
     fn from(mode: DrmMode) -> Self {
-        let clock = mode.clock() as u64;
-        let htotal = mode.hsync().2 as u64;
-        let vtotal = mode.vsync().2 as u64;
-
-        let mut refresh = (clock * 1_000_000 / htotal + vtotal / 2) / vtotal;
-
-        if mode.flags().contains(ModeFlags::INTERLACE) {
-            refresh *= 2;
-        }
-
-        if mode.flags().contains(ModeFlags::DBLSCAN) {
-            refresh /= 2;
-        }
-
-        if mode.vscan() > 1 {
-            refresh /= mode.vscan() as u64;
-        }
+        let refresh = crate::backend::drm::mode_refresh_mhz(&mode);
 
         let (w, h) = mode.size();
 
@@ -1385,6 +1385,25 @@ impl<N: Coordinate> Rectangle<N, Buffer> {
     }
 }
 
+impl Rectangle<f64, Buffer> {
+    /// Convert this buffer rectangle to the physical destination rectangle it covers on an output
+    ///
+    /// `scale` is the fractional scale between the buffer and the output, `transform` the transformation
+    /// applied to the buffer when displayed and `output_size` the physical size of the output.
+    /// The result can be used as the `dst` of a plane scanning out this rectangle of the buffer.
+    #[inline]
+    pub fn to_physical_dst(
+        self,
+        scale: impl Into<Scale<f64>>,
+        transform: Transform,
+        output_size: &Size<f64, Physical>,
+    ) -> Rectangle<f64, Physical> {
+        let scale = scale.into();
+        let area = output_size.to_logical(1.0).to_buffer(scale, transform);
+        self.to_logical(scale, transform, &area).to_physical(1.0)
+    }
+}
+
 impl<N: fmt::Debug> fmt::Debug for Rectangle<N, Logical> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("Rectangle<Logical>")
@@ -1459,6 +1478,7 @@ impl<N: Default, Kind> Default for Rectangle<N, Kind> {
 }
 
 #[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 /// Possible transformations to two-dimensional planes
 pub enum Transform {
     /// Identity transformation (plane is unaltered when applied)
@@ -1620,7 +1640,7 @@ impl From<Transform> for WlTransform {
 
 #[cfg(test)]
 mod tests {
-    use super::{Logical, Rectangle, Size, Transform};
+    use super::{Buffer, Logical, Physical, Rectangle, Size, Transform};
 
     #[test]
     fn transform_rect_ident() {
@@ -1866,4 +1886,27 @@ mod tests {
         let main = Rectangle::<i32, Logical>::from_loc_and_size((0, 0), (800, 600));
         assert!(!main.overlaps(right));
     }
+
+    #[test]
+    fn buffer_rect_to_physical_dst() {
+        let output_size = Size::<f64, Physical>::from((1920.0, 1080.0));
+
+        let rect = Rectangle::<f64, Buffer>::from_loc_and_size((0.0, 0.0), (2880.0, 1620.0));
+        assert_eq!(
+            Rectangle::from_loc_and_size((0.0, 0.0), (1920.0, 1080.0)),
+            rect.to_physical_dst(1.5, Transform::Normal, &output_size)
+        );
+
+        let rect = Rectangle::<f64, Buffer>::from_loc_and_size((150.0, 300.0), (300.0, 600.0));
+        assert_eq!(
+            Rectangle::from_loc_and_size((100.0, 200.0), (200.0, 400.0)),
+            rect.to_physical_dst(1.5, Transform::Normal, &output_size)
+        );
+
+        let rect = Rectangle::<f64, Buffer>::from_loc_and_size((0.0, 0.0), (200.0, 100.0));
+        assert_eq!(
+            Rectangle::from_loc_and_size((0.0, 980.0), (50.0, 100.0)),
+            rect.to_physical_dst(2.0, Transform::_90, &output_size)
+        );
+    }
 }